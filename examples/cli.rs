@@ -1,13 +1,19 @@
 /*use bank_system::balance::balance_manager::BalanceManager;
 use bank_system::users::user_manager::UserManager;*/
-use bank_system::{Name, Storage};
+use bank_system::{Balance, Name, Storage};
 use std::env;
 
 fn main() {
     // Загружаем текущее состояние банка из CSV-файла
     // Здесь демонстрация использования BufRead в методе load_data()
     // Файл читается построчно, и каждая строка преобразуется в (Name, Balance)
-    let mut storage = Storage::load_data("balance.csv");
+    let mut storage = match Storage::load_data("balance.csv") {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Не удалось загрузить balance.csv: {}", e);
+            return;
+        }
+    };
 
     // Получаем аргументы командной строки
     let args: Vec<String> = env::args().collect();
@@ -30,14 +36,16 @@ fn main() {
                 return;
             }
             let name: Name = args[2].clone();
-            let amount: i64 = args[3].parse().expect("Сумма должна быть числом");
+            let amount: Balance = args[3].parse().expect("Сумма должна быть числом, например 10.50");
 
             // Пытаемся пополнить баланс
             match storage.deposit(&name, amount) {
                 Ok(_) => {
                     println!("Пополнено: {} на {}", name, amount);
                     // После изменения баланса сохраняем новое состояние в CSV
-                    storage.save("balance.csv");
+                    if let Err(e) = storage.save("balance.csv") {
+                        eprintln!("Не удалось сохранить balance.csv: {}", e);
+                    }
                 }
                 Err(e) => println!("Ошибка: {}", e),
             }
@@ -48,14 +56,16 @@ fn main() {
                 return;
             }
             let name: Name = args[2].clone();
-            let amount: i64 = args[3].parse().expect("Сумма должна быть числом");
+            let amount: Balance = args[3].parse().expect("Сумма должна быть числом, например 10.50");
 
             // Пытаемся снять деньги
             match storage.withdraw(&name, amount) {
                 Ok(_) => {
                     println!("Снято: {} на {}", name, amount);
                     // Сохраняем изменения
-                    storage.save("balance.csv");
+                    if let Err(e) = storage.save("balance.csv") {
+                        eprintln!("Не удалось сохранить balance.csv: {}", e);
+                    }
                 }
                 Err(e) => println!("Ошибка: {}", e),
             }
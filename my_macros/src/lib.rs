@@ -37,33 +37,69 @@ pub fn transaction_derive(input: TokenStream) -> TokenStream {
         }
     }
 
+    // Вся работа с балансами делегируется методам Storage: они не заводят
+    // новые счета, отклоняют неположительные суммы и складывают/вычитают
+    // с проверкой переполнения и публикуют события. StorageError переводится
+    // в TxError через `?`, об отказе подписчикам сообщает Storage::report
     let body = match kind {
         "deposit" => quote! {
-            *storage.accounts.entry(self.account.clone()).or_insert(0) += self.amount;
+            storage.deposit(&self.account, self.amount)?;
         },
         "withdraw" => quote! {
-            let bal = storage.accounts.entry(self.account.clone()).or_insert(0);
-            if *bal < self.amount {
-                return Err(TxError::InsufficientFunds);
-            }
-            *bal -= self.amount;
+            storage.withdraw(&self.account, self.amount)?;
         },
         "transfer" => quote! {
-            let from_bal = storage.accounts.entry(self.from.clone()).or_insert(0);
-            if *from_bal < self.amount {
-                return Err(TxError::InsufficientFunds);
-            }
-            *from_bal -= self.amount;
-            *storage.accounts.entry(self.to.clone()).or_insert(0) += self.amount;
+            storage.transfer(&self.from, &self.to, self.amount)?;
         },
         _ => panic!("Unknown transaction kind"),
     };
 
+    // Операция для журнала: поля копируются в соответствующий тип из крейта
+    let operation = match kind {
+        "deposit" => quote! {
+            Operation::Deposit(Deposit { account: self.account.clone(), amount: self.amount })
+        },
+        "withdraw" => quote! {
+            Operation::Withdraw(Withdraw { account: self.account.clone(), amount: self.amount })
+        },
+        _ => quote! {
+            Operation::Transfer(Transfer {
+                from: self.from.clone(),
+                to: self.to.clone(),
+                amount: self.amount,
+            })
+        },
+    };
+
+    // Компенсирующая транзакция: депозит <-> снятие, перевод — в обратную сторону
+    let reverse = match kind {
+        "deposit" => quote! {
+            Withdraw { account: self.account.clone(), amount: self.amount }
+        },
+        "withdraw" => quote! {
+            Deposit { account: self.account.clone(), amount: self.amount }
+        },
+        _ => quote! {
+            Transfer { from: self.to.clone(), to: self.from.clone(), amount: self.amount }
+        },
+    };
+
     let expanded = quote! {
         impl Transaction for #name {
             fn apply(&self, storage: &mut Storage) -> Result<(), TxError> {
-                #body
-                Ok(())
+                let result = (|| -> Result<(), TxError> {
+                    #body
+                    Ok(())
+                })();
+                storage.report(result, || self.operations())
+            }
+
+            fn operations(&self) -> Vec<Operation> {
+                vec![#operation]
+            }
+
+            fn reverse(&self) -> Box<dyn Transaction> {
+                Box::new(#reverse)
             }
         }
     };
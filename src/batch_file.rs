@@ -0,0 +1,304 @@
+pub mod batch_file {
+    use std::fmt::{Display, Formatter};
+    use std::io::{self, BufRead, Write};
+    use std::str::FromStr;
+    use crate::idempotency::idempotency;
+    use crate::persist::persist;
+    use crate::{Balance, Batch, Deposit, Operation, Outcome, Storage, Transaction, Transfer, TxError, Withdraw};
+
+    /// Режим обработки файла платёжных инструкций
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BatchMode {
+        /// Весь файл — одна транзакция: ошибка в любой строке отменяет все
+        AllOrNothing,
+        /// Каждая строка — отдельная транзакция: ошибочные пропускаются
+        BestEffort,
+    }
+
+    /// Одна строка файла: `kind,from,to,amount,reference`.
+    ///
+    /// Для `deposit` заполняется только `to`, для `withdraw` — только `from`.
+    /// Поле с запятой или кавычкой заключается в кавычки, кавычка внутри удваивается.
+    /// `reference` необязателен и может быть любым текстом: ключ идемпотентности
+    /// выводится из него (см. [`reference_key`]) в обоих режимах, поэтому повторная
+    /// обработка того же файла не проводит платёж второй раз
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Instruction {
+        pub operation: Operation,
+        pub reference: Option<String>,
+    }
+
+    impl FromStr for Instruction {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let fields = split_fields(s)?;
+            let fields: Vec<&str> = fields.iter().map(|field| field.trim()).collect();
+            let [kind, from, to, amount, reference] = fields.as_slice() else {
+                return Err(format!("ожидается 5 полей kind,from,to,amount,reference, получено {}", fields.len()));
+            };
+            let amount: Balance = amount.parse().map_err(|e| format!("{}", e))?;
+            let required = |field: &str, name: &str| {
+                if field.is_empty() {
+                    Err(format!("для {} нужно заполнить поле {}", kind, name))
+                } else {
+                    Ok(field.to_string())
+                }
+            };
+
+            let operation = match *kind {
+                "deposit" => Operation::Deposit(Deposit { account: required(to, "to")?, amount }),
+                "withdraw" => Operation::Withdraw(Withdraw { account: required(from, "from")?, amount }),
+                "transfer" => Operation::Transfer(Transfer {
+                    from: required(from, "from")?,
+                    to: required(to, "to")?,
+                    amount,
+                }),
+                _ => return Err(format!("неизвестный вид операции: {}", kind)),
+            };
+            Ok(Instruction {
+                operation,
+                reference: (!reference.is_empty()).then(|| reference.to_string()),
+            })
+        }
+    }
+
+    /// Делит строку CSV на поля. Поле в кавычках может содержать запятые и удвоенные кавычки
+    fn split_fields(line: &str) -> Result<Vec<String>, String> {
+        let mut fields = Vec::new();
+        let mut chars = line.chars().peekable();
+        loop {
+            let mut field = String::new();
+            if chars.next_if_eq(&'"').is_some() {
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                        Some('"') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(format!("не закрыта кавычка в поле {}", fields.len() + 1)),
+                    }
+                }
+                if chars.peek().is_some_and(|c| *c != ',') {
+                    return Err(format!("после закрывающей кавычки в поле {} ожидается запятая", fields.len() + 1));
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| *c != ',') {
+                    field.push(c);
+                }
+            }
+            fields.push(field);
+            if chars.next().is_none() {
+                return Ok(fields);
+            }
+        }
+    }
+
+    /// Поле CSV: в кавычках, если в нём есть запятая, кавычка или перевод строки
+    fn quote(field: &str) -> String {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    /// Ключ идемпотентности строки с этим `reference`: reference — произвольный текст,
+    /// а ключ должен быть коротким и без пробелов, поэтому в ключ идёт хеш
+    pub fn reference_key(reference: &str) -> String {
+        format!("ref-{:016x}", persist::checksum(reference.as_bytes()))
+    }
+
+    /// Номер строки файла и разобранная из неё инструкция или ошибка разбора
+    pub type ParsedLine = (usize, Result<Instruction, String>);
+
+    /// Итог обработки одной строки
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum LineStatus {
+        Applied,
+        /// Инструкция с этим reference уже обрабатывалась с указанным итогом; ничего не применено
+        Duplicate(Outcome),
+        Failed(String),
+        /// Строка корректна, но вся транзакция отменена из-за ошибки в другой строке
+        RolledBack,
+    }
+
+    impl Display for LineStatus {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                LineStatus::Applied => "applied",
+                LineStatus::Duplicate(_) => "duplicate",
+                LineStatus::Failed(_) => "failed",
+                LineStatus::RolledBack => "rolled_back",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    /// Строка отчёта; `line` — номер строки входного файла с единицы
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct LineResult {
+        pub line: usize,
+        pub reference: Option<String>,
+        pub status: LineStatus,
+    }
+
+    /// Отчёт об обработке файла: по строке на каждую инструкцию
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct Report {
+        pub lines: Vec<LineResult>,
+    }
+
+    impl Report {
+        fn count(&self, status: fn(&LineStatus) -> bool) -> usize {
+            self.lines.iter().filter(|line| status(&line.status)).count()
+        }
+
+        pub fn applied(&self) -> usize {
+            self.count(|status| *status == LineStatus::Applied)
+        }
+
+        pub fn failed(&self) -> usize {
+            self.count(|status| matches!(status, LineStatus::Failed(_)))
+        }
+
+        /// Пишет отчёт в CSV: `line,reference,status,message`
+        pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+            writeln!(writer, "line,reference,status,message")?;
+            for result in &self.lines {
+                let message = match &result.status {
+                    LineStatus::Failed(message) => format!("\"{}\"", message.replace('"', "\"\"")),
+                    LineStatus::Duplicate(outcome) => outcome.to_string(),
+                    _ => String::new(),
+                };
+                writeln!(
+                    writer,
+                    "{},{},{},{}",
+                    result.line,
+                    quote(result.reference.as_deref().unwrap_or("")),
+                    result.status,
+                    message
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Читает инструкции: пустые строки, комментарии `#` и заголовок `kind,...` пропускаются.
+    /// Ошибка разбора строки не прерывает чтение — она попадёт в отчёт
+    pub fn read_instructions<R: BufRead>(reader: R) -> io::Result<Vec<ParsedLine>> {
+        let mut instructions = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (index == 0 && line.starts_with("kind,")) {
+                continue;
+            }
+            instructions.push((index + 1, line.parse()));
+        }
+        Ok(instructions)
+    }
+
+    /// Применяет инструкции к хранилищу в заданном режиме и возвращает отчёт
+    pub fn process(storage: &mut Storage, instructions: Vec<ParsedLine>, mode: BatchMode) -> Report {
+        match mode {
+            BatchMode::BestEffort => process_each(storage, instructions),
+            BatchMode::AllOrNothing => process_all(storage, instructions),
+        }
+    }
+
+    /// Итог, с которым строка уже была обработана; None — строку нужно применить.
+    /// Если ключ уже использован для другой операции, строка применяется и получает отказ
+    fn processed(storage: &Storage, key: Option<&str>, operation: &Operation) -> Option<Outcome> {
+        let key = key?;
+        storage.keys().get(key).filter(|_| storage.keys().matches(key, idempotency::fingerprint(&operation.operations())))
+    }
+
+    fn process_each(storage: &mut Storage, instructions: Vec<ParsedLine>) -> Report {
+        let mut report = Report::default();
+        for (line, instruction) in instructions {
+            let (reference, status) = match instruction {
+                Err(message) => (None, LineStatus::Failed(message)),
+                Ok(Instruction { operation, reference }) => {
+                    let key = reference.as_deref().map(reference_key);
+                    let status = match (processed(storage, key.as_deref(), &operation), key) {
+                        (Some(outcome), _) => LineStatus::Duplicate(outcome),
+                        (None, Some(key)) => status_of(storage.commit_keyed(&key, &operation)),
+                        (None, None) => status_of(storage.commit(&operation)),
+                    };
+                    (reference, status)
+                }
+            };
+            report.lines.push(LineResult { line, reference, status });
+        }
+        report
+    }
+
+    /// Весь файл применяется одним пакетом, и ключи всех его строк запоминаются вместе
+    /// с ним. Уже обработанные строки пропускаются, как и построчно; отказ пакета
+    /// не запоминается, поэтому исправленный файл можно обработать ещё раз
+    fn process_all(storage: &mut Storage, instructions: Vec<ParsedLine>) -> Report {
+        let mut report = Report::default();
+        let mut steps = Vec::new();
+        // Номер строки отчёта для каждого шага пакета: уже обработанные строки в пакет не входят
+        let mut step_lines = Vec::new();
+        let mut parse_failed = false;
+        for (line, instruction) in instructions {
+            let (reference, status) = match instruction {
+                Ok(Instruction { operation, reference }) => {
+                    let key = reference.as_deref().map(reference_key);
+                    match processed(storage, key.as_deref(), &operation) {
+                        Some(outcome) => (reference, LineStatus::Duplicate(outcome)),
+                        None => {
+                            step_lines.push(report.lines.len());
+                            steps.push((key, operation));
+                            (reference, LineStatus::Applied)
+                        }
+                    }
+                }
+                Err(message) => {
+                    parse_failed = true;
+                    (None, LineStatus::Failed(message))
+                }
+            };
+            report.lines.push(LineResult { line, reference, status });
+        }
+
+        // Корректные строки отменяются вместе с ошибочной
+        if parse_failed {
+            for result in report.lines.iter_mut().filter(|result| result.status == LineStatus::Applied) {
+                result.status = LineStatus::RolledBack;
+            }
+            return report;
+        }
+
+        let result = if steps.is_empty() {
+            Ok(())
+        } else if steps.iter().any(|(key, _)| key.is_some()) {
+            storage.commit_keyed_steps(&steps)
+        } else {
+            storage.commit(&steps.into_iter().map(|(_, operation)| operation).fold(Batch::new(), Batch::with))
+        };
+        if let Err(e) = result {
+            // Ошибка без номера шага (например, не удалась запись журнала) относится ко всем строкам пакета
+            let (failed_step, message) = match e {
+                TxError::Step { index, source } => (Some(index), source.to_string()),
+                e => (None, e.to_string()),
+            };
+            for (step, &line) in step_lines.iter().enumerate() {
+                report.lines[line].status = if failed_step.is_none_or(|index| index == step) {
+                    LineStatus::Failed(message.clone())
+                } else {
+                    LineStatus::RolledBack
+                };
+            }
+        }
+        report
+    }
+
+    fn status_of(result: Result<(), TxError>) -> LineStatus {
+        match result {
+            Ok(()) => LineStatus::Applied,
+            Err(e) => LineStatus::Failed(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use bank_system::{BatchMode, DEFAULT_FEES_FILE, Storage, process, read_instructions};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::process::exit;
+
+const FILE_NAME: &str = "balance.csv";
+
+fn usage() -> ! {
+    eprintln!("Использование: batch <instructions.csv> [--all-or-nothing] [--report <report.csv>]");
+    eprintln!("  Файл инструкций: kind,from,to,amount,reference — по операции на строку");
+    eprintln!("  --all-or-nothing  применить весь файл одной транзакцией (по умолчанию — построчно)");
+    eprintln!("  --report <file>   записать отчёт в файл (по умолчанию — в stdout)");
+    exit(2);
+}
+
+fn main() {
+    let mut input = None;
+    let mut report_path = None;
+    let mut mode = BatchMode::BestEffort;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--all-or-nothing" => mode = BatchMode::AllOrNothing,
+            "--report" => report_path = Some(args.next().unwrap_or_else(|| usage())),
+            _ if input.is_none() && !arg.starts_with("--") => input = Some(arg),
+            _ => usage(),
+        }
+    }
+    let Some(input) = input else { usage() };
+
+    let instructions = match File::open(&input).and_then(|file| read_instructions(BufReader::new(file))) {
+        Ok(instructions) => instructions,
+        Err(e) => {
+            eprintln!("Не удалось прочитать {}: {}", input, e);
+            exit(1);
+        }
+    };
+
+    let mut storage = match Storage::load_data(FILE_NAME) {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Не удалось загрузить {}: {}", FILE_NAME, e);
+            exit(1);
+        }
+    };
+    if let Err(e) = storage.load_fees(Path::new(DEFAULT_FEES_FILE)) {
+        eprintln!("Не удалось загрузить {}: {}", DEFAULT_FEES_FILE, e);
+        exit(1);
+    }
+
+    let report = process(&mut storage, instructions, mode);
+    if let Err(e) = storage.save_progress(FILE_NAME) {
+        eprintln!("Не удалось сохранить {}: {}", FILE_NAME, e);
+    }
+
+    let written = match &report_path {
+        Some(path) => File::create(path).and_then(|file| report.write_to(file)),
+        None => report.write_to(io::stdout().lock()),
+    };
+    if let Err(e) = written {
+        eprintln!("Не удалось записать отчёт: {}", e);
+        exit(1);
+    }
+
+    eprintln!(
+        "Строк: {}, применено: {}, с ошибкой: {}",
+        report.lines.len(),
+        report.applied(),
+        report.failed()
+    );
+    if report.failed() > 0 {
+        exit(1);
+    }
+}
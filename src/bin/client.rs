@@ -0,0 +1,85 @@
+use bank_system::{DEFAULT_ADDR, Response};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process;
+
+/// Отправляет команду и читает ответ сервера
+fn request(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, command: &str) -> io::Result<Option<Response>> {
+    writeln!(stream, "{}", command)?;
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None); // сервер закрыл соединение
+    }
+    Response::parse(&line)
+        .map(Some)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("непонятный ответ сервера: {}", line.trim_end())))
+}
+
+fn main() {
+    // client [--addr host:port] [команда...]: с командой — выполнить её и выйти,
+    // без команды — читать команды из stdin
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut addr = DEFAULT_ADDR.to_string();
+    if args.first().map(String::as_str) == Some("--addr") && args.len() >= 2 {
+        addr = args.remove(1);
+        args.remove(0);
+    }
+
+    let mut stream = match TcpStream::connect(&addr) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Не удалось подключиться к {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    let mut reader = BufReader::new(stream.try_clone().expect("не удалось клонировать сокет"));
+
+    if !args.is_empty() {
+        match request(&mut stream, &mut reader, &args.join(" ")) {
+            Ok(Some(Response::Ok(text))) if text.is_empty() => println!("OK"),
+            Ok(Some(Response::Ok(text))) => println!("{}", text),
+            Ok(Some(Response::Err(text))) => {
+                eprintln!("Ошибка: {}", text);
+                process::exit(1);
+            }
+            Ok(None) => eprintln!("Сервер закрыл соединение"),
+            Err(e) => eprintln!("Ошибка связи: {}", e),
+        }
+        return;
+    }
+
+    println!("Подключено к {}. Команды: add, deposit, withdraw, transfer, balance, list, quit", addr);
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+
+        let mut input = String::new();
+        if stdin.lock().read_line(&mut input).unwrap() == 0 {
+            let _ = request(&mut stream, &mut reader, "quit");
+            break;
+        }
+        let command = input.trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        match request(&mut stream, &mut reader, command) {
+            Ok(Some(Response::Ok(text))) if text.is_empty() => println!("OK"),
+            Ok(Some(Response::Ok(text))) => println!("{}", text),
+            Ok(Some(Response::Err(text))) => println!("Ошибка: {}", text),
+            Ok(None) => {
+                println!("Сервер закрыл соединение");
+                break;
+            }
+            Err(e) => {
+                eprintln!("Ошибка связи: {}", e);
+                break;
+            }
+        }
+        if command == "quit" {
+            break;
+        }
+    }
+}
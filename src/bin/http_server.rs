@@ -0,0 +1,70 @@
+use bank_system::{DEFAULT_FEES_FILE, DEFAULT_HTTP_ADDR, Scheduler, Storage, SystemClock, TICK_EVERY, loopback_addrs, serve_http};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+const FILE_NAME: &str = "balance.csv";
+/// Через сколько успешных изменяющих запросов сохранять снапшот и сокращать журнал
+const COMPACT_EVERY: usize = 100;
+
+fn main() {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| DEFAULT_HTTP_ADDR.to_string());
+    let addrs = match loopback_addrs(&addr) {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            eprintln!("Нельзя слушать {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+
+    let mut storage = match Storage::load_data(FILE_NAME) {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Не удалось загрузить {}: {}", FILE_NAME, e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = storage.load_fees(Path::new(DEFAULT_FEES_FILE)) {
+        eprintln!("Не удалось загрузить {}: {}", DEFAULT_FEES_FILE, e);
+        process::exit(1);
+    }
+    let storage = Arc::new(Mutex::new(storage));
+    // Платежи, проценты и удержания наступают со временем, а не только по запросам
+    Scheduler::new(SystemClock).spawn(Arc::clone(&storage), TICK_EVERY, |tick| print!("{}", tick));
+
+    let listener = match TcpListener::bind(&addrs[..]) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Не удалось открыть {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+    println!("HTTP API слушает http://{}", addr);
+
+    let changes = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Ошибка подключения: {}", e);
+                continue;
+            }
+        };
+        let (storage, changes) = (Arc::clone(&storage), Arc::clone(&changes));
+        thread::spawn(move || {
+            let changed = serve_http(stream, &storage).unwrap_or_else(|e| {
+                eprintln!("Ошибка обработки запроса: {}", e);
+                false
+            });
+            if changed && (changes.fetch_add(1, Ordering::SeqCst) + 1) % COMPACT_EVERY == 0 {
+                let mut storage = storage.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Err(e) = storage.save_progress(FILE_NAME) {
+                    eprintln!("Не удалось сохранить {}: {}", FILE_NAME, e);
+                }
+            }
+        });
+    }
+}
@@ -1,9 +1,9 @@
 // bin/macros_demo.rs
 
-use bank_system::{tx_chain};
+/*use bank_system::{tx_chain};
 use bank_system::Storage;
 use bank_system::{Deposit, Transaction, Transfer, Withdraw};
-use my_macros::say_hello;
+use my_macros::say_hello;*/
 
 use my_macros::ToSql;
 
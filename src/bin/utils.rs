@@ -1,11 +1,65 @@
 /*use bank_system::balance::balance_manager::BalanceManager;
 use bank_system::users::user_manager::UserManager;*/
-use bank_system::{Transaction};
-use bank_system::{Deposit, Name, Storage, Transfer, Withdraw};
+use bank_system::{Balance, Deposit, Script, ScriptError, Transaction, Name, Storage, StorageError, Transfer, TxError, Withdraw};
+use bank_system::{EntryKind, SECONDS_PER_DAY, Timestamp, format_date, format_timestamp, parse_date};
+use bank_system::{Clock, DEFAULT_FEES_FILE, DEFAULT_HOLD_TTL, InterestProduct, Recurrence, Schedule, Scheduler, SystemClock};
 use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+const FILE_NAME: &str = "balance.csv";
+
+/// Сохраняет снапшот и сообщает об ошибке, не прерывая работу CLI
+fn compact(storage: &mut Storage, file: &str) -> bool {
+    match storage.save_progress(file) {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("Не удалось сохранить {}: {}", file, e);
+            false
+        }
+    }
+}
+
+/// Печатает, как транзакция изменила бы балансы, ничего не применяя
+fn preview<T: Transaction + ?Sized>(storage: &Storage, tx: &T) {
+    match tx.simulate(storage) {
+        Ok(deltas) if deltas.is_empty() => println!("Пробный прогон: балансы не изменятся"),
+        Ok(deltas) => {
+            println!("Пробный прогон: транзакция пройдёт, изменения балансов:");
+            for (name, delta) in deltas {
+                let sign = if delta.is_positive() { "+" } else { "" };
+                let after = storage.get_balance(&name).and_then(|balance| balance.checked_add(delta));
+                match after {
+                    Some(after) => println!("  {} {}{} -> {}", name, sign, delta, after),
+                    None => println!("  {} {}{}", name, sign, delta),
+                }
+            }
+        }
+        Err(e) => println!("Пробный прогон: транзакция не пройдёт: {}", e),
+    }
+}
+
+/// Проводит наступившие регулярные платежи, начисляет проценты за прошедшие дни,
+/// снимает истёкшие удержания и сообщает о каждой попытке, выплате и снятии
+fn run_schedules(storage: &mut Storage, scheduler: &Scheduler<SystemClock>) {
+    print!("{}", scheduler.tick(storage));
+}
 
 fn main() {
-    let mut storage = Storage::load_data("balance.csv");
+    let mut storage = match Storage::load_data(FILE_NAME) {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Не удалось загрузить {}: {}", FILE_NAME, e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = storage.load_fees(Path::new(DEFAULT_FEES_FILE)) {
+        eprintln!("Не удалось загрузить {}: {}", DEFAULT_FEES_FILE, e);
+        process::exit(1);
+    }
+
+    let scheduler = Scheduler::new(SystemClock);
+    run_schedules(&mut storage, &scheduler);
 
     println!("=== Bank CLI Utils ===");
     println!("Команды:");
@@ -14,9 +68,34 @@ fn main() {
     println!("  deposit <name> <amount>   - пополнить баланс");
     println!("  withdraw <name> <amount>  - снять со счёта");
     println!("  transfer <name_from>\
-                <name_to> <amount>        - перевести со счёта одного пользователя другому");
+                <name_to> <amount> [key]  - перевести со счёта одного пользователя другому");
+    println!("                              (повтор с тем же key не переводит деньги второй раз)");
     println!("  balance <name>            - показать баланс");
     println!("  list                      - показать список пользователей");
+    println!("  statement <name>\
+                [from] [to]               - выписка по счёту за период (даты YYYY-MM-DD)");
+    println!("  reverse <seq>             - отменить транзакцию с номером из выписки (или undo <seq>)");
+    println!("  trial                     - оборотно-сальдовая ведомость главной книги");
+    println!("  compact                   - сохранить снапшот и сократить журнал");
+    println!("  schedule <from> <to> <amount> <monthly:D|every:N> [start]");
+    println!("                            - регулярный перевод, например: schedule Alice Bob 100 monthly:1");
+    println!("  schedules                 - список регулярных платежей");
+    println!("  unschedule <id>           - отменить регулярный платёж");
+    println!("  runs                      - попытки провести регулярные платежи");
+    println!("  interest <name> <product> - процентный продукт счёта: rate,day_count,compounding,posting[,rounding[,overdraft]]");
+    println!("                              например: interest Alice 4.75%,act/365,monthly,monthly; off — отключить");
+    println!("  accruals                  - начисленные, но не выплаченные проценты");
+    println!("  overdraft <name> <limit>  - лимит овердрафта счёта; 0 — снять лимит");
+    println!("  authorize <name> <amount> [days]");
+    println!("                            - удержать сумму на счёте (по умолчанию на {} дн.)", DEFAULT_HOLD_TTL / SECONDS_PER_DAY);
+    println!("  capture <id> [amount] [to]");
+    println!("                            - списать удержание целиком или частично; с to — переводом");
+    println!("  release <id>              - снять удержание без списания");
+    println!("  holds                     - список удержаний");
+    println!("  fees [reload]             - тариф комиссий; reload — перечитать {}", DEFAULT_FEES_FILE);
+    println!("  + <script>                - выполнить сценарий из нескольких операций атомарно,");
+    println!("                              например: + deposit Alice 100; transfer Alice Bob 30");
+    println!("  --dry-run                 - флаг для deposit, withdraw, transfer и +: только показать изменения");
     println!("  exit                      - выйти");
 
     let stdin = io::stdin();
@@ -31,12 +110,14 @@ fn main() {
             break; // EOF
         }
 
-        let args: Vec<&str> = input.trim().split_whitespace().collect();
+        let mut args: Vec<&str> = input.split_whitespace().collect();
+        let dry_run = args.contains(&"--dry-run");
+        args.retain(|arg| *arg != "--dry-run");
         if args.is_empty() {
             continue;
         }
-
-        const FILE_NAME: &str = "balance.csv";
+        // Время идёт и между командами: наступившие платежи проводятся перед каждой
+        run_schedules(&mut storage, &scheduler);
 
         match args[0] {
             "add" => {
@@ -45,19 +126,19 @@ fn main() {
                     continue;
                 }
                 let name: Name = args[1].to_string();
-                let balance: i64 = match args[2].parse() {
+                let balance: Balance = match args[2].parse() {
                     Ok(b) => b,
                     Err(_) => {
-                        println!("Сумма должна быть числом");
+                        println!("Сумма должна быть числом, например 10.50");
                         continue;
                     }
                 };
-                if storage.add_user(name.clone()).is_some() {
-                    let _ = storage.deposit(&name, balance);
-                    println!("Пользователь {} добавлен с балансом {}", name, balance);
-                    storage.save(FILE_NAME);
-                } else {
-                    println!("Пользователь {} уже существует", name);
+                match storage.open_account(name.clone(), balance) {
+                    Ok(_) => println!("Пользователь {} добавлен с балансом {}", name, balance),
+                    Err(StorageError::AlreadyExists(_)) => {
+                        println!("Пользователь {} уже существует", name)
+                    }
+                    Err(e) => println!("Ошибка: {}", e),
                 }
             }
             "remove" => {
@@ -66,11 +147,10 @@ fn main() {
                     continue;
                 }
                 let name = args[1];
-                if storage.remove_user(&name.to_string()).is_some() {
-                    println!("Пользователь {} удалён", name);
-                    storage.save(FILE_NAME);
-                } else {
-                    println!("Пользователь {} не найден", name);
+                match storage.close_account(&name.to_string()) {
+                    Ok(_) => println!("Пользователь {} удалён", name),
+                    Err(StorageError::NotFound(_)) => println!("Пользователь {} не найден", name),
+                    Err(e) => println!("Ошибка: {}", e),
                 }
             }
             "deposit" => {
@@ -79,10 +159,10 @@ fn main() {
                     continue;
                 }
                 let name = args[1].to_string();
-                let amount: i64 = match args[2].parse() {
+                let amount: Balance = match args[2].parse() {
                     Ok(a) => a,
                     Err(_) => {
-                        println!("Сумма должна быть числом");
+                        println!("Сумма должна быть числом, например 10.50");
                         continue;
                     }
                 };
@@ -91,11 +171,14 @@ fn main() {
                     account: name.clone(),
                     amount,
                 };
+                if dry_run {
+                    preview(&storage, &tx);
+                    continue;
+                }
                 // Применяем транзакцию
-                match tx.apply(&mut storage) {
+                match storage.commit(&tx) {
                     Ok(_) => {
                         println!("Транзакция: депозит {} на {}", name, amount);
-                        storage.save(FILE_NAME);
                     }
                     Err(e) => println!("Ошибка транзакции: {:?}", e),
                 }
@@ -107,20 +190,23 @@ fn main() {
                 }
 
                 let name = args[1].to_string();
-                let amount: i64 = match args[2].parse() {
+                let amount: Balance = match args[2].parse() {
                     Ok(a) => a,
                     Err(_) => {
-                        println!("Сумма должна быть числом");
+                        println!("Сумма должна быть числом, например 10.50");
                         continue;
                     }
                 };
 
                 let withdraw_tx = Withdraw { account: name, amount };
+                if dry_run {
+                    preview(&storage, &withdraw_tx);
+                    continue;
+                }
 
-                match withdraw_tx.apply(&mut storage) {
+                match storage.commit(&withdraw_tx) {
                     Ok(_) => {
                         println!("Вывод средств прошел успешно.");
-                        storage.save(FILE_NAME);
                     },
                     Err(e) => { eprintln!("Ошибка транзакции: {}", e) }
                 }
@@ -131,18 +217,26 @@ fn main() {
                     continue;
                 }
                 let name = args[1].to_string();
-                let amount: i64 = match args[2].parse() {
+                let amount: Balance = match args[2].parse() {
                     Ok(a) => a,
                     Err(_) => {
-                        println!("Сумма должна быть числом");
+                        println!("Сумма должна быть числом, например 10.50");
                         continue;
                     }
                 };
-                match storage.withdraw(&name, amount) {
+                let tx = Withdraw { account: name.clone(), amount };
+                if dry_run {
+                    preview(&storage, &tx);
+                    continue;
+                }
+                match storage.commit(&tx) {
                     Ok(_) => {
                         println!("С баланса пользователя {} снято {}", name, amount);
-                        storage.save(FILE_NAME);
                     }
+                    Err(TxError::InsufficientFunds { available, .. }) => {
+                        println!("У пользователя {} недостаточно средств: доступно {}", name, available)
+                    }
+                    Err(TxError::InvalidAccount) => println!("Пользователь {} не найден", name),
                     Err(e) => println!("Ошибка: {}", e),
                 }
             }
@@ -156,36 +250,51 @@ fn main() {
                 match storage.get_balance(&name.to_string()) {
                     Some(balance) => {
                         println!("Пользователь {} имеет на балансе следующую сумму: {}", name, balance);
+                        let (limit, held) = (storage.overdraft_limit(&name), storage.held(&name));
+                        if !limit.is_zero() || !held.is_zero() {
+                            let available = storage.available(&name).unwrap_or(balance);
+                            println!("Лимит овердрафта: {}, удержано: {}, доступно: {}", limit, held, available);
+                        }
                     }
                     None => println!("Данный пользователь не найден в БД"),
                 }
             },
             "transfer" => {
-                if args.len() != 4 {
-                    println!("Пример: transfer Alice Bob 50");
+                if !(4..=5).contains(&args.len()) {
+                    println!("Пример: transfer Alice Bob 50 [retry-key-1]");
                     continue;
                 }
                 let from = args[1].to_string();
                 let to = args[2].to_string();
-                let amount: i64 = match args[3].parse() {
+                let amount: Balance = match args[3].parse() {
                     Ok(a) => a,
                     Err(_) => {
-                        println!("Сумма должна быть числом");
+                        println!("Сумма должна быть числом, например 10.50");
                         continue;
                     }
                 };
 
                 let tx = Transfer { from, to, amount };
-                match tx.apply(&mut storage) {
+                if dry_run {
+                    preview(&storage, &tx);
+                    continue;
+                }
+                let result = match args.get(4) {
+                    Some(key) => storage.commit_keyed(key, &tx),
+                    None => storage.commit(&tx),
+                };
+                match result {
                     Ok(_) => {
                         println!("{}", tx);
-                        storage.save(FILE_NAME);
                     },
+                    Err(TxError::InvalidAccount) => {
+                        eprintln!("Ошибка транзакции: счёт {} или {} не найден", tx.from, tx.to)
+                    }
                     Err(e) => { eprintln!("Ошибка транзакции: {}", e) }
                 }
             },
             "list" => {
-                if storage.accounts.len() == 0 {
+                if storage.accounts.is_empty() {
                     println!("Пользователи отсутствуют");
                     continue;
                 }
@@ -194,37 +303,369 @@ fn main() {
                 storage.accounts.iter().for_each(|(name, balance)| println!("{} --> {}", name, balance));
             },
             "+" => {
-                if args.len() != 8 {
-                    println!(
-                        "Пример: + deposit Alice 100 transfer Alice Bob 30: cur {}",
-                        args.len()
-                    );
+                let text = args[1..].join(" ");
+                let script: Script = match text.parse() {
+                    Ok(script) => script,
+                    Err(e) => {
+                        // Показываем место ошибки прямо под сценарием
+                        let ScriptError { column, message, .. } = e;
+                        println!("  {}\n  {:>column$}\nОшибка в сценарии: {}", text, "^", message);
+                        continue;
+                    }
+                };
+                if script.steps.is_empty() {
+                    println!("Пример: + deposit Alice 100; transfer Alice Bob 30; withdraw Bob 5");
+                    continue;
+                }
+                if dry_run {
+                    preview(&storage, &script);
                     continue;
                 }
 
-                let deposit = Deposit {
-                    account: args[2].to_string(),
-                    amount: args[3].parse().unwrap_or(0),
-                };
+                // Сценарий атомарен и попадает в журнал одной записью:
+                // при ошибке балансы остаются прежними
+                match storage.commit(&script) {
+                    Ok(_) => {
+                        println!("Выполнено: {}", script);
+                    }
+                    Err(e) => println!("Ошибка при выполнении, изменения отменены: {}", e),
+                }
+            },
+            "statement" => {
+                if !(2..=4).contains(&args.len()) {
+                    println!("Пример: statement Alice 2024-01-01 2024-01-31");
+                    continue;
+                }
+                let name = args[1];
 
-                let from = args[5].to_string();
-                let to = args[6].to_string();
-                let amount: i64 = args[7].parse().unwrap_or(0);
-                let transfer = Transfer { from, to, amount };
-                
-                let combined_tx = deposit + transfer;
+                // Граница `to` включительно: выписка до конца указанного дня
+                let parse = |arg: Option<&str>, shift: Timestamp| match arg {
+                    None => Ok(None),
+                    Some(date) => parse_date(date).map(|ts| Some(ts + shift)).ok_or(date.to_string()),
+                };
+                let from = parse(args.get(2).copied(), 0);
+                let to = parse(args.get(3).copied(), SECONDS_PER_DAY);
+                let (from, to) = match (from, to) {
+                    (Ok(from), Ok(to)) => (from, to),
+                    (Err(date), _) | (_, Err(date)) => {
+                        println!("Некорректная дата {}, ожидается YYYY-MM-DD", date);
+                        continue;
+                    }
+                };
 
-                match combined_tx.apply(&mut storage) {
-                    Ok(_) => println!("Транзакции выполнены!"),
-                    Err(e) => println!("Ошибка при выполнении: {:?}", e),
+                let Some(statement) = storage.history().statement(name, from, to) else {
+                    println!("По счёту {} нет операций", name);
+                    continue;
+                };
+                println!("Выписка по счёту {}", name);
+                println!("Входящий остаток: {}", statement.opening);
+                for entry in &statement.entries {
+                    let sign = match entry.kind {
+                        EntryKind::Withdraw | EntryKind::TransferOut | EntryKind::Close | EntryKind::InterestCharge | EntryKind::Fee => "-",
+                        _ => "+",
+                    };
+                    let reverses = entry.reverses.map(|seq| format!(" (отмена #{})", seq)).unwrap_or_default();
+                    println!(
+                        "  #{} {} {:<12} {:<8} {}{} -> {}{}",
+                        entry.seq,
+                        format_timestamp(entry.timestamp),
+                        entry.kind,
+                        entry.counterparty.as_deref().unwrap_or(""),
+                        sign,
+                        entry.amount,
+                        entry.balance,
+                        reverses
+                    );
                 }
-
-                storage.save(FILE_NAME);
+                println!("Исходящий остаток: {}", statement.closing);
+            }
+            "reverse" | "undo" => {
+                let seq = match args.as_slice() {
+                    [_, seq] => seq.parse::<u64>().ok(),
+                    _ => None,
+                };
+                let Some(seq) = seq else {
+                    println!("Пример: reverse 12 (номер транзакции — из выписки)");
+                    continue;
+                };
+                match storage.reverse(seq) {
+                    Ok(_) => println!("Транзакция #{} отменена", seq),
+                    Err(TxError::InsufficientFunds { .. }) => {
+                        println!("Отменить #{} нельзя: деньги уже потрачены", seq)
+                    }
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "trial" => match storage.trial_balance() {
+                Ok(trial) => {
+                    println!("{:<12} {:>14} {:>14}", "Счёт", "Дебет", "Кредит");
+                    for (account, balance) in &trial.rows {
+                        match balance.checked_neg().filter(|_| balance.is_negative()) {
+                            Some(debit) => println!("{:<12} {:>14} {:>14}", account, debit, ""),
+                            None => println!("{:<12} {:>14} {:>14}", account, "", balance),
+                        }
+                    }
+                    println!("{:<12} {:>14} {:>14}", "Итого", trial.total_debit, trial.total_credit);
+                    if !trial.is_balanced() {
+                        println!("Внимание: дебет и кредит не сходятся!");
+                    }
+                }
+                Err(e) => println!("Ошибка: {}", e),
             },
+            "schedule" => {
+                if !(5..=6).contains(&args.len()) {
+                    println!("Пример: schedule Alice Bob 100 monthly:1 2024-01-01");
+                    continue;
+                }
+                let amount: Balance = match args[3].parse() {
+                    Ok(amount) => amount,
+                    Err(_) => {
+                        println!("Сумма должна быть числом, например 10.50");
+                        continue;
+                    }
+                };
+                let recurrence: Recurrence = match args[4].parse() {
+                    Ok(recurrence) => recurrence,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                let start = match args.get(5) {
+                    None => scheduler.clock().now(),
+                    Some(date) => match parse_date(date) {
+                        Some(start) => start,
+                        None => {
+                            println!("Некорректная дата {}, ожидается YYYY-MM-DD", date);
+                            continue;
+                        }
+                    },
+                };
+                let schedule = Schedule::new(args[1].to_string(), args[2].to_string(), amount, recurrence, start);
+                let first = schedule.due;
+                match storage.add_schedule(schedule) {
+                    Ok(id) => {
+                        println!("Регулярный платёж #{} добавлен, первый — {}", id, format_date(first));
+                        run_schedules(&mut storage, &scheduler);
+                    }
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "schedules" => {
+                if storage.schedules().is_empty() {
+                    println!("Регулярных платежей нет");
+                    continue;
+                }
+                for schedule in storage.schedules().iter() {
+                    let retry = if schedule.failures > 0 {
+                        format!(", повтор {}", format_timestamp(schedule.next_run))
+                    } else {
+                        String::new()
+                    };
+                    println!(
+                        "  #{} {} -> {} {} {}, следующий {}{}",
+                        schedule.id,
+                        schedule.from,
+                        schedule.to,
+                        schedule.amount,
+                        schedule.recurrence,
+                        format_date(schedule.due),
+                        retry
+                    );
+                }
+            }
+            "unschedule" => {
+                let Some(id) = args.get(1).and_then(|id| id.parse::<u64>().ok()) else {
+                    println!("Пример: unschedule 1 (номер — из списка schedules)");
+                    continue;
+                };
+                match storage.cancel_schedule(id) {
+                    Ok(_) => println!("Регулярный платёж #{} отменён", id),
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "runs" => {
+                if storage.schedules().runs().is_empty() {
+                    println!("Регулярные платежи ещё не проводились");
+                    continue;
+                }
+                for run in storage.schedules().runs() {
+                    println!(
+                        "  #{} за {} попытка {} {}: {} {}",
+                        run.schedule,
+                        format_date(run.due),
+                        run.attempt,
+                        format_timestamp(run.at),
+                        run.status,
+                        run.error.as_deref().unwrap_or("")
+                    );
+                }
+            }
+            "interest" => {
+                if args.len() != 3 {
+                    println!("Пример: interest Alice 4.75%,act/365,monthly,monthly,half_even");
+                    continue;
+                }
+                let name = args[1].to_string();
+                if args[2] == "off" {
+                    match storage.clear_interest(&name) {
+                        Ok(true) => println!("Проценты по счёту {} отключены", name),
+                        Ok(false) => println!("У счёта {} нет процентного продукта", name),
+                        Err(e) => println!("Ошибка: {}", e),
+                    }
+                    continue;
+                }
+                let product: InterestProduct = match args[2].parse() {
+                    Ok(product) => product,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                match storage.set_interest(&name, product, scheduler.clock().now()) {
+                    Ok(_) => println!("Счёту {} назначен продукт {}", name, product),
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "accruals" => {
+                let report = match storage.interest().report() {
+                    Ok(report) => report,
+                    Err(e) => {
+                        println!("Ошибка: {}", e);
+                        continue;
+                    }
+                };
+                if report.is_empty() {
+                    println!("Процентных продуктов нет");
+                    continue;
+                }
+                println!("{:<12} {:>14}  {:<12} Продукт", "Счёт", "Не выплачено", "Начислено по");
+                for line in report {
+                    // Начислено по день, предшествующий accrued_through
+                    let through = format_date(line.accrued_through.saturating_sub(SECONDS_PER_DAY));
+                    println!("{:<12} {:>14}  {:<12} {}", line.account, line.unposted, through, line.product);
+                }
+            }
+            "overdraft" => {
+                if args.len() != 3 {
+                    println!("Пример: overdraft Alice 500");
+                    continue;
+                }
+                let name = args[1].to_string();
+                let limit: Balance = match args[2].parse() {
+                    Ok(limit) => limit,
+                    Err(e) => {
+                        println!("Некорректный лимит: {}", e);
+                        continue;
+                    }
+                };
+                match storage.set_overdraft(&name, limit) {
+                    Ok(_) if limit.is_zero() => println!("Лимит овердрафта счёта {} снят", name),
+                    Ok(_) => println!("Счёту {} установлен лимит овердрафта {}", name, limit),
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "authorize" => {
+                if !(3..=4).contains(&args.len()) {
+                    println!("Пример: authorize Alice 25.50 [3]");
+                    continue;
+                }
+                let name = args[1].to_string();
+                let amount: Balance = match args[2].parse() {
+                    Ok(amount) => amount,
+                    Err(e) => {
+                        println!("Некорректная сумма: {}", e);
+                        continue;
+                    }
+                };
+                let ttl = match args.get(3).map(|days| days.parse::<u64>()) {
+                    None => DEFAULT_HOLD_TTL,
+                    Some(Ok(days)) if days > 0 => days * SECONDS_PER_DAY,
+                    Some(_) => {
+                        println!("Срок удержания — целое число дней больше нуля");
+                        continue;
+                    }
+                };
+                match storage.authorize(&name, amount, ttl) {
+                    Ok(id) => println!("Удержание #{}: {} на счёте {}", id, amount, name),
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "capture" => {
+                let Some(id) = args.get(1).and_then(|id| id.parse::<u64>().ok()).filter(|_| args.len() <= 4) else {
+                    println!("Пример: capture 1 [10.00] [Bob]");
+                    continue;
+                };
+                // Сумма необязательна: второй аргумент — либо сумма, либо получатель перевода
+                let (amount, to) = match args.get(2).map(|arg| arg.parse::<Balance>()) {
+                    Some(Ok(amount)) => (Some(amount), args.get(3)),
+                    Some(Err(_)) if args.len() == 3 => (None, args.get(2)),
+                    Some(Err(e)) => {
+                        println!("Некорректная сумма: {}", e);
+                        continue;
+                    }
+                    None => (None, None),
+                };
+                let to = to.map(|to| to.to_string());
+                match storage.capture(id, amount, to.as_ref()) {
+                    Ok(_) => match storage.holds().get(id) {
+                        Some(hold) => println!("Удержание #{} списано частично, удерживается ещё {}", id, hold.amount),
+                        None => println!("Удержание #{} списано", id),
+                    },
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "release" => {
+                let Some(id) = args.get(1).and_then(|id| id.parse::<u64>().ok()) else {
+                    println!("Пример: release 1");
+                    continue;
+                };
+                match storage.release(id) {
+                    Ok(hold) => println!("Удержание #{} снято, {} снова доступно на счёте {}", id, hold.amount, hold.account),
+                    Err(e) => println!("Ошибка: {}", e),
+                }
+            }
+            "holds" => {
+                if storage.holds().is_empty() {
+                    println!("Удержаний нет");
+                    continue;
+                }
+                println!("{:>4} {:<12} {:>14} {:>14}  Истекает", "#", "Счёт", "Удержано", "Списано");
+                for hold in storage.holds().iter() {
+                    println!(
+                        "{:>4} {:<12} {:>14} {:>14}  {}",
+                        hold.id,
+                        hold.account,
+                        hold.amount,
+                        hold.captured,
+                        format_timestamp(hold.expires)
+                    );
+                }
+            }
+            "fees" => {
+                if args.get(1) == Some(&"reload")
+                    && let Err(e) = storage.load_fees(Path::new(DEFAULT_FEES_FILE))
+                {
+                    println!("Ошибка: {}", e);
+                    continue;
+                }
+                if storage.fees().is_empty() {
+                    println!("Комиссий нет");
+                }
+                for rule in storage.fees().rules() {
+                    println!("{}", rule);
+                }
+            }
+            "compact" => {
+                if compact(&mut storage, FILE_NAME) {
+                    println!("Снапшот сохранён, журнал сокращён");
+                }
+            }
             "exit" => break,
             _ => println!("Неизвестная команда"),
         }
     }
 
+    compact(&mut storage, FILE_NAME);
     println!("Выход из CLI, все изменения сохранены.");
 }
\ No newline at end of file
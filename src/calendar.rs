@@ -0,0 +1,137 @@
+pub mod calendar {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Момент времени — секунды от начала эпохи Unix (UTC)
+    pub type Timestamp = u64;
+
+    pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+    /// Текущее время по системным часам
+    pub fn now() -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// Источник текущего времени для хранилища и планировщика. В тестах подменяется
+    /// на [`ManualClock`], чтобы «перематывать» время вперёд
+    pub trait Clock: Send + Sync {
+        fn now(&self) -> Timestamp;
+    }
+
+    /// Часы можно отдать хранилищу и продолжать переводить через общую ссылку
+    impl<C: Clock + ?Sized> Clock for Arc<C> {
+        fn now(&self) -> Timestamp {
+            C::now(self)
+        }
+    }
+
+    /// Системные часы
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> Timestamp {
+            now()
+        }
+    }
+
+    /// Часы, которые идут только тогда, когда их переводят
+    #[derive(Debug, Default)]
+    pub struct ManualClock {
+        now: AtomicU64,
+    }
+
+    impl ManualClock {
+        pub fn new(now: Timestamp) -> Self {
+            ManualClock { now: AtomicU64::new(now) }
+        }
+
+        pub fn set(&self, now: Timestamp) {
+            self.now.store(now, Ordering::SeqCst);
+        }
+
+        pub fn advance(&self, seconds: u64) {
+            self.now.fetch_add(seconds, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Timestamp {
+            self.now.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Номер дня от 1970-01-01 для даты григорианского календаря
+    /// (алгоритм days_from_civil Говарда Хиннанта)
+    pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let month = month as i64;
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    /// Дата (год, месяц, день) по номеру дня от 1970-01-01
+    pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    /// Разбирает дату `YYYY-MM-DD` в момент начала этого дня (00:00 UTC)
+    pub fn parse_date(s: &str) -> Option<Timestamp> {
+        let mut parts = s.split('-');
+        let year: i64 = parts.next()?.parse().ok()?;
+        let month: u32 = parts.next()?.parse().ok()?;
+        let day: u32 = parts.next()?.parse().ok()?;
+        if parts.next().is_some() || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+        let days = days_from_civil(year, month, day);
+        u64::try_from(days).ok().map(|days| days * SECONDS_PER_DAY)
+    }
+
+    /// Дата момента времени в виде `YYYY-MM-DD`
+    pub fn format_date(ts: Timestamp) -> String {
+        let (year, month, day) = civil_from_days((ts / SECONDS_PER_DAY) as i64);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    /// Момент времени в виде `YYYY-MM-DD HH:MM:SS` (UTC)
+    pub fn format_timestamp(ts: Timestamp) -> String {
+        let secs = ts % SECONDS_PER_DAY;
+        format!(
+            "{} {:02}:{:02}:{:02}",
+            format_date(ts),
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    }
+
+    pub fn is_leap_year(year: i64) -> bool {
+        (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+    }
+
+    pub fn days_in_month(year: i64, month: u32) -> u32 {
+        match month {
+            2 if is_leap_year(year) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
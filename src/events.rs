@@ -0,0 +1,98 @@
+pub mod events {
+    use crate::{Balance, Name, Operation};
+
+    /// Событие изменения балансов. Балансы в событиях — после изменения
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Event {
+        AccountOpened { account: Name },
+        /// `balance` — остаток, перенесённый при закрытии на транзитный счёт
+        AccountClosed { account: Name, balance: Balance },
+        Deposited { account: Name, amount: Balance, balance: Balance },
+        Withdrawn { account: Name, amount: Balance, balance: Balance },
+        Transferred { from: Name, to: Name, amount: Balance },
+        /// Выплачены (`amount` > 0) или списаны (`amount` < 0) проценты
+        InterestPosted { account: Name, amount: Balance, balance: Balance },
+        /// Списана комиссия за операцию
+        FeeCharged { account: Name, amount: Balance, balance: Balance },
+        /// На счёте удержана сумма; баланс не изменился
+        HoldPlaced { id: u64, account: Name, amount: Balance },
+        /// Удержание снято без списания; `amount` — остаток, который снова доступен
+        HoldReleased { id: u64, account: Name, amount: Balance },
+        /// Транзакция отклонена и ничего не изменила
+        TransactionFailed { operations: Vec<Operation>, error: String },
+    }
+
+    /// Получатель событий. Хранилище разделяется между потоками (в том числе
+    /// внутри [`crate::SharedStorage`]), поэтому подписчик — `Send + Sync`
+    pub trait Subscriber: Send + Sync {
+        fn on_event(&mut self, event: &Event);
+    }
+
+    impl<F: FnMut(&Event) + Send + Sync> Subscriber for F {
+        fn on_event(&mut self, event: &Event) {
+            self(event)
+        }
+    }
+
+    /// Номер подписки для [`EventBus::unsubscribe`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct SubscriberId(u64);
+
+    /// Шина событий хранилища.
+    ///
+    /// События копятся в буфере и рассылаются, только когда изменение
+    /// окончательно прошло: при откате транзакции хвост буфера отбрасывается,
+    /// поэтому подписчики не видят изменений, которых не было
+    #[derive(Default)]
+    pub struct EventBus {
+        subscribers: Vec<(SubscriberId, Box<dyn Subscriber>)>,
+        pending: Vec<Event>,
+        next_id: u64,
+    }
+
+    impl EventBus {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn subscribe(&mut self, subscriber: impl Subscriber + 'static) -> SubscriberId {
+            let id = SubscriberId(self.next_id);
+            self.next_id += 1;
+            self.subscribers.push((id, Box::new(subscriber)));
+            id
+        }
+
+        /// Отписывает подписчика; false — такой подписки нет
+        pub fn unsubscribe(&mut self, id: SubscriberId) -> bool {
+            let before = self.subscribers.len();
+            self.subscribers.retain(|(sid, _)| *sid != id);
+            self.subscribers.len() != before
+        }
+
+        /// Откладывает событие до [`EventBus::flush`]. Без подписчиков событие не хранится
+        pub fn publish(&mut self, event: Event) {
+            if !self.subscribers.is_empty() {
+                self.pending.push(event);
+            }
+        }
+
+        /// Сколько событий ждёт рассылки — отметка для [`EventBus::discard_after`]
+        pub fn pending_len(&self) -> usize {
+            self.pending.len()
+        }
+
+        /// Отбрасывает события, отложенные после отметки `len`
+        pub fn discard_after(&mut self, len: usize) {
+            self.pending.truncate(len);
+        }
+
+        /// Рассылает отложенные события всем подписчикам в порядке подписки
+        pub fn flush(&mut self) {
+            for event in std::mem::take(&mut self.pending) {
+                for (_, subscriber) in self.subscribers.iter_mut() {
+                    subscriber.on_event(&event);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+pub mod fees {
+    use std::fmt::{Display, Formatter};
+    use std::fs;
+    use std::path::Path;
+    use std::str::FromStr;
+    use crate::calendar::calendar::{self, SECONDS_PER_DAY, Timestamp};
+    use crate::history::history::{EntryKind, History};
+    use crate::interest::interest::Rate;
+    use crate::{Balance, StorageError};
+
+    /// Файл с комиссиями по умолчанию — рядом с `balance.csv`
+    pub const DEFAULT_FEES_FILE: &str = "fees.conf";
+
+    /// Вид операции, за которую берётся комиссия
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FeeKind {
+        Deposit,
+        Withdraw,
+        /// Комиссию платит отправитель
+        Transfer,
+    }
+
+    impl FeeKind {
+        /// Запись истории, за которую берётся комиссия этого вида
+        fn matches(self, kind: EntryKind) -> bool {
+            matches!(
+                (self, kind),
+                (FeeKind::Deposit, EntryKind::Deposit)
+                    | (FeeKind::Withdraw, EntryKind::Withdraw)
+                    | (FeeKind::Transfer, EntryKind::TransferOut)
+            )
+        }
+    }
+
+    impl Display for FeeKind {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                FeeKind::Deposit => "deposit",
+                FeeKind::Withdraw => "withdraw",
+                FeeKind::Transfer => "transfer",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl FromStr for FeeKind {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "deposit" => Ok(FeeKind::Deposit),
+                "withdraw" => Ok(FeeKind::Withdraw),
+                "transfer" => Ok(FeeKind::Transfer),
+                _ => Err(format!("неизвестный вид операции: {}", s)),
+            }
+        }
+    }
+
+    /// Процент от суммы, округлённый до копеек (половина — вверх)
+    fn percent_of(amount: Balance, rate: Rate) -> Option<Balance> {
+        let minor = i128::from(amount.minor()) * i128::from(rate.ppm());
+        let rounded = (minor + 500_000).div_euclid(1_000_000);
+        i64::try_from(rounded).ok().map(Balance::from_minor)
+    }
+
+    /// Как считается комиссия от суммы операции
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Charge {
+        Flat(Balance),
+        /// Процент от суммы, с необязательными нижней и верхней границами
+        Percent { rate: Rate, min: Option<Balance>, max: Option<Balance> },
+        /// Ступени по сумме операции: берётся ступень с наибольшим порогом,
+        /// не превышающим сумму. Ступени идут по возрастанию порога
+        Tiered(Vec<(Balance, Charge)>),
+    }
+
+    impl Charge {
+        /// Комиссия за операцию на сумму `amount`
+        pub fn fee(&self, amount: Balance) -> Option<Balance> {
+            match self {
+                Charge::Flat(fee) => Some(*fee),
+                Charge::Percent { rate, min, max } => {
+                    let mut fee = percent_of(amount, *rate)?;
+                    if let Some(min) = min {
+                        fee = fee.max(*min);
+                    }
+                    if let Some(max) = max {
+                        fee = fee.min(*max);
+                    }
+                    Some(fee)
+                }
+                Charge::Tiered(tiers) => match tiers.iter().rev().find(|(from, _)| *from <= amount) {
+                    Some((_, charge)) => charge.fee(amount),
+                    None => Some(Balance::ZERO),
+                },
+            }
+        }
+
+        /// Есть ли в тарифе отрицательная сумма или ставка
+        fn is_negative(&self) -> bool {
+            match self {
+                Charge::Flat(fee) => fee.is_negative(),
+                Charge::Percent { rate, min, max } => {
+                    rate.ppm() < 0 || min.is_some_and(Balance::is_negative) || max.is_some_and(Balance::is_negative)
+                }
+                Charge::Tiered(tiers) => tiers.iter().any(|(_, charge)| charge.is_negative()),
+            }
+        }
+
+        /// Ступень тарифа: `<порог>:<сумма>` или `<порог>:<ставка>%`
+        fn tier(s: &str) -> Result<(Balance, Charge), String> {
+            let (from, fee) = s.split_once(':').ok_or_else(|| format!("ожидается <порог>:<комиссия>: {}", s))?;
+            let from = from.parse().map_err(|e: crate::ParseMoneyError| e.to_string())?;
+            let charge = if fee.ends_with('%') {
+                Charge::Percent { rate: fee.parse()?, min: None, max: None }
+            } else {
+                Charge::Flat(fee.parse().map_err(|e: crate::ParseMoneyError| e.to_string())?)
+            };
+            Ok((from, charge))
+        }
+    }
+
+    /// Текстовый вид: `flat 1.00`, `percent 0.5% [min 0.50] [max 10.00]`,
+    /// `tiered 0:1.00 1000:0.5%`
+    impl Display for Charge {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Charge::Flat(fee) => write!(f, "flat {}", fee),
+                Charge::Percent { rate, min, max } => {
+                    write!(f, "percent {}", rate)?;
+                    if let Some(min) = min {
+                        write!(f, " min {}", min)?;
+                    }
+                    if let Some(max) = max {
+                        write!(f, " max {}", max)?;
+                    }
+                    Ok(())
+                }
+                Charge::Tiered(tiers) => {
+                    write!(f, "tiered")?;
+                    for (from, charge) in tiers {
+                        match charge {
+                            Charge::Percent { rate, .. } => write!(f, " {}:{}", from, rate)?,
+                            Charge::Flat(fee) => write!(f, " {}:{}", from, fee)?,
+                            Charge::Tiered(_) => unreachable!("вложенные ступени не разбираются"),
+                        }
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Период, за который действует лимит комиссий
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CapPeriod {
+        Day,
+        Month,
+    }
+
+    impl CapPeriod {
+        /// Начало периода, в который попадает `time`
+        pub fn start(self, time: Timestamp) -> Timestamp {
+            let day = time / SECONDS_PER_DAY * SECONDS_PER_DAY;
+            match self {
+                CapPeriod::Day => day,
+                CapPeriod::Month => {
+                    let (_, _, day_of_month) = calendar::civil_from_days((day / SECONDS_PER_DAY) as i64);
+                    day - u64::from(day_of_month - 1) * SECONDS_PER_DAY
+                }
+            }
+        }
+    }
+
+    /// Не больше `limit` комиссий этого вида с одного счёта за период
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Cap {
+        pub limit: Balance,
+        pub period: CapPeriod,
+    }
+
+    /// Правило: комиссия за операции одного вида
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct FeeRule {
+        pub kind: FeeKind,
+        pub charge: Charge,
+        pub cap: Option<Cap>,
+    }
+
+    /// Текстовый вид — строка файла комиссий:
+    /// `<вид> <комиссия> [cap <лимит>/day|month]`
+    impl Display for FeeRule {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{} {}", self.kind, self.charge)?;
+            if let Some(cap) = self.cap {
+                let period = match cap.period {
+                    CapPeriod::Day => "day",
+                    CapPeriod::Month => "month",
+                };
+                write!(f, " cap {}/{}", cap.limit, period)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl FromStr for FeeRule {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let money = |s: &str| s.parse::<Balance>().map_err(|e| e.to_string());
+            let mut words: Vec<&str> = s.split_whitespace().collect();
+
+            let cap = match words.as_slice() {
+                [.., "cap", cap] => {
+                    let (limit, period) = cap.split_once('/').ok_or_else(|| format!("ожидается cap <лимит>/day|month: {}", s))?;
+                    let period = match period {
+                        "day" => CapPeriod::Day,
+                        "month" => CapPeriod::Month,
+                        _ => return Err(format!("неизвестный период лимита: {}", period)),
+                    };
+                    words.truncate(words.len() - 2);
+                    Some(Cap { limit: money(limit)?, period })
+                }
+                _ => None,
+            };
+
+            let charge = match words.as_slice() {
+                [_, "flat", fee] => Charge::Flat(money(fee)?),
+                [_, "percent", rate, bounds @ ..] => {
+                    let (mut min, mut max) = (None, None);
+                    for pair in bounds.chunks(2) {
+                        match pair {
+                            ["min", amount] => min = Some(money(amount)?),
+                            ["max", amount] => max = Some(money(amount)?),
+                            _ => return Err(format!("ожидается min <сумма> или max <сумма>: {}", s)),
+                        }
+                    }
+                    Charge::Percent { rate: rate.parse()?, min, max }
+                }
+                [_, "tiered", tiers @ ..] if !tiers.is_empty() => {
+                    let tiers = tiers.iter().map(|tier| Charge::tier(tier)).collect::<Result<Vec<_>, _>>()?;
+                    if !tiers.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+                        return Err(format!("пороги ступеней должны возрастать: {}", s));
+                    }
+                    Charge::Tiered(tiers)
+                }
+                _ => return Err(format!("ожидается <вид> flat|percent|tiered ...: {}", s)),
+            };
+            if charge.is_negative() || cap.is_some_and(|cap| cap.limit.is_negative()) {
+                return Err(format!("комиссия не может быть отрицательной: {}", s));
+            }
+            Ok(FeeRule { kind: words[0].parse()?, charge, cap })
+        }
+    }
+
+    /// Тариф: не больше одного правила на вид операции
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct FeeSchedule {
+        rules: Vec<FeeRule>,
+    }
+
+    impl FeeSchedule {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn rules(&self) -> &[FeeRule] {
+            &self.rules
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.rules.is_empty()
+        }
+
+        pub fn rule(&self, kind: FeeKind) -> Option<&FeeRule> {
+            self.rules.iter().find(|rule| rule.kind == kind)
+        }
+
+        /// Добавляет правило; правило для того же вида операции заменяется
+        pub fn with(mut self, rule: FeeRule) -> Self {
+            self.rules.retain(|existing| existing.kind != rule.kind);
+            self.rules.push(rule);
+            self
+        }
+
+        /// Читает тариф из файла; если файла нет — тариф пустой
+        pub fn load(path: &Path) -> Result<FeeSchedule, StorageError> {
+            if !path.exists() {
+                return Ok(FeeSchedule::new());
+            }
+            fs::read_to_string(path)?
+                .parse::<FeeSchedule>()
+                .map_err(|(line, message)| StorageError::Parse { line, message })
+        }
+    }
+
+    /// Правила через `;` — так тариф записывается в журнал одной строкой
+    impl Display for FeeSchedule {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            for (i, rule) in self.rules.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ";")?;
+                }
+                write!(f, "{}", rule)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Разбирает файл комиссий: по правилу на строку (или через `;`),
+    /// `#` — комментарий до конца строки. Ошибка — номер строки с единицы и текст
+    impl FromStr for FeeSchedule {
+        type Err = (usize, String);
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut schedule = FeeSchedule::new();
+            for (index, line) in s.lines().enumerate() {
+                let line = line.split_once('#').map_or(line, |(code, _)| code);
+                for rule in line.split(';').map(str::trim).filter(|rule| !rule.is_empty()) {
+                    let rule: FeeRule = rule.parse().map_err(|message| (index + 1, message))?;
+                    if schedule.rule(rule.kind).is_some() {
+                        return Err((index + 1, format!("повторное правило для {}", rule.kind)));
+                    }
+                    schedule.rules.push(rule);
+                }
+            }
+            Ok(schedule)
+        }
+    }
+
+    /// Сколько комиссий за операции вида `kind` взято со счёта начиная с `since`.
+    /// Комиссия записывается в историю сразу после своей операции, с тем же номером изменения
+    pub fn charged(history: &History, account: &str, kind: FeeKind, since: Timestamp) -> Balance {
+        let mut operation: Option<(u64, EntryKind)> = None;
+        let mut total = Balance::ZERO;
+        for entry in history.for_account(account) {
+            match entry.kind {
+                EntryKind::Fee => {
+                    let paid_for = operation.filter(|(seq, _)| *seq == entry.seq).map(|(_, kind)| kind);
+                    if entry.timestamp >= since && paid_for.is_some_and(|paid_for| kind.matches(paid_for)) {
+                        total = total.checked_add(entry.amount).unwrap_or(Balance::MAX);
+                    }
+                }
+                // Перевод самому себе: комиссия идёт после входящей записи, но платится за исходящую
+                EntryKind::TransferIn => {}
+                other => operation = Some((entry.seq, other)),
+            }
+        }
+        total
+    }
+}
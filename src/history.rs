@@ -0,0 +1,301 @@
+pub mod history {
+    use std::collections::HashMap;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+    use crate::calendar::calendar::Timestamp;
+    use crate::{Balance, Name};
+
+    /// Вид изменения баланса
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum EntryKind {
+        Open,
+        Close,
+        Deposit,
+        Withdraw,
+        /// Входящий перевод: деньги пришли от `counterparty`
+        TransferIn,
+        /// Исходящий перевод: деньги ушли к `counterparty`
+        TransferOut,
+        /// Выплачены проценты
+        Interest,
+        /// Списаны проценты (отрицательная ставка или долг)
+        InterestCharge,
+        /// Списана комиссия за операцию, записанную перед ней
+        Fee,
+    }
+
+    impl Display for EntryKind {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                EntryKind::Open => "open",
+                EntryKind::Close => "close",
+                EntryKind::Deposit => "deposit",
+                EntryKind::Withdraw => "withdraw",
+                EntryKind::TransferIn => "transfer_in",
+                EntryKind::TransferOut => "transfer_out",
+                EntryKind::Interest => "interest",
+                EntryKind::InterestCharge => "interest_charge",
+                EntryKind::Fee => "fee",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl FromStr for EntryKind {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "open" => Ok(EntryKind::Open),
+                "close" => Ok(EntryKind::Close),
+                "deposit" => Ok(EntryKind::Deposit),
+                "withdraw" => Ok(EntryKind::Withdraw),
+                "transfer_in" => Ok(EntryKind::TransferIn),
+                "transfer_out" => Ok(EntryKind::TransferOut),
+                "interest" => Ok(EntryKind::Interest),
+                "interest_charge" => Ok(EntryKind::InterestCharge),
+                "fee" => Ok(EntryKind::Fee),
+                _ => Err(format!("неизвестный вид операции: {}", s)),
+            }
+        }
+    }
+
+    /// Одна запись истории счёта
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct HistoryEntry {
+        /// Номер изменения. У всех записей одной транзакции (обеих сторон перевода,
+        /// всех шагов цепочки) номер общий — он же идентификатор транзакции
+        pub seq: u64,
+        pub timestamp: Timestamp,
+        pub account: Name,
+        pub kind: EntryKind,
+        /// Второй участник перевода
+        pub counterparty: Option<Name>,
+        pub amount: Balance,
+        /// Баланс счёта после операции
+        pub balance: Balance,
+        /// Номер изменения, которое отменяет эта запись
+        pub reverses: Option<u64>,
+    }
+
+    /// Текстовый вид: `seq,timestamp,account,kind,counterparty,amount,balance,reverses`
+    impl Display for HistoryEntry {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "{},{},{},{},{},{},{},{}",
+                self.seq,
+                self.timestamp,
+                self.account,
+                self.kind,
+                self.counterparty.as_deref().unwrap_or(""),
+                self.amount,
+                self.balance,
+                self.reverses.map(|seq| seq.to_string()).unwrap_or_default()
+            )
+        }
+    }
+
+    impl FromStr for HistoryEntry {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let parts: Vec<&str> = s.split(',').collect();
+            let [seq, timestamp, account, kind, counterparty, amount, balance, reverses] = parts[..] else {
+                return Err(format!("ожидается 8 полей записи истории: {}", s));
+            };
+            let number = |field: &str| field.parse::<u64>().map_err(|_| format!("некорректное число: {}", field));
+            let money = |field: &str| field.parse::<Balance>().map_err(|e| e.to_string());
+
+            Ok(HistoryEntry {
+                seq: number(seq)?,
+                timestamp: number(timestamp)?,
+                account: account.to_string(),
+                kind: kind.parse()?,
+                counterparty: (!counterparty.is_empty()).then(|| counterparty.to_string()),
+                amount: money(amount)?,
+                balance: money(balance)?,
+                reverses: if reverses.is_empty() { None } else { Some(number(reverses)?) },
+            })
+        }
+    }
+
+    /// Выписка по счёту за период
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Statement {
+        pub account: Name,
+        /// Баланс на начало периода
+        pub opening: Balance,
+        /// Баланс на конец периода
+        pub closing: Balance,
+        pub entries: Vec<HistoryEntry>,
+    }
+
+    /// История изменений всех счетов в порядке их применения.
+    ///
+    /// Старые записи переносятся в архив (см. [`History::archive`]): в памяти и в снапшоте
+    /// остаются только записи не раньше [`History::archived_before`], а вместо
+    /// перенесённых — баланс каждого счёта после его последней перенесённой записи
+    #[derive(Debug, Clone)]
+    pub struct History {
+        entries: Vec<HistoryEntry>,
+        next_seq: u64,
+        /// Номера записей в `entries` по счетам — чтобы не просматривать чужие записи
+        by_account: HashMap<Name, Vec<usize>>,
+        /// Записи раньше этого момента перенесены в архив
+        archived_before: Timestamp,
+        /// Баланс счёта после его последней перенесённой в архив записи
+        opening: HashMap<Name, Balance>,
+    }
+
+    impl Default for History {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl History {
+        pub fn new() -> Self {
+            History {
+                entries: Vec::new(),
+                next_seq: 1,
+                by_account: HashMap::new(),
+                archived_before: 0,
+                opening: HashMap::new(),
+            }
+        }
+
+        /// Выдаёт номер для следующего изменения
+        pub fn next_seq(&mut self) -> u64 {
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            seq
+        }
+
+        pub fn push(&mut self, entry: HistoryEntry) {
+            self.next_seq = self.next_seq.max(entry.seq + 1);
+            self.by_account.entry(entry.account.clone()).or_default().push(self.entries.len());
+            self.entries.push(entry);
+        }
+
+        pub fn entries(&self) -> &[HistoryEntry] {
+            &self.entries
+        }
+
+        /// Все записи по одному счёту, кроме перенесённых в архив
+        pub fn for_account<'a>(&'a self, account: &'a str) -> impl DoubleEndedIterator<Item = &'a HistoryEntry> + 'a {
+            self.by_account.get(account).into_iter().flatten().map(|&index| &self.entries[index])
+        }
+
+        /// Момент, записи раньше которого перенесены в архив; 0 — архива нет
+        pub fn archived_before(&self) -> Timestamp {
+            self.archived_before
+        }
+
+        /// Балансы счетов после их последних перенесённых в архив записей
+        pub fn opening(&self) -> impl Iterator<Item = (&Name, &Balance)> {
+            self.opening.iter()
+        }
+
+        /// Выписка по счёту за полуинтервал `[from, to)`; None — границы не заданы.
+        /// Выписка строится без архива: начало периода раньше [`History::archived_before`]
+        /// сдвигается к нему. Возвращает None, если по счёту нет ни одной записи
+        pub fn statement(&self, account: &str, from: Option<Timestamp>, to: Option<Timestamp>) -> Option<Statement> {
+            let archived = self.opening.get(account).copied();
+            let mut entries = self.for_account(account).peekable();
+            if archived.is_none() {
+                entries.peek()?;
+            }
+
+            let mut opening = archived.unwrap_or(Balance::ZERO);
+            let mut in_period = Vec::new();
+            for entry in entries {
+                if from.is_some_and(|from| entry.timestamp < from) {
+                    opening = entry.balance;
+                } else if to.is_none_or(|to| entry.timestamp < to) {
+                    in_period.push(entry.clone());
+                }
+            }
+
+            let closing = in_period.last().map(|entry| entry.balance).unwrap_or(opening);
+            Some(Statement {
+                account: account.to_string(),
+                opening,
+                closing,
+                entries: in_period,
+            })
+        }
+
+        /// Баланс счёта на момент `time` — после последней записи раньше него.
+        /// Записи просматриваются с конца, поэтому недавний момент находится быстро;
+        /// раньше всех оставшихся записей — баланс на начало истории.
+        /// None, если по счёту нет ни одной записи
+        pub fn balance_at(&self, account: &str, time: Timestamp) -> Option<Balance> {
+            let archived = self.opening.get(account).copied();
+            let mut entries = self.for_account(account).rev().peekable();
+            if archived.is_none() {
+                entries.peek()?;
+            }
+            let found = entries.find(|entry| entry.timestamp < time).map(|entry| entry.balance);
+            Some(found.or(archived).unwrap_or(Balance::ZERO))
+        }
+
+        /// Сколько первых записей относятся ко времени раньше `before` и могут уйти в архив.
+        /// Записи уходят только с начала: запись задним числом после более поздних остаётся
+        pub(crate) fn archivable(&self, before: Timestamp) -> usize {
+            self.entries.iter().take_while(|entry| entry.timestamp < before).count()
+        }
+
+        /// Убирает `count` первых записей, запоминая балансы счетов после них,
+        /// и возвращает убранные записи. Сохранить их в архив — дело вызывающего
+        pub(crate) fn archive(&mut self, count: usize, before: Timestamp) -> Vec<HistoryEntry> {
+            let archived: Vec<HistoryEntry> = self.entries.drain(..count).collect();
+            for entry in &archived {
+                self.opening.insert(entry.account.clone(), entry.balance);
+            }
+            self.archived_before = self.archived_before.max(before);
+            self.by_account.clear();
+            for (index, entry) in self.entries.iter().enumerate() {
+                self.by_account.entry(entry.account.clone()).or_default().push(index);
+            }
+            archived
+        }
+
+        /// Восстанавливает из снапшота состояние архива: границу, счётчик номеров
+        /// и балансы счетов после перенесённых записей
+        pub(crate) fn restore_archive(&mut self, before: Timestamp, next_seq: u64, opening: HashMap<Name, Balance>) {
+            self.archived_before = before;
+            self.next_seq = self.next_seq.max(next_seq);
+            self.opening = opening;
+        }
+
+        /// Все записи изменения с номером `seq` в порядке применения
+        pub fn change(&self, seq: u64) -> impl Iterator<Item = &HistoryEntry> {
+            self.entries.iter().filter(move |entry| entry.seq == seq)
+        }
+
+        /// Номер изменения, отменившего изменение `seq`, если такое есть
+        pub fn reversed_by(&self, seq: u64) -> Option<u64> {
+            self.entries.iter().find(|entry| entry.reverses == Some(seq)).map(|entry| entry.seq)
+        }
+
+        /// Количество записей — используется для отката
+        pub(crate) fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        /// Отбрасывает записи, сделанные после отметки `len`/`next_seq`
+        pub(crate) fn truncate(&mut self, len: usize, next_seq: u64) {
+            for entry in self.entries.drain(len.min(self.entries.len())..).rev() {
+                if let Some(indexes) = self.by_account.get_mut(&entry.account) {
+                    indexes.pop();
+                }
+            }
+            self.next_seq = next_seq;
+        }
+
+        pub(crate) fn peek_seq(&self) -> u64 {
+            self.next_seq
+        }
+    }
+}
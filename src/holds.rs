@@ -0,0 +1,155 @@
+pub mod holds {
+    use std::collections::BTreeMap;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+    use crate::calendar::calendar::{SECONDS_PER_DAY, Timestamp};
+    use crate::{Balance, Name, StorageError};
+
+    /// Срок удержания по умолчанию — неделя, как у карточных авторизаций
+    pub const DEFAULT_HOLD_TTL: u64 = 7 * SECONDS_PER_DAY;
+
+    /// Удержание (авторизация): сумма зарезервирована на счёте, но ещё не списана.
+    /// Баланс счёта не меняется, уменьшается только доступная сумма
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Hold {
+        pub id: u64,
+        pub account: Name,
+        /// Сколько ещё удерживается
+        pub amount: Balance,
+        /// Сколько уже списано частичными захватами
+        pub captured: Balance,
+        pub created: Timestamp,
+        /// С этого момента удержание не действует и снимается
+        pub expires: Timestamp,
+    }
+
+    impl Hold {
+        /// Удержание на `ttl` секунд с момента `created`; номер назначает хранилище
+        pub fn new(account: Name, amount: Balance, created: Timestamp, ttl: u64) -> Self {
+            Hold { id: 0, account, amount, captured: Balance::ZERO, created, expires: created.saturating_add(ttl) }
+        }
+
+        pub fn is_active(&self, now: Timestamp) -> bool {
+            now < self.expires
+        }
+    }
+
+    /// Текстовый вид: `id,account,amount,captured,created,expires`
+    impl Display for Hold {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{},{},{},{},{},{}", self.id, self.account, self.amount, self.captured, self.created, self.expires)
+        }
+    }
+
+    impl FromStr for Hold {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let fields: Vec<&str> = s.split(',').collect();
+            let [id, account, amount, captured, created, expires] = fields.as_slice() else {
+                return Err(format!("ожидается 6 полей удержания: {}", s));
+            };
+            let number = |field: &str| field.parse::<u64>().map_err(|_| format!("некорректное число: {}", field));
+            let money = |field: &str| field.parse::<Balance>().map_err(|e| e.to_string());
+            Ok(Hold {
+                id: number(id)?,
+                account: account.to_string(),
+                amount: money(amount)?,
+                captured: money(captured)?,
+                created: number(created)?,
+                expires: number(expires)?,
+            })
+        }
+    }
+
+    /// Удержания хранилища
+    #[derive(Debug, Clone, Default)]
+    pub struct HoldBook {
+        holds: BTreeMap<u64, Hold>,
+        next_id: u64,
+    }
+
+    impl HoldBook {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(&self, id: u64) -> Option<&Hold> {
+            self.holds.get(&id)
+        }
+
+        /// Удержания по возрастанию номера, включая истёкшие, но ещё не снятые
+        pub fn iter(&self) -> impl Iterator<Item = &Hold> {
+            self.holds.values()
+        }
+
+        pub fn len(&self) -> usize {
+            self.holds.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.holds.is_empty()
+        }
+
+        /// Номер для следующего удержания; номера снятых не переиспользуются
+        pub fn next_id(&self) -> u64 {
+            self.next_id.max(1)
+        }
+
+        /// Сколько удерживается на счёте в момент `now`; истёкшие удержания не считаются
+        pub fn held(&self, account: &str, now: Timestamp) -> Balance {
+            self.holds
+                .values()
+                .filter(|hold| hold.account == account && hold.is_active(now))
+                .fold(Balance::ZERO, |sum, hold| sum.checked_add(hold.amount).unwrap_or(Balance::MAX))
+        }
+
+        pub(crate) fn set_next_id(&mut self, id: u64) {
+            self.next_id = self.next_id.max(id);
+        }
+
+        pub(crate) fn insert(&mut self, hold: Hold) {
+            self.set_next_id(hold.id + 1);
+            self.holds.insert(hold.id, hold);
+        }
+
+        pub(crate) fn remove(&mut self, id: u64) -> Result<Hold, StorageError> {
+            self.holds.remove(&id).ok_or(StorageError::HoldNotFound(id))
+        }
+
+        /// Снимает все удержания закрываемого счёта
+        pub(crate) fn remove_account(&mut self, account: &str) {
+            self.holds.retain(|_, hold| hold.account != account);
+        }
+
+        /// Возвращает удержание `id` и счётчик номеров в прежнее состояние при откате
+        pub(crate) fn restore(&mut self, id: u64, hold: Option<Hold>, next_id: u64) {
+            match hold {
+                Some(hold) => self.holds.insert(id, hold),
+                None => self.holds.remove(&id),
+            };
+            self.next_id = next_id;
+        }
+
+        /// Переводит `amount` из удержания в списанное. Удержание, удержанная
+        /// сумма которого кончилась, снимается. Истёкшее удержание захватить нельзя
+        pub(crate) fn capture(&mut self, id: u64, amount: Balance, now: Timestamp) -> Result<Hold, StorageError> {
+            let hold = self.holds.get_mut(&id).filter(|hold| hold.is_active(now)).ok_or(StorageError::HoldNotFound(id))?;
+            if !amount.is_positive() || amount > hold.amount {
+                return Err(StorageError::InvalidAmount(amount));
+            }
+            hold.amount = hold.amount.checked_sub(amount).ok_or(StorageError::Overflow)?;
+            hold.captured = hold.captured.checked_add(amount).ok_or(StorageError::Overflow)?;
+            let hold = hold.clone();
+            if hold.amount.is_zero() {
+                self.holds.remove(&id);
+            }
+            Ok(hold)
+        }
+
+        /// Номера удержаний, истёкших к моменту `now`
+        pub(crate) fn expired(&self, now: Timestamp) -> Vec<u64> {
+            self.holds.values().filter(|hold| !hold.is_active(now)).map(|hold| hold.id).collect()
+        }
+    }
+}
@@ -0,0 +1,434 @@
+pub mod http {
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+    use std::sync::{Mutex, PoisonError};
+    use crate::json::json::Json;
+    use crate::{Balance, Batch, Deposit, HistoryEntry, Operation, Storage, StorageError, Transaction, Transfer, TxError, Withdraw};
+    use crate::calendar::calendar::format_timestamp;
+
+    /// Адрес HTTP-сервера по умолчанию: только локальные подключения
+    pub const DEFAULT_HTTP_ADDR: &str = "127.0.0.1:8080";
+    /// Наибольший размер тела запроса
+    const MAX_BODY: usize = 1 << 20;
+    /// Наибольшая длина строки запроса и каждого заголовка вместе с переводом строки
+    const MAX_LINE: usize = 8 << 10;
+    /// Наибольшее число заголовков в запросе
+    const MAX_HEADERS: usize = 100;
+
+    /// Адреса, на которых можно слушать HTTP API или TCP-сервер. Аутентификации нет
+    /// ни там, ни там, поэтому все адреса, в которые разрешается `addr`, должны быть loopback
+    pub fn loopback_addrs(addr: &str) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        if addrs.is_empty() {
+            return Err(invalid(&format!("адрес {} ни во что не разрешается", addr)));
+        }
+        if let Some(open) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("сервер без аутентификации слушает только loopback, а {} доступен извне", open),
+            ));
+        }
+        Ok(addrs)
+    }
+
+    /// Разобранный HTTP-запрос
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Request {
+        pub method: String,
+        /// Путь без строки запроса (`?…`)
+        pub path: String,
+        pub headers: Vec<(String, String)>,
+        pub body: String,
+    }
+
+    impl Request {
+        /// Значение заголовка; имя сравнивается без учёта регистра
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// Ответ: код статуса и JSON-тело
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct HttpResponse {
+        pub status: u16,
+        pub body: Json,
+    }
+
+    impl HttpResponse {
+        pub fn new(status: u16, body: Json) -> Self {
+            HttpResponse { status, body }
+        }
+
+        /// Ошибка в едином формате `{"error": <код>, "message": <текст>}`
+        pub fn error(status: u16, code: &str, message: impl Into<String>) -> Self {
+            HttpResponse::new(status, Json::object([("error", Json::string(code)), ("message", Json::string(message))]))
+        }
+
+        pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+            let body = self.body.to_string();
+            write!(
+                writer,
+                "HTTP/1.1 {} {}\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                self.status,
+                reason(self.status),
+                body.len(),
+                body
+            )?;
+            writer.flush()
+        }
+    }
+
+    fn reason(status: u16) -> &'static str {
+        match status {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            422 => "Unprocessable Entity",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn invalid(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+    }
+
+    fn headers_too_large(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::FileTooLarge, message.to_string())
+    }
+
+    /// Читает строку не длиннее [`MAX_LINE`]: иначе клиент мог бы занять
+    /// всю память сервера одной бесконечной строкой. Ok(None) — строка длиннее
+    fn read_line_limited<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<Option<usize>> {
+        let read = Read::take(reader, MAX_LINE as u64).read_line(line)?;
+        if read == MAX_LINE && !line.ends_with('\n') {
+            return Ok(None);
+        }
+        Ok(Some(read))
+    }
+
+    /// Читает один запрос; None — клиент закрыл соединение, ничего не прислав.
+    /// Тело, строка запроса, каждый заголовок и их число ограничены
+    pub fn read_request<R: BufRead>(mut reader: R) -> io::Result<Option<Request>> {
+        let mut line = String::new();
+        match read_line_limited(&mut reader, &mut line)? {
+            Some(0) => return Ok(None),
+            Some(_) => {}
+            None => return Err(invalid("слишком длинная строка запроса")),
+        }
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(invalid("некорректная строка запроса"));
+        };
+        let path = target.split_once('?').map_or(target, |(path, _)| path).to_string();
+        let method = method.to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            match read_line_limited(&mut reader, &mut line)? {
+                Some(0) => return Err(invalid("заголовки оборваны")),
+                Some(_) => {}
+                None => return Err(headers_too_large("слишком длинный заголовок")),
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(headers_too_large("слишком много заголовков"));
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| invalid("некорректный заголовок"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut request = Request { method, path, headers, body: String::new() };
+        let length: usize = match request.header("Content-Length") {
+            Some(length) => length.parse().map_err(|_| invalid("некорректный Content-Length"))?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(io::Error::new(io::ErrorKind::OutOfMemory, "слишком большое тело запроса"));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        request.body = String::from_utf8(body).map_err(|_| invalid("тело запроса не в UTF-8"))?;
+        Ok(Some(request))
+    }
+
+    /// Раскодирует `%XX` в сегменте пути (имена клиентов могут быть не латиницей)
+    fn percent_decode(segment: &str) -> Option<String> {
+        let bytes = segment.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            } else {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8(decoded).ok()
+    }
+
+    /// Сопоставляет запрос с обработчиком:
+    ///
+    /// ```text
+    /// GET  /accounts                -> 200 [{"name", "balance"}]
+    /// POST /accounts                -> 201 {"name", "balance"}   тело: {"name", "balance"?}
+    /// GET  /accounts/{name}         -> 200 {"name", "balance", "held", "available"}
+    /// GET  /accounts/{name}/history -> 200 [запись истории]
+    /// POST /transactions            -> 200 {"status", "balances"} тело: см. parse_transaction
+    /// ```
+    ///
+    /// Заголовок `Idempotency-Key` у POST /transactions делает повтор запроса безопасным;
+    /// тот же ключ с другой транзакцией — 409 `key_conflict`.
+    /// Суммы в ответах — строки (`"10.50"`), в запросах — строки или числа
+    pub fn route(storage: &mut Storage, request: &Request) -> HttpResponse {
+        let Some(segments) = request
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(percent_decode)
+            .collect::<Option<Vec<String>>>()
+        else {
+            return HttpResponse::error(400, "bad_request", "некорректный путь");
+        };
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["accounts"]) => {
+                let mut accounts = storage.get_all();
+                accounts.sort();
+                let accounts = accounts.iter().map(|(name, balance)| account_json(name, *balance)).collect();
+                HttpResponse::new(200, Json::Array(accounts))
+            }
+            ("POST", ["accounts"]) => create_account(storage, &request.body),
+            ("GET", ["accounts", name]) => match storage.get_balance(&name.to_string()) {
+                Some(balance) => {
+                    let mut account = account_json(name, balance);
+                    if let Json::Object(fields) = &mut account {
+                        let available = storage.available(&name.to_string()).unwrap_or(Balance::ZERO);
+                        fields.push(("held".to_string(), Json::string(storage.held(name).to_string())));
+                        fields.push(("available".to_string(), Json::string(available.to_string())));
+                    }
+                    HttpResponse::new(200, account)
+                }
+                None => storage_error(&StorageError::NotFound(name.to_string())),
+            },
+            ("GET", ["accounts", name, "history"]) => {
+                if storage.get_balance(&name.to_string()).is_none() && storage.history().for_account(name).next().is_none() {
+                    return storage_error(&StorageError::NotFound(name.to_string()));
+                }
+                let entries = storage.history().for_account(name).map(history_json).collect();
+                HttpResponse::new(200, Json::Array(entries))
+            }
+            ("POST", ["transactions"]) => commit_transaction(storage, request),
+            (_, ["accounts"] | ["accounts", _] | ["accounts", _, "history"] | ["transactions"]) => {
+                HttpResponse::error(405, "method_not_allowed", format!("метод {} не поддерживается", request.method))
+            }
+            _ => HttpResponse::error(404, "not_found", format!("нет такого адреса: {}", request.path)),
+        }
+    }
+
+    fn account_json(name: &str, balance: Balance) -> Json {
+        Json::object([("name", Json::string(name)), ("balance", Json::string(balance.to_string()))])
+    }
+
+    fn history_json(entry: &HistoryEntry) -> Json {
+        let optional = |value: Option<String>| value.map_or(Json::Null, Json::String);
+        Json::object([
+            ("seq", Json::Number(entry.seq.to_string())),
+            ("timestamp", Json::Number(entry.timestamp.to_string())),
+            ("time", Json::string(format_timestamp(entry.timestamp))),
+            ("kind", Json::string(entry.kind.to_string())),
+            ("counterparty", optional(entry.counterparty.clone())),
+            ("amount", Json::string(entry.amount.to_string())),
+            ("balance", Json::string(entry.balance.to_string())),
+            ("reverses", entry.reverses.map_or(Json::Null, |seq| Json::Number(seq.to_string()))),
+        ])
+    }
+
+    fn parse_body(body: &str) -> Result<Json, HttpResponse> {
+        body.parse()
+            .map_err(|e| HttpResponse::error(400, "bad_request", format!("некорректный JSON: {}", e)))
+    }
+
+    fn amount_field(json: &Json, field: &str) -> Result<Balance, String> {
+        let text = json
+            .get(field)
+            .and_then(Json::as_number_text)
+            .ok_or_else(|| format!("нет поля {}", field))?;
+        text.parse().map_err(|e| format!("поле {}: {}", field, e))
+    }
+
+    fn string_field(json: &Json, field: &str) -> Result<String, String> {
+        json.get(field)
+            .and_then(Json::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| format!("нет строкового поля {}", field))
+    }
+
+    fn create_account(storage: &mut Storage, body: &str) -> HttpResponse {
+        let json = match parse_body(body) {
+            Ok(json) => json,
+            Err(response) => return response,
+        };
+        let fields = string_field(&json, "name").and_then(|name| {
+            let balance = match json.get("balance") {
+                None | Some(Json::Null) => Balance::ZERO,
+                Some(_) => amount_field(&json, "balance")?,
+            };
+            Ok((name, balance))
+        });
+        let (name, balance) = match fields {
+            Ok(fields) => fields,
+            Err(message) => return HttpResponse::error(400, "bad_request", message),
+        };
+        match storage.open_account(name.clone(), balance) {
+            Ok(()) => HttpResponse::new(201, account_json(&name, balance)),
+            Err(e) => storage_error(&e),
+        }
+    }
+
+    /// Транзакция из тела запроса:
+    /// `{"type": "deposit" | "withdraw", "account", "amount"}`,
+    /// `{"type": "transfer", "from", "to", "amount"}` или
+    /// `{"type": "batch", "steps": [транзакция, ...]}`
+    pub fn parse_transaction(json: &Json) -> Result<Box<dyn Transaction>, String> {
+        let kind = json.get("type").and_then(Json::as_str).ok_or("нет поля type")?;
+        let tx: Box<dyn Transaction> = match kind {
+            "deposit" => Box::new(Deposit { account: string_field(json, "account")?, amount: amount_field(json, "amount")? }),
+            "withdraw" => Box::new(Withdraw { account: string_field(json, "account")?, amount: amount_field(json, "amount")? }),
+            "transfer" => Box::new(Transfer {
+                from: string_field(json, "from")?,
+                to: string_field(json, "to")?,
+                amount: amount_field(json, "amount")?,
+            }),
+            "batch" => {
+                let steps = json.get("steps").and_then(Json::as_array).ok_or("нет массива steps")?;
+                let steps = steps
+                    .iter()
+                    .enumerate()
+                    .map(|(index, step)| parse_transaction(step).map_err(|e| format!("steps[{}]: {}", index, e)))
+                    .collect::<Result<Batch, String>>()?;
+                Box::new(steps)
+            }
+            _ => return Err(format!("неизвестный type: {}", kind)),
+        };
+        Ok(tx)
+    }
+
+    fn commit_transaction(storage: &mut Storage, request: &Request) -> HttpResponse {
+        let tx = match parse_body(&request.body).and_then(|json| {
+            parse_transaction(&json).map_err(|message| HttpResponse::error(400, "bad_request", message))
+        }) {
+            Ok(tx) => tx,
+            Err(response) => return response,
+        };
+
+        let result = match request.header("Idempotency-Key") {
+            Some(key) => storage.commit_keyed(key, &tx),
+            None => storage.commit(&tx),
+        };
+        if let Err(e) = result {
+            return tx_error(&e);
+        }
+
+        // Балансы всех счетов, которых коснулась транзакция
+        let mut names: Vec<&str> = Vec::new();
+        let ops = tx.operations();
+        for op in &ops {
+            match op {
+                Operation::Deposit(tx) => names.push(&tx.account),
+                Operation::Withdraw(tx) => names.push(&tx.account),
+                Operation::Transfer(tx) => names.extend([tx.from.as_str(), tx.to.as_str()]),
+            }
+        }
+        names.sort_unstable();
+        names.dedup();
+        let balances = names
+            .into_iter()
+            .filter_map(|name| storage.get_balance(&name.to_string()).map(|balance| (name, Json::string(balance.to_string()))));
+        HttpResponse::new(200, Json::object([("status", Json::string("applied")), ("balances", Json::object(balances))]))
+    }
+
+    /// Код статуса и машинный код ошибки хранилища
+    fn storage_status(e: &StorageError) -> (u16, &'static str) {
+        match e {
+            StorageError::NotFound(_) => (404, "account_not_found"),
+            StorageError::AlreadyExists(_) => (409, "account_exists"),
+            StorageError::InvalidName(_) => (400, "invalid_name"),
+            StorageError::InsufficientFunds { .. } => (422, "insufficient_funds"),
+            StorageError::InvalidAmount(_) => (400, "invalid_amount"),
+            StorageError::Overflow => (422, "overflow"),
+            StorageError::InvalidKey(_) => (400, "invalid_key"),
+            StorageError::KeyConflict(_) => (409, "key_conflict"),
+            StorageError::Irreversible { .. } => (409, "irreversible"),
+            StorageError::ScheduleNotFound(_) => (404, "schedule_not_found"),
+            StorageError::HoldNotFound(_) => (404, "hold_not_found"),
+            StorageError::Unsupported(_) => (409, "unsupported"),
+            StorageError::Io(_)
+            | StorageError::Parse { .. }
+            | StorageError::Journal { .. }
+            | StorageError::Unbalanced(_)
+            | StorageError::Locked(_) => (500, "internal"),
+        }
+    }
+
+    fn storage_error(e: &StorageError) -> HttpResponse {
+        let (status, code) = storage_status(e);
+        HttpResponse::error(status, code, e.to_string())
+    }
+
+    /// Ошибка транзакции; для пакета добавляется `"step"` — индекс шага в `steps`
+    fn tx_error(e: &TxError) -> HttpResponse {
+        let (status, code) = match e.root() {
+            TxError::InsufficientFunds { .. } => (422, "insufficient_funds"),
+            TxError::InvalidAccount => (404, "account_not_found"),
+            TxError::InvalidAmount => (400, "invalid_amount"),
+            TxError::Overflow => (422, "overflow"),
+            TxError::Storage(e) => storage_status(e),
+            TxError::Step { .. } => (500, "internal"),
+        };
+        let mut response = HttpResponse::error(status, code, e.to_string());
+        if let (TxError::Step { index, .. }, Json::Object(fields)) = (e, &mut response.body) {
+            fields.push(("step".to_string(), Json::Number(index.to_string())));
+        }
+        if let (TxError::InsufficientFunds { available, requested }, Json::Object(fields)) = (e.root(), &mut response.body) {
+            fields.push(("available".to_string(), Json::string(available.to_string())));
+            fields.push(("requested".to_string(), Json::string(requested.to_string())));
+        }
+        response
+    }
+
+    /// Обслуживает одно соединение: один запрос — один ответ, затем соединение закрывается.
+    /// Возвращает true, если запрос изменял хранилище (не GET) и получил успешный ответ
+    pub fn serve_http(stream: TcpStream, storage: &Mutex<Storage>) -> io::Result<bool> {
+        let reader = BufReader::new(stream.try_clone()?);
+        let (response, changed) = match read_request(reader) {
+            Ok(Some(request)) => {
+                let response = route(&mut storage.lock().unwrap_or_else(PoisonError::into_inner), &request);
+                let changed = request.method != "GET" && (200..300).contains(&response.status);
+                (response, changed)
+            }
+            Ok(None) => return Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::OutOfMemory => (HttpResponse::error(413, "payload_too_large", e.to_string()), false),
+            Err(e) if e.kind() == io::ErrorKind::FileTooLarge => (HttpResponse::error(431, "headers_too_large", e.to_string()), false),
+            Err(e) => (HttpResponse::error(400, "bad_request", e.to_string()), false),
+        };
+        response.write_to(&stream)?;
+        Ok(changed)
+    }
+}
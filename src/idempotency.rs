@@ -0,0 +1,148 @@
+pub mod idempotency {
+    use std::collections::HashMap;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+    use crate::persist::persist;
+    use crate::{Balance, Operation, TxError};
+
+    /// Итог транзакции, запомненный под ключом идемпотентности.
+    ///
+    /// Запоминаются только окончательные итоги: транзакция применена или отклонена
+    /// по бизнес-причине. Сбой хранилища (например, ошибка записи журнала) итогом
+    /// не считается — такую транзакцию можно безопасно повторить с тем же ключом
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Outcome {
+        Applied,
+        InsufficientFunds { available: Balance, requested: Balance },
+        InvalidAccount,
+        InvalidAmount,
+        Overflow,
+    }
+
+    impl Outcome {
+        /// Итог для результата транзакции; None — результат не окончательный.
+        /// Для пакета запоминается только причина отказа, без номера шага
+        pub fn of(result: &Result<(), TxError>) -> Option<Outcome> {
+            let Err(e) = result else {
+                return Some(Outcome::Applied);
+            };
+            match e.root() {
+                TxError::InsufficientFunds { available, requested } => {
+                    Some(Outcome::InsufficientFunds { available: *available, requested: *requested })
+                }
+                TxError::InvalidAccount => Some(Outcome::InvalidAccount),
+                TxError::InvalidAmount => Some(Outcome::InvalidAmount),
+                TxError::Overflow => Some(Outcome::Overflow),
+                TxError::Storage(_) | TxError::Step { .. } => None,
+            }
+        }
+
+        /// Тот же результат, который вернула транзакция в первый раз
+        pub fn to_result(self) -> Result<(), TxError> {
+            match self {
+                Outcome::Applied => Ok(()),
+                Outcome::InsufficientFunds { available, requested } => Err(TxError::InsufficientFunds { available, requested }),
+                Outcome::InvalidAccount => Err(TxError::InvalidAccount),
+                Outcome::InvalidAmount => Err(TxError::InvalidAmount),
+                Outcome::Overflow => Err(TxError::Overflow),
+            }
+        }
+    }
+
+    /// Текстовый вид: `applied`, `insufficient_funds:<доступно>:<требуется>`, `invalid_account`, ...
+    impl Display for Outcome {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                Outcome::Applied => "applied",
+                Outcome::InsufficientFunds { available, requested } => {
+                    return write!(f, "insufficient_funds:{}:{}", available, requested);
+                }
+                Outcome::InvalidAccount => "invalid_account",
+                Outcome::InvalidAmount => "invalid_amount",
+                Outcome::Overflow => "overflow",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl FromStr for Outcome {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "applied" => Ok(Outcome::Applied),
+                "invalid_account" => Ok(Outcome::InvalidAccount),
+                "invalid_amount" => Ok(Outcome::InvalidAmount),
+                "overflow" => Ok(Outcome::Overflow),
+                _ => {
+                    let amounts = s.strip_prefix("insufficient_funds:").and_then(|amounts| amounts.split_once(':'));
+                    let Some((available, requested)) = amounts else {
+                        return Err(format!("неизвестный итог транзакции: {}", s));
+                    };
+                    let money = |amount: &str| amount.parse::<Balance>().map_err(|e| e.to_string());
+                    Ok(Outcome::InsufficientFunds { available: money(available)?, requested: money(requested)? })
+                }
+            }
+        }
+    }
+
+    /// Ключ допустим, если он непустой, не длиннее 64 символов и состоит из латиницы,
+    /// цифр и `-_.:` — так он без экранирования пишется и в журнал, и в снапшот
+    pub fn is_valid_key(key: &str) -> bool {
+        !key.is_empty()
+            && key.len() <= 64
+            && key.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+    }
+
+    /// Отпечаток транзакции: FNV-1a от её операций в том виде, в каком они пишутся
+    /// в журнал. Так повтор с тем же ключом можно отличить от другой транзакции
+    pub fn fingerprint(ops: &[Operation]) -> u64 {
+        persist::checksum(ops.iter().map(Operation::to_string).collect::<Vec<_>>().join(";").as_bytes())
+    }
+
+    /// Обработанные ключи идемпотентности, итоги их транзакций и отпечатки
+    #[derive(Debug, Clone, Default)]
+    pub struct KeyStore {
+        outcomes: HashMap<String, (Outcome, u64)>,
+    }
+
+    impl KeyStore {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(&self, key: &str) -> Option<Outcome> {
+            self.outcomes.get(key).map(|(outcome, _)| *outcome)
+        }
+
+        /// Ключ ещё не использован или использован для транзакции с тем же отпечатком
+        pub fn matches(&self, key: &str, fingerprint: u64) -> bool {
+            self.outcomes.get(key).is_none_or(|(_, stored)| *stored == fingerprint)
+        }
+
+        pub fn insert(&mut self, key: String, outcome: Outcome, fingerprint: u64) {
+            self.outcomes.insert(key, (outcome, fingerprint));
+        }
+
+        /// Забывает ключ — только при откате транзакции, которая его записала
+        pub(crate) fn remove(&mut self, key: &str) {
+            self.outcomes.remove(key);
+        }
+
+        pub fn len(&self) -> usize {
+            self.outcomes.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.outcomes.is_empty()
+        }
+
+        /// Ключи в порядке сортировки — чтобы снапшот не зависел от порядка в HashMap
+        pub fn iter(&self) -> impl Iterator<Item = (&str, Outcome, u64)> {
+            let mut keys: Vec<_> =
+                self.outcomes.iter().map(|(key, (outcome, fingerprint))| (key.as_str(), *outcome, *fingerprint)).collect();
+            keys.sort_unstable_by_key(|(key, _, _)| *key);
+            keys.into_iter()
+        }
+    }
+}
@@ -0,0 +1,444 @@
+pub mod interest {
+    use std::collections::BTreeMap;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+    use crate::calendar::calendar::{self, SECONDS_PER_DAY, Timestamp};
+    use crate::{Balance, Name, StorageError};
+
+    /// Начисленные проценты копятся в миллиардных долях копейки,
+    /// чтобы ежедневные начисления не терялись при округлении
+    pub const ACCRUAL_UNITS_PER_MINOR: i128 = 1_000_000_000;
+
+    /// Годовая ставка в миллионных долях: 4.75% — это 47 500
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Rate(i64);
+
+    impl Rate {
+        pub const fn from_ppm(ppm: i64) -> Rate {
+            Rate(ppm)
+        }
+
+        pub const fn ppm(self) -> i64 {
+            self.0
+        }
+    }
+
+    /// Текстовый вид — проценты: `4.75%`
+    impl Display for Rate {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let sign = if self.0 < 0 { "-" } else { "" };
+            let abs = self.0.unsigned_abs();
+            let fraction = format!("{:04}", abs % 10_000);
+            let fraction = fraction.trim_end_matches('0');
+            if fraction.is_empty() {
+                write!(f, "{}{}%", sign, abs / 10_000)
+            } else {
+                write!(f, "{}{}.{}%", sign, abs / 10_000, fraction)
+            }
+        }
+    }
+
+    /// Разбирает проценты с необязательным знаком `%`: `4.75%`, `5`, `-0.5%`.
+    /// Больше четырёх знаков после точки не поддерживается
+    impl FromStr for Rate {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = || format!("некорректная ставка: {}", s);
+            let digits = s.strip_suffix('%').unwrap_or(s);
+            let (negative, digits) = match digits.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, digits),
+            };
+            let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+            if whole.is_empty()
+                || fraction.len() > 4
+                || !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit())
+            {
+                return Err(invalid());
+            }
+            let whole: i64 = whole.parse().map_err(|_| invalid())?;
+            let fraction: i64 = format!("{:0<4}", fraction).parse().map_err(|_| invalid())?;
+            let ppm = whole
+                .checked_mul(10_000)
+                .and_then(|ppm| ppm.checked_add(fraction))
+                .ok_or_else(invalid)?;
+            Ok(Rate(if negative { -ppm } else { ppm }))
+        }
+    }
+
+    /// Соглашение о числе дней в году при дневном начислении
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DayCount {
+        /// Actual/365 Fixed: в году всегда 365 дней
+        Act365,
+        /// Actual/360
+        Act360,
+        /// Actual/Actual: 365 или 366 дней в зависимости от года
+        ActAct,
+    }
+
+    impl DayCount {
+        /// Дней в году, к которому относится день `day`
+        pub fn days_in_year(self, day: Timestamp) -> i128 {
+            match self {
+                DayCount::Act365 => 365,
+                DayCount::Act360 => 360,
+                DayCount::ActAct => {
+                    let (year, _, _) = calendar::civil_from_days((day / SECONDS_PER_DAY) as i64);
+                    if calendar::is_leap_year(year) { 366 } else { 365 }
+                }
+            }
+        }
+    }
+
+    impl Display for DayCount {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                DayCount::Act365 => "act/365",
+                DayCount::Act360 => "act/360",
+                DayCount::ActAct => "act/act",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl FromStr for DayCount {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "act/365" => Ok(DayCount::Act365),
+                "act/360" => Ok(DayCount::Act360),
+                "act/act" => Ok(DayCount::ActAct),
+                _ => Err(format!("неизвестное соглашение о днях: {}", s)),
+            }
+        }
+    }
+
+    /// Период капитализации или выплаты; периоды заканчиваются в конце
+    /// дня, месяца, квартала или года по календарю
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Period {
+        Daily,
+        Monthly,
+        Quarterly,
+        Yearly,
+    }
+
+    impl Period {
+        /// Заканчивается ли период вместе с днём `day`
+        pub fn ends_on(self, day: Timestamp) -> bool {
+            let (_, month, day) = calendar::civil_from_days((day / SECONDS_PER_DAY) as i64 + 1);
+            match self {
+                Period::Daily => true,
+                Period::Monthly => day == 1,
+                Period::Quarterly => day == 1 && month % 3 == 1,
+                Period::Yearly => day == 1 && month == 1,
+            }
+        }
+    }
+
+    impl Display for Period {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                Period::Daily => "daily",
+                Period::Monthly => "monthly",
+                Period::Quarterly => "quarterly",
+                Period::Yearly => "yearly",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl FromStr for Period {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "daily" => Ok(Period::Daily),
+                "monthly" => Ok(Period::Monthly),
+                "quarterly" => Ok(Period::Quarterly),
+                "yearly" => Ok(Period::Yearly),
+                _ => Err(format!("неизвестный период: {}", s)),
+            }
+        }
+    }
+
+    /// Как округлять проценты до копеек при выплате.
+    /// Остаток от округления не теряется, а переходит в следующий период
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Rounding {
+        /// К нулю
+        Down,
+        /// Половина — от нуля
+        HalfUp,
+        /// Половина — к чётному (банковское округление)
+        HalfEven,
+    }
+
+    impl Rounding {
+        /// Округляет сумму в долях начисления до целых копеек
+        pub fn round(self, units: i128) -> i128 {
+            let whole = units / ACCRUAL_UNITS_PER_MINOR;
+            let rest = units % ACCRUAL_UNITS_PER_MINOR;
+            let half = ACCRUAL_UNITS_PER_MINOR / 2;
+            let away = match self {
+                Rounding::Down => false,
+                Rounding::HalfUp => rest.abs() >= half,
+                Rounding::HalfEven => rest.abs() > half || (rest.abs() == half && whole % 2 != 0),
+            };
+            if away { whole + units.signum() } else { whole }
+        }
+    }
+
+    impl Display for Rounding {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                Rounding::Down => "down",
+                Rounding::HalfUp => "half_up",
+                Rounding::HalfEven => "half_even",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl FromStr for Rounding {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "down" => Ok(Rounding::Down),
+                "half_up" => Ok(Rounding::HalfUp),
+                "half_even" => Ok(Rounding::HalfEven),
+                _ => Err(format!("неизвестное округление: {}", s)),
+            }
+        }
+    }
+
+    /// Процентный продукт счёта
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InterestProduct {
+        /// Годовая ставка; отрицательная ставка — плата за остаток
+        pub rate: Rate,
+        pub day_count: DayCount,
+        /// Как часто начисленное добавляется к базе начисления; None — простые проценты
+        pub compounding: Option<Period>,
+        /// Как часто начисленное выплачивается на счёт
+        pub posting: Period,
+        pub rounding: Rounding,
+        /// Годовая ставка на отрицательный остаток (овердрафт); None — та же, что `rate`
+        pub overdraft: Option<Rate>,
+    }
+
+    /// Текстовый вид: `rate,day_count,compounding,posting,rounding[,overdraft]`,
+    /// например `4.75%,act/365,simple,monthly,half_even,18%`. Округление можно
+    /// не указывать, если не указана и ставка овердрафта
+    impl Display for InterestProduct {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let compounding = self.compounding.map_or("simple".to_string(), |period| period.to_string());
+            write!(f, "{},{},{},{},{}", self.rate, self.day_count, compounding, self.posting, self.rounding)?;
+            if let Some(overdraft) = self.overdraft {
+                write!(f, ",{}", overdraft)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl FromStr for InterestProduct {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let fields: Vec<&str> = s.split(',').collect();
+            let (fields, rounding, overdraft) = match fields.as_slice() {
+                [fields @ .., rounding, overdraft] if fields.len() == 4 => (fields, rounding.parse()?, Some(overdraft.parse()?)),
+                [fields @ .., rounding] if fields.len() == 4 => (fields, rounding.parse()?, None),
+                fields => (fields, Rounding::HalfEven, None),
+            };
+            let [rate, day_count, compounding, posting] = fields else {
+                return Err(format!("ожидается rate,day_count,compounding,posting[,rounding[,overdraft]]: {}", s));
+            };
+            Ok(InterestProduct {
+                rate: rate.parse()?,
+                day_count: day_count.parse()?,
+                compounding: if *compounding == "simple" { None } else { Some(compounding.parse()?) },
+                posting: posting.parse()?,
+                rounding,
+                overdraft,
+            })
+        }
+    }
+
+    /// Состояние начисления по одному счёту
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Accrual {
+        pub product: InterestProduct,
+        /// Первый ещё не начисленный день (начало дня)
+        pub next_day: Timestamp,
+        /// Начислено с последней капитализации, в долях [`ACCRUAL_UNITS_PER_MINOR`]
+        pub accrued: i128,
+        /// Капитализировано, но ещё не выплачено — на это тоже начисляются проценты
+        pub capitalized: i128,
+    }
+
+    impl Accrual {
+        pub fn new(product: InterestProduct, start: Timestamp) -> Self {
+            Accrual { product, next_day: start / SECONDS_PER_DAY * SECONDS_PER_DAY, accrued: 0, capitalized: 0 }
+        }
+
+        /// Всё начисленное и не выплаченное, в долях копейки
+        pub fn unposted_units(&self) -> i128 {
+            self.capitalized + self.accrued
+        }
+
+        /// Не выплаченное, округлённое по правилам продукта
+        pub fn unposted(&self) -> Result<Balance, StorageError> {
+            let minor = self.product.rounding.round(self.unposted_units());
+            i64::try_from(minor).map(Balance::from_minor).map_err(|_| StorageError::Overflow)
+        }
+
+        /// Начисляет проценты за день `next_day` на остаток на конец этого дня.
+        /// Если в этот день заканчивается период выплаты, возвращает сумму к выплате
+        /// в копейках; остаток от округления остаётся начисленным
+        pub fn accrue_day(&mut self, end_of_day: Balance) -> Result<Option<i64>, StorageError> {
+            let day = self.next_day;
+            let base = i128::from(end_of_day.minor()) * ACCRUAL_UNITS_PER_MINOR + self.capitalized;
+            let rate = match self.product.overdraft {
+                Some(overdraft) if base < 0 => overdraft,
+                _ => self.product.rate,
+            };
+            // Деление округляет к нулю: теряется меньше миллиардной доли копейки в день
+            let daily = base * i128::from(rate.ppm()) / (1_000_000 * self.product.day_count.days_in_year(day));
+            self.accrued += daily;
+            self.next_day += SECONDS_PER_DAY;
+
+            if self.product.compounding.is_some_and(|period| period.ends_on(day)) {
+                self.capitalized += std::mem::take(&mut self.accrued);
+            }
+            if !self.product.posting.ends_on(day) {
+                return Ok(None);
+            }
+            let total = self.unposted_units();
+            let posted = self.product.rounding.round(total);
+            self.capitalized = 0;
+            self.accrued = total - posted * ACCRUAL_UNITS_PER_MINOR;
+            i64::try_from(posted).map(Some).map_err(|_| StorageError::Overflow)
+        }
+    }
+
+    /// Текстовый вид: `next_day,accrued,capitalized,<продукт>`
+    impl Display for Accrual {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{},{},{},{}", self.next_day, self.accrued, self.capitalized, self.product)
+        }
+    }
+
+    impl FromStr for Accrual {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let fields: Vec<&str> = s.splitn(4, ',').collect();
+            let [next_day, accrued, capitalized, product] = fields.as_slice() else {
+                return Err(format!("ожидается next_day,accrued,capitalized,<продукт>: {}", s));
+            };
+            let number = |field: &str| field.parse::<i128>().map_err(|_| format!("некорректное число: {}", field));
+            Ok(Accrual {
+                product: product.parse()?,
+                next_day: next_day.parse().map_err(|_| format!("некорректное число: {}", next_day))?,
+                accrued: number(accrued)?,
+                capitalized: number(capitalized)?,
+            })
+        }
+    }
+
+    /// Строка отчёта о начисленных, но не выплаченных процентах
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct AccrualLine {
+        pub account: Name,
+        pub product: InterestProduct,
+        /// Проценты начислены по день, предшествующий этому моменту
+        pub accrued_through: Timestamp,
+        /// Начислено и не выплачено, с округлением по правилам продукта
+        pub unposted: Balance,
+    }
+
+    /// Процентные продукты счетов и состояние начисления по ним
+    #[derive(Debug, Clone, Default)]
+    pub struct InterestBook {
+        accounts: BTreeMap<Name, Accrual>,
+    }
+
+    impl InterestBook {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(&self, account: &str) -> Option<&Accrual> {
+            self.accounts.get(account)
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.accounts.is_empty()
+        }
+
+        /// Счета с продуктами по имени
+        pub fn iter(&self) -> impl Iterator<Item = (&Name, &Accrual)> {
+            self.accounts.iter()
+        }
+
+        /// Отчёт по всем счетам с продуктами, по имени счёта
+        pub fn report(&self) -> Result<Vec<AccrualLine>, StorageError> {
+            self.accounts
+                .iter()
+                .map(|(account, accrual)| {
+                    Ok(AccrualLine {
+                        account: account.clone(),
+                        product: accrual.product,
+                        accrued_through: accrual.next_day,
+                        unposted: accrual.unposted()?,
+                    })
+                })
+                .collect()
+        }
+
+        /// Назначает продукт. У счёта, где продукт уже был, меняются только условия:
+        /// начисленное сохраняется
+        pub(crate) fn set(&mut self, account: &str, product: InterestProduct, start: Timestamp) {
+            self.accounts
+                .entry(account.to_string())
+                .and_modify(|accrual| accrual.product = product)
+                .or_insert_with(|| Accrual::new(product, start));
+        }
+
+        pub(crate) fn insert(&mut self, account: Name, accrual: Accrual) {
+            self.accounts.insert(account, accrual);
+        }
+
+        pub(crate) fn remove(&mut self, account: &str) -> Option<Accrual> {
+            self.accounts.remove(account)
+        }
+
+        /// Возвращает начисление счёта в прежнее состояние при откате
+        pub(crate) fn restore(&mut self, account: &str, accrual: Option<Accrual>) {
+            match accrual {
+                Some(accrual) => self.insert(account.to_string(), accrual),
+                None => {
+                    self.remove(account);
+                }
+            }
+        }
+
+        pub(crate) fn get_mut(&mut self, account: &str) -> Option<&mut Accrual> {
+            self.accounts.get_mut(account)
+        }
+
+        /// Счета, у которых есть дни до `through`, ещё не начисленные
+        pub(crate) fn behind(&self, through: Timestamp) -> Vec<Name> {
+            self.accounts
+                .iter()
+                .filter(|(_, accrual)| accrual.next_day < through)
+                .map(|(account, _)| account.clone())
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,358 @@
+pub mod journal {
+    use std::fmt::{Display, Formatter};
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, BufRead, Write};
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+    use crate::calendar::calendar::Timestamp;
+    use crate::fees::fees::FeeSchedule;
+    use crate::holds::holds::Hold;
+    use crate::idempotency::idempotency::{self, Outcome};
+    use crate::interest::interest::InterestProduct;
+    use crate::schedule::schedule::{RunRecord, Schedule};
+    use crate::persist::persist;
+    use crate::{Balance, Name, Operation, StorageError};
+
+    /// Запись журнала — одно подтверждённое изменение Storage
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Record {
+        /// Открыт счёт с начальным балансом
+        Open { name: Name, balance: Balance },
+        /// Счёт закрыт
+        Close { name: Name },
+        /// Транзакция (одна операция или целая цепочка), применённая атомарно
+        Commit(Vec<Operation>),
+        /// Транзакция с ключом идемпотентности и её итогом. Операции применяются
+        /// при восстановлении, только если итог — `applied`
+        Keyed { key: String, outcome: Outcome, ops: Vec<Operation> },
+        /// Пакет, в котором у каждой операции может быть свой ключ идемпотентности
+        /// (`keys[i]` — ключ `ops[i]`). Записывается только применённым
+        KeyedSteps { keys: Vec<Option<String>>, ops: Vec<Operation> },
+        /// Компенсирующая транзакция, отменившая изменение истории с номером `original`
+        Reverse { original: u64, ops: Vec<Operation> },
+        /// Добавлен регулярный платёж
+        Schedule(Schedule),
+        /// Регулярный платёж отменён
+        Unschedule { id: u64 },
+        /// Попытка провести регулярный платёж; перевод применяется при восстановлении,
+        /// только если итог — `applied`
+        Run(RunRecord),
+        /// Счёту назначен процентный продукт; начисление — с дня `start`
+        Interest { account: Name, product: InterestProduct, start: Timestamp },
+        /// Процентный продукт счёта отключён
+        InterestOff { account: Name },
+        /// Проценты начислены по всем счетам за дни до `through`
+        Accrue { through: Timestamp },
+        /// Сменился тариф комиссий; действует для всех следующих записей
+        Fees(FeeSchedule),
+        /// Счёту установлен лимит овердрафта; ноль — лимит снят
+        Overdraft { account: Name, limit: Balance },
+        /// Поставлено удержание
+        Hold(Hold),
+        /// Из удержания `id` списано `amount`: снятием или переводом на `to`
+        Capture { id: u64, amount: Balance, to: Option<Name> },
+        /// Удержание снято — вручную или по истечении срока
+        Release { id: u64 },
+    }
+
+    fn write_ops(f: &mut Formatter<'_>, ops: &[Operation]) -> std::fmt::Result {
+        for (i, op) in ops.iter().enumerate() {
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            write!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+
+    fn parse_ops(s: &str) -> Result<Vec<Operation>, String> {
+        s.split(';').map(str::parse).collect()
+    }
+
+    impl Display for Record {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Record::Open { name, balance } => write!(f, "open {} {}", name, balance),
+                Record::Close { name } => write!(f, "close {}", name),
+                Record::Commit(ops) => {
+                    write!(f, "commit ")?;
+                    write_ops(f, ops)
+                }
+                Record::Keyed { key, outcome, ops } => {
+                    write!(f, "keyed {} {} ", key, outcome)?;
+                    write_ops(f, ops)
+                }
+                Record::KeyedSteps { keys, ops } => {
+                    let keys: Vec<&str> = keys.iter().map(|key| key.as_deref().unwrap_or("")).collect();
+                    write!(f, "keyed_steps {} ", keys.join(","))?;
+                    write_ops(f, ops)
+                }
+                Record::Reverse { original, ops } => {
+                    write!(f, "reverse {} ", original)?;
+                    write_ops(f, ops)
+                }
+                Record::Schedule(schedule) => write!(f, "schedule {}", schedule),
+                Record::Unschedule { id } => write!(f, "unschedule {}", id),
+                Record::Run(run) => write!(f, "run {}", run),
+                Record::Interest { account, product, start } => write!(f, "interest {} {} {}", account, start, product),
+                Record::InterestOff { account } => write!(f, "interest_off {}", account),
+                Record::Accrue { through } => write!(f, "accrue {}", through),
+                Record::Fees(schedule) => write!(f, "fees {}", schedule),
+                Record::Overdraft { account, limit } => write!(f, "overdraft {} {}", account, limit),
+                Record::Hold(hold) => write!(f, "hold {}", hold),
+                Record::Capture { id, amount, to: Some(to) } => write!(f, "capture {} {} {}", id, amount, to),
+                Record::Capture { id, amount, to: None } => write!(f, "capture {} {}", id, amount),
+                Record::Release { id } => write!(f, "release {}", id),
+            }
+        }
+    }
+
+    impl FromStr for Record {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (kind, rest) = s.split_once(' ').unwrap_or((s, ""));
+            match kind {
+                "open" => {
+                    let (name, balance) = rest
+                        .split_once(' ')
+                        .ok_or_else(|| format!("ожидается \"open <name> <balance>\": {}", s))?;
+                    let balance = balance
+                        .parse()
+                        .map_err(|_| format!("некорректный баланс: {}", balance))?;
+                    Ok(Record::Open { name: name.to_string(), balance })
+                }
+                "close" if !rest.is_empty() => Ok(Record::Close { name: rest.to_string() }),
+                "commit" => parse_ops(rest).map(Record::Commit),
+                "keyed" => {
+                    let mut parts = rest.splitn(3, ' ');
+                    let (Some(key), Some(outcome), Some(ops)) = (parts.next(), parts.next(), parts.next()) else {
+                        return Err(format!("ожидается \"keyed <key> <outcome> <ops>\": {}", s));
+                    };
+                    if !idempotency::is_valid_key(key) {
+                        return Err(format!("некорректный ключ идемпотентности: {}", key));
+                    }
+                    Ok(Record::Keyed { key: key.to_string(), outcome: outcome.parse()?, ops: parse_ops(ops)? })
+                }
+                "keyed_steps" => {
+                    let (keys, ops) = rest
+                        .split_once(' ')
+                        .ok_or_else(|| format!("ожидается \"keyed_steps <keys> <ops>\": {}", s))?;
+                    let keys: Vec<Option<String>> =
+                        keys.split(',').map(|key| (!key.is_empty()).then(|| key.to_string())).collect();
+                    if let Some(key) = keys.iter().flatten().find(|key| !idempotency::is_valid_key(key)) {
+                        return Err(format!("некорректный ключ идемпотентности: {}", key));
+                    }
+                    let ops = parse_ops(ops)?;
+                    if keys.len() != ops.len() {
+                        return Err(format!("ключей {}, а операций {}: {}", keys.len(), ops.len(), s));
+                    }
+                    Ok(Record::KeyedSteps { keys, ops })
+                }
+                "reverse" => {
+                    let (original, ops) = rest
+                        .split_once(' ')
+                        .ok_or_else(|| format!("ожидается \"reverse <seq> <ops>\": {}", s))?;
+                    let original = original
+                        .parse()
+                        .map_err(|_| format!("некорректный номер изменения: {}", original))?;
+                    Ok(Record::Reverse { original, ops: parse_ops(ops)? })
+                }
+                "schedule" => rest.parse().map(Record::Schedule),
+                "unschedule" => rest
+                    .parse()
+                    .map(|id| Record::Unschedule { id })
+                    .map_err(|_| format!("некорректный номер платежа: {}", rest)),
+                "run" => rest.parse().map(Record::Run),
+                "interest" => {
+                    let mut parts = rest.splitn(3, ' ');
+                    let (Some(account), Some(start), Some(product)) = (parts.next(), parts.next(), parts.next()) else {
+                        return Err(format!("ожидается \"interest <account> <start> <product>\": {}", s));
+                    };
+                    let start = start.parse().map_err(|_| format!("некорректное время: {}", start))?;
+                    Ok(Record::Interest { account: account.to_string(), product: product.parse()?, start })
+                }
+                "interest_off" if !rest.is_empty() => Ok(Record::InterestOff { account: rest.to_string() }),
+                "accrue" => rest
+                    .parse()
+                    .map(|through| Record::Accrue { through })
+                    .map_err(|_| format!("некорректное время: {}", rest)),
+                "overdraft" => {
+                    let (account, limit) = rest
+                        .split_once(' ')
+                        .ok_or_else(|| format!("ожидается \"overdraft <account> <limit>\": {}", s))?;
+                    let limit = limit.parse().map_err(|_| format!("некорректный лимит: {}", limit))?;
+                    Ok(Record::Overdraft { account: account.to_string(), limit })
+                }
+                "hold" => rest.parse().map(Record::Hold),
+                "capture" => {
+                    let parts: Vec<&str> = rest.split(' ').collect();
+                    let (id, amount, to) = match parts.as_slice() {
+                        [id, amount] => (id, amount, None),
+                        [id, amount, to] => (id, amount, Some(to.to_string())),
+                        _ => return Err(format!("ожидается \"capture <id> <amount> [to]\": {}", s)),
+                    };
+                    let id = id.parse().map_err(|_| format!("некорректный номер удержания: {}", id))?;
+                    let amount = amount.parse().map_err(|_| format!("некорректная сумма: {}", amount))?;
+                    Ok(Record::Capture { id, amount, to })
+                }
+                "release" => rest
+                    .parse()
+                    .map(|id| Record::Release { id })
+                    .map_err(|_| format!("некорректный номер удержания: {}", rest)),
+                "fees" => rest.parse().map(Record::Fees).map_err(|(_, message)| message),
+                _ => Err(format!("неизвестная запись журнала: {}", s)),
+            }
+        }
+    }
+
+    /// Запись журнала вместе с её номером и временем применения
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct JournalEntry {
+        pub seq: u64,
+        pub timestamp: Timestamp,
+        pub record: Record,
+    }
+
+    /// Журнал упреждающей записи (write-ahead log).
+    ///
+    /// Каждая строка файла — `<seq> <checksum> <timestamp> <record>`. Запись дописывается
+    /// в конец и сбрасывается на диск до того, как изменение считается подтверждённым.
+    /// Оборванная последняя строка (сбой посреди записи) при чтении отбрасывается
+    pub struct Journal {
+        path: PathBuf,
+        /// Файл открывается лениво — при первой записи
+        file: Option<File>,
+        last_seq: u64,
+        /// Неудачную запись не удалось стереть: дописывать после неё нельзя
+        poisoned: bool,
+    }
+
+    impl Journal {
+        /// Путь к журналу для файла снапшота: `balance.csv` -> `balance.csv.journal`
+        pub fn path_for(snapshot: &Path) -> PathBuf {
+            persist::with_suffix(snapshot, ".journal")
+        }
+
+        /// Открывает журнал и читает все его записи по порядку
+        pub fn open(path: &Path) -> Result<(Journal, Vec<JournalEntry>), StorageError> {
+            let mut records = Vec::new();
+
+            if path.exists() {
+                let reader = io::BufReader::new(File::open(path)?);
+                let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+
+                for (index, line) in lines.iter().enumerate() {
+                    match parse_line(line) {
+                        Ok(entry) => records.push(entry),
+                        // Последняя строка могла не дописаться: она не была подтверждена
+                        Err(_) if index + 1 == lines.len() => {
+                            truncate_to(path, &lines[..index])?;
+                        }
+                        Err(message) => {
+                            return Err(StorageError::Parse { line: index + 1, message });
+                        }
+                    }
+                }
+            }
+
+            let last_seq = records.last().map(|entry: &JournalEntry| entry.seq).unwrap_or(0);
+            let journal = Journal {
+                path: path.to_path_buf(),
+                file: None,
+                last_seq,
+                poisoned: false,
+            };
+            Ok((journal, records))
+        }
+
+        /// Номер последней записанной записи (0 — журнал пуст)
+        pub fn last_seq(&self) -> u64 {
+            self.last_seq
+        }
+
+        /// Гарантирует, что следующая запись получит номер больше `seq`
+        pub fn continue_after(&mut self, seq: u64) {
+            self.last_seq = self.last_seq.max(seq);
+        }
+
+        /// Удаляет из журнала все записи с номерами не больше `seq` —
+        /// они уже учтены в снапшоте. Файл переписывается атомарно
+        pub fn truncate_through(&mut self, seq: u64) -> Result<(), StorageError> {
+            let (_, records) = Journal::open(&self.path)?;
+            let mut data = String::new();
+            for entry in records.iter().filter(|entry| entry.seq > seq) {
+                data.push_str(&format_line(entry));
+            }
+
+            // Старый дескриптор указывает на заменённый файл, следующая запись откроет новый
+            self.file = None;
+            persist::write_atomic(&self.path, data.as_bytes())?;
+            Ok(())
+        }
+
+        /// Дописывает запись и дожидается её сброса на диск. Возвращает номер записи.
+        ///
+        /// Если запись не удалась, её байты обрезаются: иначе целая строка проигралась бы
+        /// при загрузке, хотя изменение отменено, а обрывок посреди файла сделал бы журнал
+        /// нечитаемым. Если не удалось и обрезать, все следующие записи отклоняются
+        pub fn append(&mut self, timestamp: Timestamp, record: Record) -> Result<u64, StorageError> {
+            if self.poisoned {
+                return Err(io::Error::other(format!("журнал {} повреждён неудачной записью", self.path.display())).into());
+            }
+            let seq = self.last_seq + 1;
+            let line = format_line(&JournalEntry { seq, timestamp, record });
+
+            let file = match self.file.as_mut() {
+                Some(file) => file,
+                None => self
+                    .file
+                    .insert(OpenOptions::new().create(true).append(true).open(&self.path)?),
+            };
+            let len = file.metadata()?.len();
+            if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.sync_data()) {
+                self.poisoned = file.set_len(len).and_then(|_| file.sync_data()).is_err();
+                return Err(e.into());
+            }
+
+            self.last_seq = seq;
+            Ok(seq)
+        }
+    }
+
+    fn format_line(entry: &JournalEntry) -> String {
+        let payload = format!("{} {}", entry.timestamp, entry.record);
+        format!("{} {:016x} {}\n", entry.seq, persist::checksum(payload.as_bytes()), payload)
+    }
+
+    fn parse_line(line: &str) -> Result<JournalEntry, String> {
+        let mut parts = line.splitn(3, ' ');
+        let (Some(seq), Some(checksum), Some(payload)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(format!("неполная запись журнала: {}", line));
+        };
+
+        let seq: u64 = seq.parse().map_err(|_| format!("некорректный номер записи: {}", seq))?;
+        let checksum = u64::from_str_radix(checksum, 16)
+            .map_err(|_| format!("некорректная контрольная сумма: {}", checksum))?;
+        if checksum != persist::checksum(payload.as_bytes()) {
+            return Err(format!("контрольная сумма записи {} не совпадает", seq));
+        }
+
+        let (timestamp, record) = payload
+            .split_once(' ')
+            .ok_or_else(|| format!("неполная запись журнала: {}", line))?;
+        let timestamp = timestamp.parse().map_err(|_| format!("некорректное время: {}", timestamp))?;
+
+        Ok(JournalEntry { seq, timestamp, record: record.parse()? })
+    }
+
+    /// Переписывает журнал, оставляя только целые строки
+    fn truncate_to(path: &Path, lines: &[String]) -> io::Result<()> {
+        let mut data = String::new();
+        for line in lines {
+            data.push_str(line);
+            data.push('\n');
+        }
+        persist::write_atomic(path, data.as_bytes())
+    }
+}
@@ -0,0 +1,280 @@
+pub mod json {
+    use std::fmt::{Display, Formatter, Write};
+    use std::str::FromStr;
+
+    /// Значение JSON. Числа хранятся исходным текстом: суммы разбираются
+    /// в [`crate::Money`] без промежуточного f64 и потери копеек.
+    /// Поля объекта сохраняют порядок
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Json {
+        Null,
+        Bool(bool),
+        Number(String),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        /// Объект из пар «ключ — значение»
+        pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Json)>) -> Json {
+            Json::Object(fields.into_iter().map(|(key, value)| (key.into(), value)).collect())
+        }
+
+        pub fn string(s: impl Into<String>) -> Json {
+            Json::String(s.into())
+        }
+
+        /// Поле объекта; None, если это не объект или поля нет
+        pub fn get(&self, key: &str) -> Option<&Json> {
+            match self {
+                Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+                _ => None,
+            }
+        }
+
+        pub fn as_str(&self) -> Option<&str> {
+            match self {
+                Json::String(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        /// Текст числа или строки — так сумму можно передать и как `10.5`, и как `"10.50"`
+        pub fn as_number_text(&self) -> Option<&str> {
+            match self {
+                Json::Number(s) | Json::String(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        pub fn as_bool(&self) -> Option<bool> {
+            match self {
+                Json::Bool(b) => Some(*b),
+                _ => None,
+            }
+        }
+
+        pub fn as_array(&self) -> Option<&[Json]> {
+            match self {
+                Json::Array(items) => Some(items),
+                _ => None,
+            }
+        }
+    }
+
+    fn write_string(f: &mut Formatter<'_>, s: &str) -> std::fmt::Result {
+        f.write_char('"')?;
+        for c in s.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+
+    /// Компактная запись без пробелов
+    impl Display for Json {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Json::Null => f.write_str("null"),
+                Json::Bool(b) => write!(f, "{}", b),
+                Json::Number(n) => f.write_str(n),
+                Json::String(s) => write_string(f, s),
+                Json::Array(items) => {
+                    f.write_char('[')?;
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            f.write_char(',')?;
+                        }
+                        write!(f, "{}", item)?;
+                    }
+                    f.write_char(']')
+                }
+                Json::Object(fields) => {
+                    f.write_char('{')?;
+                    for (i, (key, value)) in fields.iter().enumerate() {
+                        if i > 0 {
+                            f.write_char(',')?;
+                        }
+                        write_string(f, key)?;
+                        write!(f, ":{}", value)?;
+                    }
+                    f.write_char('}')
+                }
+            }
+        }
+    }
+
+    /// Разбор JSON; ошибка содержит позицию (в символах, с нуля)
+    impl FromStr for Json {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut parser = Parser { chars: s.chars().collect(), pos: 0 };
+            let value = parser.value()?;
+            parser.skip_whitespace();
+            if parser.pos < parser.chars.len() {
+                return Err(parser.error("лишние символы после значения"));
+            }
+            Ok(value)
+        }
+    }
+
+    struct Parser {
+        chars: Vec<char>,
+        pos: usize,
+    }
+
+    impl Parser {
+        fn error(&self, message: &str) -> String {
+            format!("позиция {}: {}", self.pos, message)
+        }
+
+        fn peek(&self) -> Option<char> {
+            self.chars.get(self.pos).copied()
+        }
+
+        fn skip_whitespace(&mut self) {
+            while self.peek().is_some_and(char::is_whitespace) {
+                self.pos += 1;
+            }
+        }
+
+        fn expect(&mut self, c: char) -> Result<(), String> {
+            self.skip_whitespace();
+            if self.peek() == Some(c) {
+                self.pos += 1;
+                Ok(())
+            } else {
+                Err(self.error(&format!("ожидается '{}'", c)))
+            }
+        }
+
+        fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+            let end = self.pos + word.chars().count();
+            if self.chars.get(self.pos..end).is_some_and(|chars| chars.iter().copied().eq(word.chars())) {
+                self.pos = end;
+                Ok(value)
+            } else {
+                Err(self.error("неизвестное значение"))
+            }
+        }
+
+        fn value(&mut self) -> Result<Json, String> {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('n') => self.literal("null", Json::Null),
+                Some('t') => self.literal("true", Json::Bool(true)),
+                Some('f') => self.literal("false", Json::Bool(false)),
+                Some('"') => self.string().map(Json::String),
+                Some('[') => self.array(),
+                Some('{') => self.object(),
+                Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+                Some(_) => Err(self.error("неожиданный символ")),
+                None => Err(self.error("неожиданный конец")),
+            }
+        }
+
+        fn number(&mut self) -> Result<Json, String> {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(c)) {
+                self.pos += 1;
+            }
+            let text: String = self.chars[start..self.pos].iter().collect();
+            if text.parse::<f64>().is_err() {
+                self.pos = start;
+                return Err(self.error("некорректное число"));
+            }
+            Ok(Json::Number(text))
+        }
+
+        fn string(&mut self) -> Result<String, String> {
+            self.expect('"')?;
+            let mut s = String::new();
+            loop {
+                let c = self.peek().ok_or_else(|| self.error("незакрытая строка"))?;
+                self.pos += 1;
+                match c {
+                    '"' => return Ok(s),
+                    '\\' => {
+                        let escape = self.peek().ok_or_else(|| self.error("незакрытая строка"))?;
+                        self.pos += 1;
+                        s.push(match escape {
+                            '"' => '"',
+                            '\\' => '\\',
+                            '/' => '/',
+                            'b' => '\u{8}',
+                            'f' => '\u{c}',
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            'u' => {
+                                let hex: String = self.chars.get(self.pos..self.pos + 4).unwrap_or_default().iter().collect();
+                                let code = u32::from_str_radix(&hex, 16).map_err(|_| self.error("некорректный \\u"))?;
+                                self.pos += 4;
+                                // Суррогатные пары не поддерживаются: такие символы заменяются
+                                char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                            }
+                            _ => return Err(self.error("неизвестная escape-последовательность")),
+                        });
+                    }
+                    c => s.push(c),
+                }
+            }
+        }
+
+        fn array(&mut self) -> Result<Json, String> {
+            self.expect('[')?;
+            let mut items = Vec::new();
+            self.skip_whitespace();
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(self.value()?);
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(',') => self.pos += 1,
+                    Some(']') => {
+                        self.pos += 1;
+                        return Ok(Json::Array(items));
+                    }
+                    _ => return Err(self.error("ожидается ',' или ']'")),
+                }
+            }
+        }
+
+        fn object(&mut self) -> Result<Json, String> {
+            self.expect('{')?;
+            let mut fields = Vec::new();
+            self.skip_whitespace();
+            if self.peek() == Some('}') {
+                self.pos += 1;
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                self.skip_whitespace();
+                let key = self.string()?;
+                self.expect(':')?;
+                fields.push((key, self.value()?));
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(',') => self.pos += 1,
+                    Some('}') => {
+                        self.pos += 1;
+                        return Ok(Json::Object(fields));
+                    }
+                    _ => return Err(self.error("ожидается ',' или '}'")),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+pub mod ledger {
+    use std::collections::HashMap;
+    use crate::{Balance, Name, StorageError};
+
+    /// Касса банка: сюда приходят снятые наличные и отсюда берутся пополнения
+    pub const CASH_VAULT: &str = "@cash";
+    /// Транзитный счёт для сумм неизвестного происхождения
+    /// (остатки старых снапшотов, остатки закрытых счетов)
+    pub const SUSPENSE: &str = "@suspense";
+    /// Процентные расходы банка: отсюда выплачиваются проценты клиентам
+    /// и сюда приходят проценты, списанные с клиентов
+    pub const INTEREST: &str = "@interest";
+    /// Комиссионный доход банка: сюда приходят комиссии за операции
+    pub const FEES: &str = "@fees";
+
+    /// Внутренние счета банка начинаются с `@` и не видны как счета клиентов
+    pub fn is_internal(account: &str) -> bool {
+        account.starts_with('@')
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Side {
+        Debit,
+        Credit,
+    }
+
+    /// Одна нога проводки: сумма по дебету или кредиту одного счёта
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Leg {
+        pub account: Name,
+        pub side: Side,
+        pub amount: Balance,
+    }
+
+    impl Leg {
+        pub fn debit(account: &str, amount: Balance) -> Leg {
+            Leg { account: account.to_string(), side: Side::Debit, amount }
+        }
+
+        pub fn credit(account: &str, amount: Balance) -> Leg {
+            Leg { account: account.to_string(), side: Side::Credit, amount }
+        }
+    }
+
+    /// Проводка: набор ног, у которого сумма дебетов равна сумме кредитов
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Posting {
+        pub memo: String,
+        pub legs: Vec<Leg>,
+    }
+
+    impl Posting {
+        pub fn new(memo: &str, legs: Vec<Leg>) -> Posting {
+            Posting { memo: memo.to_string(), legs }
+        }
+
+        /// Суммы по дебету и кредиту
+        fn totals(&self) -> Result<(Balance, Balance), StorageError> {
+            let mut debit = Balance::ZERO;
+            let mut credit = Balance::ZERO;
+            for leg in &self.legs {
+                let total = match leg.side {
+                    Side::Debit => &mut debit,
+                    Side::Credit => &mut credit,
+                };
+                *total = total.checked_add(leg.amount).ok_or(StorageError::Overflow)?;
+            }
+            Ok((debit, credit))
+        }
+    }
+
+    /// Оборотно-сальдовая ведомость: сальдо каждого счёта по дебету или кредиту
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TrialBalance {
+        /// Счета с ненулевым сальдо, по имени
+        pub rows: Vec<(Name, Balance)>,
+        pub total_debit: Balance,
+        pub total_credit: Balance,
+    }
+
+    impl TrialBalance {
+        /// Дебет сходится с кредитом
+        pub fn is_balanced(&self) -> bool {
+            self.total_debit == self.total_credit
+        }
+    }
+
+    /// Главная книга по двойной записи.
+    ///
+    /// Сальдо счёта хранится со знаком «кредит минус дебет»: для счетов клиентов
+    /// (обязательств банка) это их баланс, у кассы сальдо отрицательное.
+    /// Так как каждая проводка сбалансирована, сумма всех сальдо всегда равна нулю
+    #[derive(Debug, Clone, Default)]
+    pub struct Ledger {
+        balances: HashMap<Name, Balance>,
+    }
+
+    impl Ledger {
+        pub fn new() -> Self {
+            Ledger::default()
+        }
+
+        /// Сальдо счёта (кредит минус дебет); у неизвестного счёта — ноль
+        pub fn balance(&self, account: &str) -> Balance {
+            self.balances.get(account).copied().unwrap_or(Balance::ZERO)
+        }
+
+        /// Все счета книги с их сальдо
+        pub fn balances(&self) -> impl Iterator<Item = (&Name, &Balance)> {
+            self.balances.iter()
+        }
+
+        /// Проверяет проводку и применяет её. Возвращает новые сальдо затронутых счетов.
+        /// Несбалансированная проводка или переполнение не меняют книгу
+        pub fn post(&mut self, posting: Posting) -> Result<Vec<(Name, Balance)>, StorageError> {
+            let (debit, credit) = posting.totals()?;
+            let positive = posting.legs.iter().all(|leg| leg.amount.is_positive());
+            if !positive || debit != credit || posting.legs.len() < 2 {
+                return Err(StorageError::Unbalanced(posting.memo));
+            }
+
+            // Сначала считаем новые сальдо, и только если всё посчиталось — применяем
+            let mut updated: Vec<(Name, Balance)> = Vec::new();
+            for leg in &posting.legs {
+                let current = updated
+                    .iter()
+                    .rev()
+                    .find(|(account, _)| *account == leg.account)
+                    .map(|(_, balance)| *balance)
+                    .unwrap_or_else(|| self.balance(&leg.account));
+                let next = match leg.side {
+                    Side::Credit => current.checked_add(leg.amount),
+                    Side::Debit => current.checked_sub(leg.amount),
+                }
+                .ok_or(StorageError::Overflow)?;
+                updated.retain(|(account, _)| *account != leg.account);
+                updated.push((leg.account.clone(), next));
+            }
+
+            for (account, balance) in &updated {
+                self.balances.insert(account.clone(), *balance);
+            }
+            Ok(updated)
+        }
+
+        /// Сальдо счёта, если счёт есть в книге
+        pub(crate) fn get(&self, account: &str) -> Option<Balance> {
+            self.balances.get(account).copied()
+        }
+
+        /// Возвращает счёт в прежнее состояние при откате; None — счёта в книге не было
+        pub(crate) fn restore(&mut self, account: &str, balance: Option<Balance>) {
+            match balance {
+                Some(balance) => self.set_balance(account, balance),
+                None => {
+                    self.balances.remove(account);
+                }
+            }
+        }
+
+        /// Выставляет сальдо напрямую — только при загрузке снапшота
+        pub(crate) fn set_balance(&mut self, account: &str, balance: Balance) {
+            self.balances.insert(account.to_string(), balance);
+        }
+
+        /// Удаляет счёт с нулевым сальдо из книги
+        pub(crate) fn remove_account(&mut self, account: &str) {
+            if self.balance(account).is_zero() {
+                self.balances.remove(account);
+            }
+        }
+
+        /// Сумма сальдо всех счетов; у сбалансированной книги она равна нулю
+        pub fn net(&self) -> Result<Balance, StorageError> {
+            self.balances
+                .values()
+                .try_fold(Balance::ZERO, |sum, balance| sum.checked_add(*balance))
+                .ok_or(StorageError::Overflow)
+        }
+
+        /// Строит оборотно-сальдовую ведомость
+        pub fn trial_balance(&self) -> Result<TrialBalance, StorageError> {
+            let mut rows: Vec<(Name, Balance)> = self
+                .balances
+                .iter()
+                .filter(|(_, balance)| !balance.is_zero())
+                .map(|(account, balance)| (account.clone(), *balance))
+                .collect();
+            rows.sort();
+
+            let mut total_debit = Balance::ZERO;
+            let mut total_credit = Balance::ZERO;
+            for (_, balance) in &rows {
+                if balance.is_negative() {
+                    let amount = balance.checked_neg().ok_or(StorageError::Overflow)?;
+                    total_debit = total_debit.checked_add(amount).ok_or(StorageError::Overflow)?;
+                } else {
+                    total_credit = total_credit.checked_add(*balance).ok_or(StorageError::Overflow)?;
+                }
+            }
+
+            Ok(TrialBalance { rows, total_debit, total_credit })
+        }
+    }
+}
@@ -1,22 +1,83 @@
+#[allow(clippy::module_inception)]
+mod batch_file;
+#[allow(clippy::module_inception)]
+mod calendar;
+#[allow(clippy::module_inception)]
+mod events;
+#[allow(clippy::module_inception)]
+mod fees;
+#[allow(clippy::module_inception)]
+mod history;
+#[allow(clippy::module_inception)]
+mod holds;
+#[allow(clippy::module_inception)]
+mod http;
+#[allow(clippy::module_inception)]
+mod idempotency;
+#[allow(clippy::module_inception)]
+mod interest;
+#[allow(clippy::module_inception)]
+mod journal;
+#[allow(clippy::module_inception)]
+mod json;
+#[allow(clippy::module_inception)]
+mod ledger;
+#[allow(clippy::module_inception)]
+mod money;
+#[allow(clippy::module_inception)]
+mod persist;
+#[allow(clippy::module_inception)]
+mod schedule;
+#[allow(clippy::module_inception)]
+mod script;
+#[allow(clippy::module_inception)]
+mod server;
+#[allow(clippy::module_inception)]
+mod shared;
+#[allow(clippy::module_inception)]
 mod storage;
+#[allow(clippy::module_inception)]
 mod transaction;
 
-pub use storage::storage::Storage;
-pub use transaction::transaction::{Deposit, Transaction, Transfer, TxCombinator, Withdraw};
+pub use schedule::schedule::{Recurrence, RetryPolicy, RunRecord, RunStatus, Schedule, ScheduleBook, Scheduler, TICK_EVERY, Tick};
+pub use script::script::{Script, ScriptError};
+pub use server::server::{DEFAULT_ADDR, Response, handle_command, serve_client};
+pub use shared::shared::{SharedStorage, Snapshot};
+pub use storage::storage::{SaveOptions, Storage, StorageError, is_valid_name};
+pub use batch_file::batch_file::{BatchMode, Instruction, LineResult, LineStatus, ParsedLine, Report, process, read_instructions, reference_key};
+pub use calendar::calendar::{Clock, ManualClock, SECONDS_PER_DAY, SystemClock, Timestamp, format_date, format_timestamp, parse_date};
+pub use events::events::{Event, EventBus, Subscriber, SubscriberId};
+pub use fees::fees::{Cap, CapPeriod, Charge, DEFAULT_FEES_FILE, FeeKind, FeeRule, FeeSchedule};
+pub use history::history::{EntryKind, History, HistoryEntry, Statement};
+pub use holds::holds::{DEFAULT_HOLD_TTL, Hold, HoldBook};
+pub use http::http::{DEFAULT_HTTP_ADDR, HttpResponse, Request, loopback_addrs, parse_transaction, read_request, route, serve_http};
+pub use idempotency::idempotency::{KeyStore, Outcome};
+pub use interest::interest::{ACCRUAL_UNITS_PER_MINOR, Accrual, AccrualLine, DayCount, InterestBook, InterestProduct, Period, Rate, Rounding};
+pub use journal::journal::{Journal, JournalEntry, Record};
+pub use json::json::Json;
+pub use ledger::ledger::{CASH_VAULT, FEES, INTEREST, Leg, Ledger, Posting, SUSPENSE, Side, TrialBalance};
+pub use money::money::{Money, ParseMoneyError};
+pub use transaction::transaction::{BalanceDeltas, Batch, Deposit, Operation, Transaction, Transfer, TxCombinator, TxError, Withdraw};
 
 pub type Name = String;
-pub type Balance = i64;
+pub type Balance = Money;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::BufRead;
     use storage::storage::Storage;
+    use transaction::transaction::TxError;
+
+    /// Сумма в целых единицах — чтобы не писать Money::from_major в каждом тесте
+    fn m(major: i64) -> Balance {
+        Money::from_major(major)
+    }
 
     #[test]
     fn test_add_user() {
         let mut storage = Storage::new();
-        assert_eq!(storage.add_user("Alice".to_string()), Some(0)); // новый пользователь
+        assert_eq!(storage.add_user("Alice".to_string()), Some(m(0))); // новый пользователь
         assert_eq!(storage.add_user("Alice".to_string()), None); // уже существует
     }
 
@@ -24,9 +85,9 @@ mod tests {
     fn test_remove_user() {
         let mut storage = Storage::new();
         storage.add_user("Bob".to_string());
-        storage.deposit(&"Bob".to_string(), 100).unwrap();
+        storage.deposit(&"Bob".to_string(), m(100)).unwrap();
 
-        assert_eq!(storage.remove_user(&"Bob".to_string()), Some(100)); // удаляем и получаем баланс
+        assert_eq!(storage.remove_user(&"Bob".to_string()), Some(m(100))); // удаляем и получаем баланс
         assert_eq!(storage.remove_user(&"Bob".to_string()), None); // второй раз — не найден
     }
 
@@ -35,10 +96,10 @@ mod tests {
         let mut storage = Storage::new();
 
         // Депозит несуществующему пользователю
-        assert!(storage.deposit(&"Dana".to_string(), 100).is_err());
+        assert!(storage.deposit(&"Dana".to_string(), m(100)).is_err());
 
         // Снятие у несуществующего пользователя
-        assert!(storage.withdraw(&"Dana".to_string(), 50).is_err());
+        assert!(storage.withdraw(&"Dana".to_string(), m(50)).is_err());
 
         // Баланс у несуществующего пользователя
         assert_eq!(storage.get_balance(&"Dana".to_string()), None);
@@ -60,15 +121,15 @@ mod tests {
             let parts: Vec<&str> = line.trim().split(',').collect();
             if parts.len() == 2 {
                 let name = parts[0].to_string();
-                let balance: i64 = parts[1].parse().unwrap_or(0);
+                let balance: Balance = parts[1].parse().unwrap_or(Balance::ZERO);
                 storage.add_user(name.clone());
                 storage.deposit(&name, balance).unwrap();
             }
         }
 
-        assert_eq!(storage.get_balance(&"John".to_string()), Some(100));
-        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(200));
-        assert_eq!(storage.get_balance(&"Bob".to_string()), Some(50));
+        assert_eq!(storage.get_balance(&"John".to_string()), Some(m(100)));
+        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(m(200)));
+        assert_eq!(storage.get_balance(&"Bob".to_string()), Some(m(50)));
         assert_eq!(storage.get_balance(&"Vasya".to_string()), None); // нет в данных
     }
 
@@ -78,8 +139,8 @@ mod tests {
         let mut storage = Storage::new();
         storage.add_user("John".to_string());
         storage.add_user("Alice".to_string());
-        storage.deposit(&"John".to_string(), 150).unwrap();
-        storage.deposit(&"Alice".to_string(), 300).unwrap();
+        storage.deposit(&"John".to_string(), m(150)).unwrap();
+        storage.deposit(&"Alice".to_string(), m(300)).unwrap();
 
         // Сохраняем в память через BufWriter
         let buffer = Vec::new();
@@ -97,6 +158,1673 @@ mod tests {
         let mut lines: Vec<String> = BufReader::new(cursor).lines().map(|l| l.unwrap()).collect();
         lines.sort(); // сортируем для сравнения
 
-        assert_eq!(lines, vec!["Alice,300", "John,150"]);
+        assert_eq!(lines, vec!["Alice,300.00", "John,150.00"]);
+    }
+
+    #[test]
+    fn test_combinator_rolls_back_first_step() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+        storage.deposit(&"Alice".to_string(), m(10)).unwrap();
+
+        // Депозит проходит, а перевод — нет: денег не хватает даже с учётом депозита
+        let tx = Deposit { account: "Alice".to_string(), amount: m(50) }
+            + Transfer { from: "Alice".to_string(), to: "Bob".to_string(), amount: m(100) };
+
+        assert!(matches!(tx.apply(&mut storage), Err(TxError::InsufficientFunds { .. })));
+        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(m(10)));
+        assert_eq!(storage.get_balance(&"Bob".to_string()), Some(m(0)));
+    }
+
+    #[test]
+    fn test_nested_chain_is_all_or_nothing() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        // tx_chain! строит TxCombinator<TxCombinator<Deposit, Transfer>, Withdraw>
+        let tx = tx_chain!(
+            Deposit { account: "Alice".to_string(), amount: m(100) },
+            Transfer { from: "Alice".to_string(), to: "Bob".to_string(), amount: m(30) },
+            Withdraw { account: "Bob".to_string(), amount: m(50) }
+        );
+
+        assert!(tx.apply(&mut storage).is_err());
+        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(m(0)));
+        assert_eq!(storage.get_balance(&"Bob".to_string()), Some(m(0)));
+        assert_eq!(storage.accounts.len(), 2);
+
+        // Вложенная цепочка справа: Deposit + (Transfer + Withdraw)
+        let tx = TxCombinator {
+            t1: Deposit { account: "Alice".to_string(), amount: m(100) },
+            t2: TxCombinator {
+                t1: Transfer { from: "Alice".to_string(), to: "Bob".to_string(), amount: m(30) },
+                t2: Withdraw { account: "Bob".to_string(), amount: m(20) },
+            },
+        };
+
+        assert!(tx.apply(&mut storage).is_ok());
+        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(m(70)));
+        assert_eq!(storage.get_balance(&"Bob".to_string()), Some(m(10)));
+    }
+
+    #[test]
+    fn test_derived_transactions_reject_unknown_accounts() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.deposit(&"Alice".to_string(), m(100)).unwrap();
+
+        let deposit = Deposit { account: "Alcie".to_string(), amount: m(10) };
+        assert!(matches!(deposit.apply(&mut storage), Err(TxError::InvalidAccount)));
+
+        let withdraw = Withdraw { account: "Alcie".to_string(), amount: m(10) };
+        assert!(matches!(withdraw.apply(&mut storage), Err(TxError::InvalidAccount)));
+
+        // Опечатка в получателе не должна списывать деньги у отправителя
+        let transfer = Transfer { from: "Alice".to_string(), to: "Bbo".to_string(), amount: m(10) };
+        assert!(matches!(transfer.apply(&mut storage), Err(TxError::InvalidAccount)));
+
+        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(m(100)));
+        assert_eq!(storage.accounts.len(), 1); // новых счетов не появилось
+    }
+
+    #[test]
+    fn test_storage_error_kinds() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.deposit(&"Alice".to_string(), m(10)).unwrap();
+
+        assert!(matches!(
+            storage.deposit(&"Dana".to_string(), m(1)),
+            Err(StorageError::NotFound(name)) if name == "Dana"
+        ));
+        assert!(matches!(
+            storage.withdraw(&"Alice".to_string(), m(11)),
+            Err(StorageError::InsufficientFunds { .. })
+        ));
+        assert!(matches!(
+            storage.deposit(&"Alice".to_string(), Money::MAX),
+            Err(StorageError::InvalidAmount(_))
+        ));
+        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(m(10)));
+
+        // Ошибки хранилища превращаются в соответствующие TxError
+        assert!(matches!(TxError::from(StorageError::NotFound("Dana".into())), TxError::InvalidAccount));
+        assert!(matches!(TxError::from(StorageError::Overflow), TxError::Overflow));
+        assert!(matches!(TxError::from(StorageError::Io(std::io::ErrorKind::Other.into())), TxError::Storage(_)));
+    }
+
+    #[test]
+    fn test_read_from_reports_line_numbers() {
+        let storage = Storage::read_from(Cursor::new("John,100\n\nAlice,200\n")).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".to_string()), Some(m(200)));
+
+        let err = Storage::read_from(Cursor::new("John,100\nAlice,много\n")).err().unwrap();
+        assert!(matches!(err, StorageError::Parse { line: 2, .. }));
+
+        let err = Storage::read_from(Cursor::new("John,100\nBob\n")).err().unwrap();
+        assert!(matches!(err, StorageError::Parse { line: 2, .. }));
+
+        let err = Storage::read_from(Cursor::new("John,100\nJohn,5\n")).err().unwrap();
+        assert!(matches!(err, StorageError::Parse { line: 2, .. }));
+    }
+
+    /// Пустой временный каталог для тестов, работающих с файлами
+    fn temp_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("bank-system-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_save_is_atomic_and_rotates_backups() {
+        let dir = temp_dir("save");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let options = SaveOptions { backups: 2 };
+
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        for amount in [100, 200, 300] {
+            storage.deposit(&"Alice".to_string(), m(amount)).unwrap();
+            storage.save_with(file, &options).unwrap();
+        }
+
+        // Временный файл не остаётся, а в копиях лежат предыдущие версии
+        assert!(!dir.join("balance.csv.tmp").exists());
+        let balance = |path: &str| Storage::load_data(path).unwrap().get_balance(&"Alice".to_string());
+        assert_eq!(balance(file), Some(m(600)));
+        assert_eq!(balance(dir.join("balance.csv.bak.1").to_str().unwrap()), Some(m(300)));
+        assert_eq!(balance(dir.join("balance.csv.bak.2").to_str().unwrap()), Some(m(100)));
+        assert!(!dir.join("balance.csv.bak.3").exists());
+
+        // Ошибка записи возвращается как Result, а не паникой
+        let missing = dir.join("no-such-dir").join("balance.csv");
+        assert!(matches!(storage.save(missing.to_str().unwrap()), Err(StorageError::Io(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_replay_restores_unsaved_changes() {
+        let dir = temp_dir("journal");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+
+        {
+            // Новый банк: снапшота нет, дефолтные пользователи попадают в журнал
+            let mut storage = Storage::load_data(file).unwrap();
+            storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+            storage.save(file).unwrap();
+
+            storage.open_account("Dana".into(), m(7)).unwrap();
+            let chain = Deposit { account: "Bob".into(), amount: m(5) }
+                + Transfer { from: "Alice".into(), to: "Dana".into(), amount: m(30) };
+            storage.commit(&chain).unwrap();
+            storage.close_account(&"Vasya".into()).unwrap();
+
+            // Неудачная транзакция в журнал не попадает
+            assert!(storage.commit(&Withdraw { account: "Bob".into(), amount: m(1000) }).is_err());
+            // «Падение»: снапшот после последних команд не сохраняем
+        }
+
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(70)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(5)));
+        assert_eq!(storage.get_balance(&"Dana".into()), Some(m(37)));
+        assert_eq!(storage.get_balance(&"Vasya".into()), None);
+        assert_eq!(storage.accounts.len(), 4);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_ignores_torn_last_record() {
+        let dir = temp_dir("torn");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "John".into(), amount: m(10) }).unwrap();
+        drop(storage);
+
+        // Запись оборвалась посреди строки
+        let journal = dir.join("balance.csv.journal");
+        let mut data = std::fs::read_to_string(&journal).unwrap();
+        data.push_str("6 0123 commit deposit Jo");
+        std::fs::write(&journal, data).unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.get_balance(&"John".into()), Some(m(10)));
+        storage.commit(&Deposit { account: "John".into(), amount: m(1) }).unwrap();
+
+        // Пока хранилище открыто, второй загрузчик того же файла сразу получает отказ
+        assert!(matches!(Storage::load_data(file), Err(StorageError::Locked(_))));
+        drop(storage);
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.get_balance(&"John".into()), Some(m(11)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_record_round_trip() {
+        let records = [
+            Record::Open { name: "Alice".into(), balance: m(100) },
+            Record::Close { name: "Bob".into() },
+            Record::Commit(
+                (Deposit { account: "Alice".into(), amount: m(1) }
+                    + Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(2) })
+                .operations(),
+            ),
+            Record::Keyed {
+                key: "retry-1".into(),
+                outcome: Outcome::InsufficientFunds { available: m(1), requested: m(3) },
+                ops: Withdraw { account: "Bob".into(), amount: m(3) }.operations(),
+            },
+            Record::KeyedSteps {
+                keys: vec![Some("ref-1".into()), None],
+                ops: (Deposit { account: "Alice".into(), amount: m(1) } + Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(2) }).operations(),
+            },
+            Record::Reverse { original: 7, ops: Deposit { account: "Bob".into(), amount: m(3) }.reverse().operations() },
+        ];
+        for record in records {
+            assert_eq!(record.to_string().parse::<Record>(), Ok(record));
+        }
+        assert!("commit deposit Alice".parse::<Record>().is_err());
+        assert!("keyed bad;key applied deposit Alice 1.00".parse::<Record>().is_err());
+        assert!("keyed_steps a,b deposit Alice 1.00".parse::<Record>().is_err());
+    }
+
+    #[test]
+    fn test_compaction_truncates_journal_and_keeps_state() {
+        let dir = temp_dir("compact");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let journal = dir.join("balance.csv.journal");
+        let options = SaveOptions { backups: 1 };
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+        storage.compact(file, &options).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(10) }).unwrap();
+        storage.compact(file, &options).unwrap();
+
+        // В журнале остались только записи после предыдущего снапшота (он хранится в .bak.1)
+        let lines = std::fs::read_to_string(&journal).unwrap();
+        assert_eq!(lines.lines().count(), 1);
+
+        storage.commit(&Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(1) }).unwrap();
+        drop(storage);
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(109)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(1)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_falls_back_to_previous_valid_snapshot() {
+        let dir = temp_dir("fallback");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let options = SaveOptions { backups: 1 };
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+        storage.compact(file, &options).unwrap();
+        storage.commit(&Deposit { account: "Bob".into(), amount: m(50) }).unwrap();
+        storage.compact(file, &options).unwrap();
+        drop(storage);
+
+        // Свежий снапшот испорчен: контрольная сумма больше не сходится
+        let data = std::fs::read_to_string(file).unwrap().replace("Bob,50.00", "Bob,5000.00");
+        std::fs::write(file, data).unwrap();
+        assert!(matches!(Storage::read_from(BufReader::new(std::fs::File::open(file).unwrap())), Err(StorageError::Parse { .. })));
+
+        // Загружается предыдущий снапшот, а недостающее берётся из журнала
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(100)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(50)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_money_parse_and_display() {
+        assert_eq!("10.50".parse::<Money>(), Ok(Money::from_minor(1050)));
+        assert_eq!("10.5".parse::<Money>(), Ok(Money::from_minor(1050)));
+        assert_eq!("10".parse::<Money>(), Ok(m(10)));
+        assert_eq!("-0.07".parse::<Money>(), Ok(Money::from_minor(-7)));
+
+        assert_eq!(Money::from_minor(1050).to_string(), "10.50");
+        assert_eq!(Money::from_minor(-7).to_string(), "-0.07");
+        assert_eq!(m(3).to_string(), "3.00");
+
+        assert!(matches!("10.505".parse::<Money>(), Err(ParseMoneyError::TooPrecise(_))));
+        assert!(matches!("10,50".parse::<Money>(), Err(ParseMoneyError::Invalid(_))));
+        assert!(matches!(".5".parse::<Money>(), Err(ParseMoneyError::Invalid(_))));
+        assert!(matches!("5.".parse::<Money>(), Err(ParseMoneyError::Invalid(_))));
+        assert!(matches!("99999999999999999999".parse::<Money>(), Err(ParseMoneyError::Overflow(_))));
+
+        assert_eq!(Money::MAX.checked_add(Money::from_minor(1)), None);
+    }
+
+    #[test]
+    fn test_cents_survive_transactions_and_csv() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        let tx = Deposit { account: "Alice".into(), amount: "10.50".parse().unwrap() }
+            + Transfer { from: "Alice".into(), to: "Bob".into(), amount: "0.75".parse().unwrap() };
+        tx.apply(&mut storage).unwrap();
+
+        let mut buffer = Vec::new();
+        for (name, balance) in storage.get_all() {
+            writeln!(buffer, "{},{}", name, balance).unwrap();
+        }
+        let loaded = Storage::read_from(Cursor::new(buffer)).unwrap();
+        assert_eq!(loaded.get_balance(&"Alice".into()), Some(Money::from_minor(975)));
+        assert_eq!(loaded.get_balance(&"Bob".into()), Some(Money::from_minor(75)));
+    }
+
+    #[test]
+    fn test_amounts_must_be_positive_and_not_overflow() {
+        // Вместе с Alice деньги клиентов ровно исчерпывают диапазон Money
+        let rich = Money::from_minor(i64::MAX - 10_000);
+        let mut storage = Storage::read_from(Cursor::new(format!("Alice,100\nRich,{}\n", rich))).unwrap();
+        storage.add_user("Bob".to_string());
+
+        // Отрицательный депозит — скрытое списание, отрицательный перевод — перевод в обратную сторону
+        let negative = m(-50);
+        assert!(matches!(storage.deposit(&"Alice".into(), negative), Err(StorageError::InvalidAmount(_))));
+        assert!(matches!(storage.withdraw(&"Alice".into(), m(0)), Err(StorageError::InvalidAmount(_))));
+        let reverse = Transfer { from: "Bob".into(), to: "Alice".into(), amount: negative };
+        assert!(matches!(reverse.apply(&mut storage), Err(TxError::InvalidAmount)));
+        let deposit = Deposit { account: "Alice".into(), amount: negative };
+        assert!(matches!(deposit.apply(&mut storage), Err(TxError::InvalidAmount)));
+
+        // Недостаток средств или переполнение не меняют ни одного баланса
+        let transfer = Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(101) };
+        assert!(matches!(transfer.apply(&mut storage), Err(TxError::InsufficientFunds { .. })));
+        let deposit = Deposit { account: "Rich".into(), amount: m(101) };
+        assert!(matches!(deposit.apply(&mut storage), Err(TxError::Overflow)));
+
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(100)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(0)));
+        assert_eq!(storage.get_balance(&"Rich".into()), Some(rich));
+    }
+
+    #[test]
+    fn test_history_records_successful_changes_only() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        let tx = Deposit { account: "Alice".into(), amount: m(100) }
+            + Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) };
+        tx.apply(&mut storage).unwrap();
+
+        // Откаченная цепочка не оставляет следов в истории
+        let failed = Deposit { account: "Bob".into(), amount: m(1) }
+            + Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(1000) };
+        assert!(failed.apply(&mut storage).is_err());
+
+        let alice: Vec<_> = storage.history().for_account("Alice").cloned().collect();
+        assert_eq!(alice.len(), 3);
+        assert_eq!(alice[1].kind, EntryKind::Deposit);
+        assert_eq!(alice[1].balance, m(100));
+        assert_eq!(alice[2].kind, EntryKind::TransferOut);
+        assert_eq!(alice[2].counterparty.as_deref(), Some("Bob"));
+        assert_eq!(alice[2].balance, m(70));
+
+        let bob: Vec<_> = storage.history().for_account("Bob").cloned().collect();
+        assert_eq!(bob.last().unwrap().kind, EntryKind::TransferIn);
+        // Обе стороны перевода имеют общий номер
+        assert_eq!(bob.last().unwrap().seq, alice[2].seq);
+
+        // Номера после отката продолжаются без пропусков
+        storage.withdraw(&"Bob".into(), m(5)).unwrap();
+        assert_eq!(storage.history().entries().last().unwrap().seq, alice[2].seq + 1);
+    }
+
+    #[test]
+    fn test_statement_opening_and_closing_balances() {
+        let day = |date: &str| parse_date(date).unwrap();
+        let mut history = History::new();
+        for (date, kind, amount, balance) in [
+            ("2024-01-01", EntryKind::Deposit, 100, 100),
+            ("2024-01-15", EntryKind::Withdraw, 30, 70),
+            ("2024-02-01", EntryKind::Deposit, 5, 75),
+        ] {
+            let seq = history.next_seq();
+            history.push(HistoryEntry {
+                seq,
+                timestamp: day(date) + 3600,
+                account: "Alice".into(),
+                kind,
+                counterparty: None,
+                amount: m(amount),
+                balance: m(balance),
+                reverses: None,
+            });
+        }
+
+        let statement = history.statement("Alice", Some(day("2024-01-10")), Some(day("2024-02-01"))).unwrap();
+        assert_eq!(statement.opening, m(100));
+        assert_eq!(statement.closing, m(70));
+        assert_eq!(statement.entries.len(), 1);
+
+        let everything = history.statement("Alice", None, None).unwrap();
+        assert_eq!((everything.opening, everything.closing), (m(0), m(75)));
+
+        let empty = history.statement("Alice", Some(day("2025-01-01")), None).unwrap();
+        assert_eq!((empty.opening, empty.closing), (m(75), m(75)));
+
+        assert!(history.statement("Bob", None, None).is_none());
+    }
+
+    #[test]
+    fn test_history_survives_compaction_and_replay() {
+        let dir = temp_dir("history");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+        storage.compact(file, &SaveOptions::default()).unwrap();
+        storage.commit(&Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(40) }).unwrap();
+        let before: Vec<HistoryEntry> = storage.history().entries().to_vec();
+        drop(storage);
+
+        // Часть истории — из снапшота, часть — из журнала, с теми же временем и номерами
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.history().entries(), before.as_slice());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_calendar_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2024-02-29").map(format_date).as_deref(), Some("2024-02-29"));
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(format_timestamp(parse_date("2000-03-01").unwrap() + 3661), "2000-03-01 01:01:01");
+    }
+
+    #[test]
+    fn test_ledger_stays_balanced() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+        storage.deposit(&"Alice".into(), m(100)).unwrap();
+        storage.transfer(&"Alice".into(), &"Bob".into(), m(30)).unwrap();
+        storage.withdraw(&"Bob".into(), m(10)).unwrap();
+        assert!(storage.withdraw(&"Bob".into(), m(1000)).is_err());
+
+        // Касса выдала 10 и приняла 100: её сальдо — 90 по дебету
+        assert_eq!(storage.ledger().balance(CASH_VAULT), m(-90));
+        let trial = storage.trial_balance().unwrap();
+        assert!(trial.is_balanced());
+        assert_eq!(trial.total_credit, m(90));
+
+        // Остаток закрытого счёта уходит на транзитный счёт, а не исчезает
+        storage.remove_user(&"Bob".into());
+        assert_eq!(storage.ledger().balance(SUSPENSE), m(20));
+        assert!(storage.trial_balance().unwrap().is_balanced());
+
+        // Несбалансированную проводку книга не принимает
+        let posting = Posting::new("broken", vec![Leg::debit(CASH_VAULT, m(5)), Leg::credit("Alice", m(4))]);
+        assert!(matches!(storage.post(posting), Err(StorageError::Unbalanced(_))));
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(70)));
+
+        // Имена внутренних счетов клиентам недоступны
+        assert_eq!(storage.add_user(CASH_VAULT.to_string()), None);
+    }
+
+    #[test]
+    fn test_ledger_internal_accounts_are_persisted() {
+        // В старом файле нет внутренних счетов — разница уходит на транзитный счёт
+        let legacy = Storage::read_from(Cursor::new("Alice,100\nBob,50\n")).unwrap();
+        assert_eq!(legacy.ledger().balance(SUSPENSE), m(-150));
+        assert!(legacy.trial_balance().unwrap().is_balanced());
+
+        let dir = temp_dir("ledger");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+        storage.commit(&Withdraw { account: "Alice".into(), amount: m(40) }).unwrap();
+        storage.compact(file, &SaveOptions::default()).unwrap();
+        drop(storage);
+
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.ledger().balance(CASH_VAULT), m(-60));
+        assert_eq!(storage.ledger().balance(SUSPENSE), m(0));
+        assert_eq!(storage.accounts.len(), 4); // внутренние счета не считаются клиентами
+        assert!(storage.trial_balance().unwrap().is_balanced());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_idempotency_key_applies_once() {
+        let dir = temp_dir("idempotency");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+
+        let tx = Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) };
+        storage.commit_keyed("retry-1", &tx).unwrap();
+        storage.commit_keyed("retry-1", &tx).unwrap();
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(30)));
+
+        // Отказ тоже запоминается: повтор не применяет транзакцию, даже если денег стало хватать
+        let big = Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(500) };
+        assert!(matches!(storage.commit_keyed("retry-2", &big), Err(TxError::InsufficientFunds { .. })));
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(1000) }).unwrap();
+        assert!(matches!(storage.commit_keyed("retry-2", &big), Err(TxError::InsufficientFunds { .. })));
+        let funds = Outcome::InsufficientFunds { available: m(70), requested: m(500) };
+        assert_eq!(storage.keys().get("retry-2"), Some(funds));
+
+        assert!(matches!(
+            storage.commit_keyed("bad key", &tx),
+            Err(TxError::Storage(StorageError::InvalidKey(_)))
+        ));
+
+        // Ключи переживают перезапуск — и из журнала, и из снапшота
+        drop(storage);
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit_keyed("retry-1", &tx).unwrap();
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(30)));
+        storage.compact(file, &SaveOptions::default()).unwrap();
+        drop(storage);
+
+        let mut storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.keys().len(), 2);
+        storage.commit_keyed("retry-1", &tx).unwrap();
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(30)));
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(1070)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reverse_transactions() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        let deposit = Deposit { account: "Alice".into(), amount: m(100) };
+        assert_eq!(deposit.reverse().operations(), Withdraw { account: "Alice".into(), amount: m(100) }.operations());
+
+        // Цепочка отменяется в обратном порядке: сначала перевод, потом депозит
+        let chain = deposit + Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) };
+        chain.apply(&mut storage).unwrap();
+        let undo = chain.reverse();
+        assert_eq!(
+            undo.operations(),
+            vec![
+                "transfer Bob Alice 30.00".parse::<Operation>().unwrap(),
+                "withdraw Alice 100.00".parse::<Operation>().unwrap(),
+            ]
+        );
+        undo.apply(&mut storage).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(0)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(0)));
+    }
+
+    #[test]
+    fn test_reverse_by_change_seq() {
+        let dir = temp_dir("reverse");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let mut storage = Storage::load_data(file).unwrap();
+
+        storage
+            .commit(&(Deposit { account: "Alice".into(), amount: m(100) }
+                + Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) }))
+            .unwrap();
+        // Все записи транзакции получили один номер
+        let seq = storage.history().entries().last().unwrap().seq;
+        assert_eq!(storage.history().change(seq).count(), 3);
+
+        storage.reverse(seq).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(0)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(0)));
+        let undo = storage.history().reversed_by(seq).unwrap();
+        assert!(storage.history().change(undo).all(|entry| entry.reverses == Some(seq)));
+
+        // Повторно отменить нельзя, открытие счёта — тоже
+        assert!(matches!(storage.reverse(seq), Err(TxError::Storage(StorageError::Irreversible { .. }))));
+        assert!(matches!(storage.reverse(1), Err(TxError::Storage(StorageError::Irreversible { .. }))));
+
+        // Отмена, для которой уже не хватает денег, ничего не меняет
+        storage.commit(&Deposit { account: "Bob".into(), amount: m(10) }).unwrap();
+        let bob_deposit = storage.history().entries().last().unwrap().seq;
+        storage.commit(&Withdraw { account: "Bob".into(), amount: m(10) }).unwrap();
+        assert!(matches!(storage.reverse(bob_deposit), Err(TxError::InsufficientFunds { .. })));
+
+        // Отмена и ссылка на отменённое изменение восстанавливаются из журнала
+        let history = storage.history().entries().to_vec();
+        drop(storage);
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.history().entries(), history.as_slice());
+        assert_eq!(storage.history().reversed_by(seq), Some(undo));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_simulate_does_not_mutate() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+        storage.deposit(&"Alice".into(), m(50)).unwrap();
+        let history_len = storage.history().entries().len();
+
+        let chain = Deposit { account: "Alice".into(), amount: m(100) }
+            + Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) };
+        let deltas = chain.simulate(&storage).unwrap();
+        assert_eq!(deltas.get("Alice"), Some(&m(70)));
+        assert_eq!(deltas.get("Bob"), Some(&m(30)));
+
+        // Перевод самому себе ничего не меняет — пустые разницы не включаются
+        let to_self = Transfer { from: "Alice".into(), to: "Alice".into(), amount: m(10) };
+        assert!(storage.simulate(&to_self).unwrap().is_empty());
+
+        // Ошибка — первая по ходу цепочки
+        let failing = Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(500) }
+            + Transfer { from: "Alice".into(), to: "Nobody".into(), amount: m(1) };
+        assert!(matches!(failing.validate(&storage), Err(TxError::InsufficientFunds { .. })));
+
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(50)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(0)));
+        assert_eq!(storage.history().entries().len(), history_len);
+        assert!(storage.trial_balance().unwrap().is_balanced());
+    }
+
+    #[test]
+    fn test_batch_of_mixed_transactions() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        let batch = Batch::new()
+            .with(Deposit { account: "Alice".into(), amount: m(100) })
+            .with(Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) })
+            .with(Withdraw { account: "Bob".into(), amount: m(5) })
+            .with("deposit Bob 1.50".parse::<Operation>().unwrap());
+        assert_eq!(batch.len(), 4);
+        storage.commit(&batch).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(70)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(Money::from_minor(26_50)));
+
+        // Ошибка третьего шага откатывает первые два и называет номер шага
+        let failing: Batch = vec![
+            Box::new(Deposit { account: "Bob".into(), amount: m(1) }) as Box<dyn Transaction>,
+            Box::new(Transfer { from: "Bob".into(), to: "Alice".into(), amount: m(1) }),
+            Box::new(Withdraw { account: "Bob".into(), amount: m(1000) }),
+        ]
+        .into();
+        let error = storage.commit(&failing).unwrap_err();
+        assert!(matches!(error, TxError::Step { index: 2, .. }));
+        assert!(matches!(error.root(), TxError::InsufficientFunds { .. }));
+        assert_eq!(error.to_string(), "Шаг 3: Не хватает денег на балансе: доступно 26.50, требуется 1000.00");
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(Money::from_minor(26_50)));
+
+        // Пакет отменяется целиком, шаги — в обратном порядке
+        batch.reverse().apply(&mut storage).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(0)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(0)));
+    }
+
+    #[test]
+    fn test_script_parse_and_print() {
+        let script: Script = "deposit Alice 100; transfer Alice Bob 30.5;withdraw Bob 5".parse().unwrap();
+        assert_eq!(
+            script.steps,
+            vec![
+                Operation::Deposit(Deposit { account: "Alice".into(), amount: m(100) }),
+                Operation::Transfer(Transfer { from: "Alice".into(), to: "Bob".into(), amount: Money::from_minor(30_50) }),
+                Operation::Withdraw(Withdraw { account: "Bob".into(), amount: m(5) }),
+            ]
+        );
+        assert_eq!(script.to_string(), "deposit Alice 100.00; transfer Alice Bob 30.50; withdraw Bob 5.00");
+        assert_eq!(script.to_string().parse::<Script>(), Ok(script.clone()));
+        assert_eq!(format!("{:#}", script).parse::<Script>(), Ok(script.clone()));
+
+        // Переводы строк, комментарии и операции без разделителя
+        let text = "# зарплата\ndeposit Alice 100 transfer Alice Bob 30.50\n\nwithdraw Bob 5 # наличные\n";
+        assert_eq!(text.parse::<Script>(), Ok(script));
+
+        let error = |text: &str| text.parse::<Script>().unwrap_err();
+        let e = error("deposit Alice 100; depost Bob 5");
+        assert_eq!((e.line, e.column), (1, 20));
+        let e = error("deposit Alice 100\ntransfer Alice Bob 1.234");
+        assert_eq!((e.line, e.column), (2, 20));
+        // Не хватает аргумента: ошибка указывает на конец операции, а не на следующую
+        let e = error("transfer Alice 5; deposit Bob 1");
+        assert_eq!((e.line, e.column), (1, 17));
+        assert_eq!(e.to_string(), "строка 1, позиция 17: у transfer должно быть аргументов: 3, найдено: 2");
+    }
+
+    #[test]
+    fn test_script_runs_as_one_chain() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        let script: Script = "deposit Alice 100; transfer Alice Bob 30; withdraw Bob 5".parse().unwrap();
+        storage.commit(&script).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(70)));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(25)));
+
+        let failing: Script = "deposit Bob 1; withdraw Bob 500".parse().unwrap();
+        assert!(matches!(storage.commit(&failing), Err(TxError::Step { index: 1, .. })));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(25)));
+
+        storage.commit(&script.reverse()).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(0)));
+    }
+
+    #[test]
+    fn test_batch_file_best_effort() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        let file = "kind,from,to,amount,reference\n\
+                    deposit,,Alice,100,inv-1\n\
+                    transfer,Alice,Bob,30,inv-2\n\
+                    # комментарий\n\
+                    withdraw,Bob,,500,inv-3\n\
+                    withdraw,,,5,\n";
+        let instructions = read_instructions(Cursor::new(file)).unwrap();
+        assert_eq!(instructions.len(), 4);
+        let report = process(&mut storage, instructions.clone(), BatchMode::BestEffort);
+        let statuses: Vec<_> = report.lines.iter().map(|result| (result.line, result.status.to_string())).collect();
+        assert_eq!(
+            statuses,
+            [(2, "applied"), (3, "applied"), (5, "failed"), (6, "failed")].map(|(line, status)| (line, status.to_string()))
+        );
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(30)));
+
+        // Повторная обработка того же файла не проводит платежи второй раз
+        let again = process(&mut storage, instructions, BatchMode::BestEffort);
+        assert_eq!(again.lines[0].status, LineStatus::Duplicate(Outcome::Applied));
+        assert_eq!(again.lines[2].status, LineStatus::Duplicate(Outcome::InsufficientFunds { available: m(30), requested: m(500) }));
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(70)));
+
+        let mut csv = Vec::new();
+        report.write_to(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(3), Some("5,inv-3,failed,\"Не хватает денег на балансе: доступно 30.00, требуется 500.00\""));
+    }
+
+    #[test]
+    fn test_batch_file_all_or_nothing() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        let file = "deposit,,Alice,100,\ntransfer,Alice,Bob,500,\ndeposit,,Bob,1,\n";
+        let report = process(&mut storage, read_instructions(Cursor::new(file)).unwrap(), BatchMode::AllOrNothing);
+        let statuses: Vec<_> = report.lines.iter().map(|result| result.status.to_string()).collect();
+        assert_eq!(statuses, ["rolled_back", "failed", "rolled_back"]);
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(0)));
+
+        // Ошибка разбора любой строки — ничего не применяется
+        let file = "deposit,,Alice,100,\ndeposit,,Bob,abc,\n";
+        let report = process(&mut storage, read_instructions(Cursor::new(file)).unwrap(), BatchMode::AllOrNothing);
+        assert_eq!((report.applied(), report.failed()), (0, 1));
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(0)));
+
+        let file = "deposit,,Alice,100,\ntransfer,Alice,Bob,30,\n";
+        let report = process(&mut storage, read_instructions(Cursor::new(file)).unwrap(), BatchMode::AllOrNothing);
+        assert_eq!(report.applied(), 2);
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(30)));
+    }
+
+    #[test]
+    fn test_batch_file_references_are_keys_in_both_modes() {
+        let mut storage = Storage::new();
+        storage.add_user("Alice".to_string());
+        storage.add_user("Bob".to_string());
+
+        // reference — любой текст; поле с запятой берётся в кавычки, лишнее поле — ошибка
+        let file = "deposit,,Alice,100,INV/2024/001\ntransfer,Alice,Bob,30,\"счёт 7, \"\"срочно\"\"\"\n";
+        let instructions = read_instructions(Cursor::new(file)).unwrap();
+        assert_eq!(instructions[1].1.as_ref().unwrap().reference.as_deref(), Some("счёт 7, \"срочно\""));
+        assert!(read_instructions(Cursor::new("deposit,,Alice,1,000.00,x\n")).unwrap()[0].1.is_err());
+        assert!(read_instructions(Cursor::new("deposit,,Alice,1,\"x\n")).unwrap()[0].1.is_err());
+
+        let report = process(&mut storage, instructions[..1].to_vec(), BatchMode::BestEffort);
+        assert_eq!(report.applied(), 1);
+
+        // Пакет пропускает строку, уже проведённую построчно, а повтор пакета — все строки
+        for _ in 0..2 {
+            process(&mut storage, instructions.clone(), BatchMode::AllOrNothing);
+        }
+        assert_eq!((storage.get_balance(&"Alice".into()), storage.get_balance(&"Bob".into())), (Some(m(70)), Some(m(30))));
+        let again = process(&mut storage, instructions.clone(), BatchMode::BestEffort);
+        assert!(again.lines.iter().all(|result| result.status == LineStatus::Duplicate(Outcome::Applied)));
+        assert!(storage.keys().get(&reference_key("INV/2024/001")).is_some());
+
+        let mut csv = Vec::new();
+        again.write_to(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().nth(2), Some("2,\"счёт 7, \"\"срочно\"\"\",duplicate,applied"));
+    }
+
+    #[test]
+    fn test_shared_storage_conserves_money_under_load() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+
+        const NAMES: [&str; 6] = ["A", "B", "C", "D", "E", "F"];
+        let mut storage = Storage::new();
+        for name in NAMES {
+            storage.open_account(name.to_string(), m(1000)).unwrap();
+        }
+        let shared = Arc::new(SharedStorage::new(storage).unwrap());
+        let done = Arc::new(AtomicBool::new(false));
+
+        // Читатель всё время проверяет, что каждый срез сходится в ноль
+        let reader = {
+            let (shared, done) = (Arc::clone(&shared), Arc::clone(&done));
+            thread::spawn(move || {
+                let mut snapshots = 0;
+                while !done.load(Ordering::Relaxed) || snapshots == 0 {
+                    assert_eq!(shared.snapshot().net().unwrap(), m(0));
+                    snapshots += 1;
+                }
+            })
+        };
+
+        let writers: Vec<_> = (0..8u64)
+            .map(|thread_id| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    // Простой линейный конгруэнтный генератор: тесту не нужна настоящая случайность
+                    let mut seed = thread_id * 7919 + 1;
+                    let mut next = |n: u64| {
+                        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ((seed >> 33) % n) as usize
+                    };
+                    for _ in 0..2000 {
+                        let from = NAMES[next(6)].to_string();
+                        let to = NAMES[next(6)].to_string();
+                        let amount = m(next(50) as i64 + 1);
+                        // Ошибки (нехватка средств, перевод самому себе) допустимы — важно, что деньги не теряются
+                        let _ = match next(10) {
+                            0 => shared.commit(&Deposit { account: from, amount }),
+                            1 => shared.commit(&Withdraw { account: from, amount }),
+                            2 => shared.commit(&(Transfer { from: from.clone(), to: to.clone(), amount }
+                                + Transfer { from: to, to: from, amount })),
+                            _ => shared.commit(&Transfer { from, to, amount }),
+                        };
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+
+        // Деньги клиентов изменились ровно на столько, сколько прошло через кассу
+        let snapshot = shared.snapshot();
+        let customers = snapshot.accounts().fold(m(0), |sum, (_, balance)| sum.checked_add(*balance).unwrap());
+        let cash = snapshot.balances[CASH_VAULT];
+        assert_eq!(customers.checked_add(cash).unwrap(), m(0));
+        assert!(snapshot.accounts().all(|(_, balance)| !balance.is_negative()));
+
+        let storage = Arc::into_inner(shared).unwrap().into_inner().unwrap();
+        assert!(storage.trial_balance().unwrap().is_balanced());
+        for (name, balance) in snapshot.accounts() {
+            assert_eq!(storage.get_balance(name), Some(*balance));
+        }
+        assert_eq!(storage.ledger().balance(CASH_VAULT), cash);
+    }
+
+    #[test]
+    fn test_shared_storage_rejects_like_storage() {
+        let mut storage = Storage::new();
+        storage.open_account("Alice".to_string(), m(10)).unwrap();
+        storage.open_account("Bob".to_string(), m(0)).unwrap();
+        let shared = SharedStorage::new(storage).unwrap();
+
+        let chain = Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(5) }
+            + Transfer { from: "Bob".into(), to: "Alice".into(), amount: m(50) };
+        assert!(matches!(shared.commit(&chain), Err(TxError::InsufficientFunds { .. })));
+        assert!(matches!(shared.commit(&Deposit { account: "Nobody".into(), amount: m(1) }), Err(TxError::InvalidAccount)));
+        assert!(matches!(shared.commit(&Deposit { account: CASH_VAULT.into(), amount: m(1) }), Err(TxError::InvalidAccount)));
+        assert!(matches!(shared.commit(&Deposit { account: "Bob".into(), amount: m(0) }), Err(TxError::InvalidAmount)));
+        assert_eq!(shared.get_balance("Alice"), Some(m(10)));
+        assert_eq!(shared.get_balance("Bob"), Some(m(0)));
+
+        // Применённые транзакции проводятся в хранилище и попадают в историю
+        shared.commit(&Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(4) }).unwrap();
+        let storage = shared.into_inner().unwrap();
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(4)));
+        assert_eq!(storage.history().for_account("Bob").last().map(|entry| entry.kind), Some(EntryKind::TransferIn));
+
+        // Подтверждённая здесь транзакция не попала бы в журнал — хранилище с журналом не принимается
+        let dir = temp_dir("shared-journal");
+        let journaled = Storage::load_data(dir.join("balance.csv").to_str().unwrap()).unwrap();
+        assert!(matches!(SharedStorage::new(journaled), Err(StorageError::Unsupported(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Комиссии здесь не берутся, поэтому при тарифе транзакции отклоняются
+        let mut storage = Storage::new();
+        storage.open_account("Alice".to_string(), m(10)).unwrap();
+        storage.set_fees("withdraw flat 1".parse().unwrap()).unwrap();
+        let shared = SharedStorage::new(storage).unwrap();
+        let withdraw = Withdraw { account: "Alice".into(), amount: m(1) };
+        assert!(matches!(shared.commit(&withdraw), Err(TxError::Storage(StorageError::Unsupported(_)))));
+        assert_eq!(shared.get_balance("Alice"), Some(m(10)));
+    }
+
+    #[test]
+    fn test_server_commands() {
+        let mut storage = Storage::new();
+        let mut run = |line: &str| handle_command(&mut storage, line).to_string();
+        assert_eq!(run("add Alice 100"), "OK");
+        assert_eq!(run("add Bob"), "OK");
+        assert_eq!(run("deposit Bob 5.5"), "OK 5.50");
+        assert_eq!(run("withdraw Alice 10"), "OK 90.00");
+        assert_eq!(run("transfer Alice Bob 30 retry-1"), "OK");
+        assert_eq!(run("transfer Alice Bob 30 retry-1"), "OK");
+        assert_eq!(run("list"), "OK Alice=60.00 Bob=35.50");
+        assert_eq!(run("balance Bob"), "OK 35.50");
+        assert_eq!(run("withdraw Bob 100"), "ERR Не хватает денег на балансе: доступно 35.50, требуется 100.00");
+        assert_eq!(run("deposit Bob abc"), "ERR \"abc\" не является суммой");
+        assert!(run("balance Nobody").starts_with("ERR "));
+        assert!(run("transfer Alice").starts_with("ERR "));
+        assert_eq!(Response::parse("OK 1.00\n"), Some(Response::Ok("1.00".into())));
+        assert_eq!(Response::parse("ERR нет"), Some(Response::Err("нет".into())));
+    }
+
+    #[test]
+    fn test_server_serves_concurrent_clients() {
+        use std::net::{TcpListener, TcpStream};
+        use std::sync::{Arc, Mutex};
+        use std::thread;
+
+        let mut storage = Storage::new();
+        storage.open_account("Alice".into(), m(0)).unwrap();
+        let storage = Arc::new(Mutex::new(storage));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        const CLIENTS: usize = 4;
+        let server = {
+            let storage = Arc::clone(&storage);
+            thread::spawn(move || {
+                let handlers: Vec<_> = listener
+                    .incoming()
+                    .take(CLIENTS)
+                    .map(|stream| {
+                        let (stream, storage) = (stream.unwrap(), Arc::clone(&storage));
+                        thread::spawn(move || serve_client(stream, &storage).unwrap())
+                    })
+                    .collect();
+                handlers.into_iter().for_each(|handler| handler.join().unwrap());
+            })
+        };
+
+        // Все клиенты подключены одновременно и шлют команды вперемешку
+        let clients: Vec<_> = (0..CLIENTS)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    stream.set_nodelay(true).unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    for _ in 0..25 {
+                        writeln!(stream, "deposit Alice 1").unwrap();
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        assert!(matches!(Response::parse(&line), Some(Response::Ok(_))));
+                    }
+                    writeln!(stream, "quit").unwrap();
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    assert_eq!(line.trim_end(), "OK bye");
+                })
+            })
+            .collect();
+        clients.into_iter().for_each(|client| client.join().unwrap());
+        server.join().unwrap();
+
+        assert_eq!(storage.lock().unwrap().get_balance(&"Alice".into()), Some(m(100)));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let text = r#"{"name":"Анна \"А\"","amount":10.50,"tags":[true,null,-1e3],"empty":{}}"#;
+        let json: Json = text.parse().unwrap();
+        assert_eq!(json.to_string(), text);
+        assert_eq!(json.get("amount").and_then(Json::as_number_text), Some("10.50"));
+        assert_eq!(json.get("name").and_then(Json::as_str), Some("Анна \"А\""));
+        assert_eq!(" [ 1 , \"\\u0041\\n\" ] ".parse::<Json>().unwrap().to_string(), "[1,\"A\\n\"]");
+        assert_eq!("[1,]".parse::<Json>(), Err("позиция 3: неожиданный символ".to_string()));
+        assert!("{\"a\":1} x".parse::<Json>().is_err());
+    }
+
+    #[test]
+    fn test_http_routes() {
+        let mut storage = Storage::new();
+        let mut call = |method: &str, path: &str, headers: &[(&str, &str)], body: &str| {
+            let request = Request {
+                method: method.into(),
+                path: path.into(),
+                headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+                body: body.into(),
+            };
+            let response = route(&mut storage, &request);
+            (response.status, response.body.to_string())
+        };
+
+        assert_eq!(call("POST", "/accounts", &[], r#"{"name":"Alice","balance":"100"}"#).0, 201);
+        assert_eq!(call("POST", "/accounts", &[], r#"{"name":"Боб"}"#).0, 201);
+        assert_eq!(call("POST", "/accounts", &[], r#"{"name":"Alice"}"#).0, 409);
+        assert_eq!(call("POST", "/accounts", &[], "{").0, 400);
+        let (status, body) = call("POST", "/accounts", &[], r#"{"name":"john smith"}"#);
+        assert_eq!((status, body.contains(r#""error":"invalid_name""#)), (400, true));
+
+        let transfer = r#"{"type":"transfer","from":"Alice","to":"Боб","amount":30.5}"#;
+        let key = [("idempotency-key", "pay-1")];
+        let applied = (200, r#"{"status":"applied","balances":{"Alice":"69.50","Боб":"30.50"}}"#.to_string());
+        assert_eq!(call("POST", "/transactions", &key, transfer), applied);
+        assert_eq!(call("POST", "/transactions", &key, transfer), applied);
+        let other = r#"{"type":"transfer","from":"Alice","to":"Боб","amount":1}"#;
+        assert_eq!(call("POST", "/transactions", &key, other).0, 409);
+
+        let batch = r#"{"type":"batch","steps":[
+            {"type":"deposit","account":"Alice","amount":"1"},
+            {"type":"withdraw","account":"Боб","amount":"100"}]}"#;
+        let (status, body) = call("POST", "/transactions", &[], batch);
+        assert_eq!(status, 422);
+        assert!(body.contains(r#""error":"insufficient_funds""#) && body.ends_with(r#""step":1,"available":"30.50","requested":"100.00"}"#));
+        assert_eq!(call("POST", "/transactions", &[], r#"{"type":"deposit","account":"Nobody","amount":1}"#).0, 404);
+        assert_eq!(call("POST", "/transactions", &[], r#"{"type":"deposit","account":"Alice","amount":0}"#).0, 400);
+
+        assert_eq!(call("GET", "/accounts", &[], "").1, r#"[{"name":"Alice","balance":"69.50"},{"name":"Боб","balance":"30.50"}]"#);
+        assert_eq!(call("GET", "/accounts/%D0%91%D0%BE%D0%B1", &[], "").1, r#"{"name":"Боб","balance":"30.50","held":"0.00","available":"30.50"}"#);
+        assert_eq!(call("GET", "/accounts/Nobody", &[], "").0, 404);
+        let (status, history) = call("GET", "/accounts/Alice/history", &[], "");
+        assert_eq!(status, 200);
+        assert!(history.contains(r#""kind":"transfer_out","counterparty":"Боб","amount":"30.50","balance":"69.50""#));
+        assert_eq!(call("DELETE", "/accounts", &[], "").0, 405);
+        assert_eq!(call("GET", "/nowhere", &[], "").0, 404);
+
+        let raw = "POST /transactions?x=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\n{}";
+        let request = read_request(Cursor::new(raw)).unwrap().unwrap();
+        assert_eq!((request.path.as_str(), request.header("content-length"), request.body.as_str()), ("/transactions", Some("2"), "{}"));
+        let mut out = Vec::new();
+        HttpResponse::error(404, "not_found", "нет").write_to(&mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_http_limits_request_head() {
+        use std::io::{ErrorKind, Read};
+
+        // Бесконечная строка не читается целиком: отказ наступает на границе
+        let endless = |head: &'static str| BufReader::new(head.as_bytes().chain(std::io::repeat(b'a')));
+        assert_eq!(read_request(endless("")).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_request(endless("GET / HTTP/1.1\r\nX-Long: ")).unwrap_err().kind(), ErrorKind::FileTooLarge);
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X: 1\r\n".repeat(101));
+        assert_eq!(read_request(Cursor::new(many)).unwrap_err().kind(), ErrorKind::FileTooLarge);
+        let enough = format!("GET / HTTP/1.1\r\n{}\r\n", "X: 1\r\n".repeat(100));
+        assert_eq!(read_request(Cursor::new(enough)).unwrap().unwrap().headers.len(), 100);
+
+        let mut out = Vec::new();
+        HttpResponse::error(431, "headers_too_large", "много").write_to(&mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_http_listens_on_loopback_only() {
+        assert_eq!(loopback_addrs("127.0.0.1:8080").unwrap(), ["127.0.0.1:8080".parse().unwrap()]);
+        assert!(loopback_addrs("[::1]:8080").is_ok());
+        for addr in ["0.0.0.0:8080", "[::]:8080", "192.168.1.10:8080"] {
+            assert_eq!(loopback_addrs(addr).unwrap_err().kind(), std::io::ErrorKind::PermissionDenied, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn test_events_are_published_after_success_only() {
+        use std::sync::{Arc, Mutex};
+        let mut storage = Storage::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let id = storage.subscribe(move |event: &Event| sink.lock().unwrap().push(event.clone()));
+        let take = || std::mem::take(&mut *events.lock().unwrap());
+
+        storage.open_account("Alice".into(), m(100)).unwrap();
+        assert_eq!(
+            take(),
+            vec![
+                Event::AccountOpened { account: "Alice".into() },
+                Event::Deposited { account: "Alice".into(), amount: m(100), balance: m(100) },
+            ]
+        );
+
+        // Первый шаг прошёл, но пакет откатился — подписчик видит только отказ
+        storage.add_user("Bob".into());
+        take();
+        let batch = Batch::new()
+            .with(Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) })
+            .with(Withdraw { account: "Bob".into(), amount: m(50) });
+        assert!(storage.commit(&batch).is_err());
+        let failed = take();
+        assert_eq!(failed.len(), 1);
+        assert!(matches!(&failed[0], Event::TransactionFailed { operations, .. } if operations.len() == 2));
+
+        // Derive-транзакция, применённая напрямую, публикует и успех, и отказ
+        Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) }.apply(&mut storage).unwrap();
+        assert_eq!(take(), vec![Event::Transferred { from: "Alice".into(), to: "Bob".into(), amount: m(30) }]);
+        assert!(Withdraw { account: "Bob".into(), amount: m(50) }.apply(&mut storage).is_err());
+        assert!(matches!(take().as_slice(), [Event::TransactionFailed { .. }]));
+
+        storage.withdraw(&"Bob".into(), m(10)).unwrap();
+        assert_eq!(take(), vec![Event::Withdrawn { account: "Bob".into(), amount: m(10), balance: m(20) }]);
+
+        assert!(storage.unsubscribe(id));
+        storage.close_account(&"Bob".into()).unwrap();
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn test_recurrence_dates() {
+        let date = |s: &str| parse_date(s).unwrap();
+        let monthly: Recurrence = "monthly:31".parse().unwrap();
+        assert_eq!(monthly.first_on_or_after(date("2024-02-10")), date("2024-02-29"));
+        assert_eq!(monthly.next_after(date("2024-02-29")), date("2024-03-31"));
+        assert_eq!(monthly.next_after(date("2024-12-31")), date("2025-01-31"));
+        let first: Recurrence = "monthly:1".parse().unwrap();
+        assert_eq!(first.first_on_or_after(date("2024-01-01") + 1), date("2024-02-01"));
+        let weekly: Recurrence = "every:7".parse().unwrap();
+        assert_eq!(weekly.first_on_or_after(date("2024-01-01") + 1), date("2024-01-02"));
+        assert_eq!(weekly.next_after(date("2024-01-02")), date("2024-01-09"));
+        assert!("monthly:32".parse::<Recurrence>().is_err());
+        assert!("every:0".parse::<Recurrence>().is_err());
+        assert_eq!(weekly.to_string(), "every:7");
+    }
+
+    #[test]
+    fn test_scheduled_payments_retry_and_survive_restart() {
+        let dir = temp_dir("schedule");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let date = |s: &str| parse_date(s).unwrap();
+        let scheduler = Scheduler::new(ManualClock::new(date("2024-01-15")));
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(150) }).unwrap();
+        let schedule = Schedule::new("Alice".into(), "Bob".into(), m(100), "monthly:31".parse().unwrap(), date("2024-01-15"))
+            .with_retry(RetryPolicy { max_attempts: 2, delay: SECONDS_PER_DAY });
+        let id = storage.add_schedule(schedule).unwrap();
+        assert!(scheduler.run_due(&mut storage).unwrap().is_empty());
+
+        // Часы перемотаны на месяц: январский платёж проходит задним числом, февральский — нет
+        scheduler.clock().set(date("2024-02-29") + 12 * 3600);
+        let runs = scheduler.run_due(&mut storage).unwrap();
+        let statuses: Vec<RunStatus> = runs.iter().map(|run| run.status).collect();
+        assert_eq!(statuses, [RunStatus::Applied, RunStatus::Retry]);
+        assert_eq!(runs[1].due, date("2024-02-29"));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(100)));
+        let transfer_in = storage.history().for_account("Bob").last().unwrap();
+        assert_eq!(transfer_in.timestamp, date("2024-01-31"));
+        storage.compact(file, &SaveOptions::default()).unwrap();
+
+        scheduler.clock().advance(SECONDS_PER_DAY);
+        let runs = scheduler.run_due(&mut storage).unwrap();
+        assert_eq!((runs.len(), runs[0].status, runs[0].attempt), (1, RunStatus::GaveUp, 2));
+        assert_eq!(storage.schedules().get(id).unwrap().due, date("2024-03-31"));
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+        let (schedules, runs) = (storage.schedules().iter().cloned().collect::<Vec<_>>(), storage.schedules().runs().to_vec());
+        drop(storage);
+
+        // Часть попыток — из снапшота, часть — из журнала
+        let mut storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.schedules().iter().cloned().collect::<Vec<_>>(), schedules);
+        assert_eq!(storage.schedules().runs(), runs.as_slice());
+        scheduler.clock().set(date("2024-03-31"));
+        assert_eq!(scheduler.run_due(&mut storage).unwrap()[0].status, RunStatus::Applied);
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(200)));
+
+        storage.cancel_schedule(id).unwrap();
+        assert!(matches!(storage.cancel_schedule(id), Err(StorageError::ScheduleNotFound(_))));
+        let schedule = Schedule::new("Alice".into(), "Bob".into(), m(1), Recurrence::EveryDays(1), 0);
+        assert_eq!(storage.add_schedule(schedule).unwrap(), id + 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_interest_product_parse_and_rounding() {
+        let product: InterestProduct = "4.75%,act/365,monthly,quarterly".parse().unwrap();
+        assert_eq!(product.rate, Rate::from_ppm(47_500));
+        assert_eq!(product.rounding, Rounding::HalfEven);
+        assert_eq!(product.to_string(), "4.75%,act/365,monthly,quarterly,half_even");
+        assert_eq!("-0.5,act/act,simple,yearly,down".parse::<InterestProduct>().unwrap().to_string(), "-0.5%,act/act,simple,yearly,down");
+        assert!("4.12345%,act/365,simple,monthly".parse::<InterestProduct>().is_err());
+        assert!("4%,30/360,simple,monthly".parse::<InterestProduct>().is_err());
+
+        let units = |cents: f64| (cents * ACCRUAL_UNITS_PER_MINOR as f64) as i128;
+        assert_eq!(Rounding::HalfEven.round(units(2.5)), 2);
+        assert_eq!(Rounding::HalfEven.round(units(3.5)), 4);
+        assert_eq!(Rounding::HalfUp.round(units(2.5)), 3);
+        assert_eq!(Rounding::HalfUp.round(units(-2.5)), -3);
+        assert_eq!(Rounding::Down.round(units(2.9)), 2);
+        assert!(Period::Quarterly.ends_on(parse_date("2024-03-31").unwrap()));
+        assert!(!Period::Quarterly.ends_on(parse_date("2024-04-30").unwrap()));
+
+        // Невыплаченное больше, чем помещается в Balance, — ошибка, а не обрезанное число
+        let mut accrual = Accrual::new(product, 0);
+        accrual.capitalized = 2 * i128::from(i64::MAX) * ACCRUAL_UNITS_PER_MINOR;
+        assert!(matches!(accrual.unposted(), Err(StorageError::Overflow)));
+        accrual.capitalized = 150 * ACCRUAL_UNITS_PER_MINOR;
+        assert_eq!(accrual.unposted().unwrap(), Balance::from_minor(150));
+    }
+
+    #[test]
+    fn test_interest_accrues_daily_and_survives_restart() {
+        let dir = temp_dir("interest");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        let today = parse_date("2024-03-01").unwrap();
+        storage.set_clock(ManualClock::new(today + 60));
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(1000) }).unwrap();
+        storage.commit(&Deposit { account: "Bob".into(), amount: m(100) }).unwrap();
+        // 3.65% годовых на 1000.00 — ровно 0.10 в день
+        let daily = "3.65%,act/365,simple,daily".parse().unwrap();
+        storage.set_interest(&"Alice".into(), daily, today).unwrap();
+        let yearly = "36.5%,act/365,daily,yearly".parse().unwrap();
+        storage.set_interest(&"Bob".into(), yearly, today).unwrap();
+
+        let posted = storage.accrue_interest(today + SECONDS_PER_DAY).unwrap();
+        assert_eq!(posted, vec![("Alice".to_string(), Balance::from_minor(10))]);
+        assert!(storage.accrue_interest(today + SECONDS_PER_DAY + 60).unwrap().is_empty());
+        storage.compact(file, &SaveOptions::default()).unwrap();
+
+        // Выплаченные проценты тоже приносят проценты, остаток от округления переносится
+        storage.accrue_interest(today + 3 * SECONDS_PER_DAY).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(Balance::from_minor(100_030)));
+        assert_eq!(storage.interest().get("Alice").unwrap().unposted_units(), 1_000_000 + 2_000_000);
+        // Ежедневная капитализация у Bob: 0.10 + 0.1001 + 0.100200... ещё не выплачено
+        let bob = storage.interest().report().unwrap().into_iter().find(|line| line.account == "Bob").unwrap();
+        assert_eq!((bob.unposted, bob.accrued_through), (Balance::from_minor(30), today + 3 * SECONDS_PER_DAY));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(100)));
+
+        let alice: Vec<HistoryEntry> = storage.history().for_account("Alice").cloned().collect();
+        let last = alice.last().unwrap();
+        assert_eq!((last.kind, last.timestamp), (EntryKind::Interest, today + 3 * SECONDS_PER_DAY - 1));
+        assert_eq!(storage.ledger().balance(INTEREST), Balance::from_minor(-30));
+        assert!(storage.trial_balance().unwrap().is_balanced());
+        drop(storage);
+
+        // Начисление после компактизации проигрывается из журнала по истории
+        let mut storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.history().for_account("Alice").cloned().collect::<Vec<_>>(), alice);
+        assert_eq!(storage.interest().report().unwrap().iter().map(|line| line.unposted).collect::<Vec<_>>(), [Balance::from_minor(0), Balance::from_minor(30)]);
+        assert!(storage.clear_interest(&"Bob".into()).unwrap());
+        assert!(!storage.clear_interest(&"Bob".into()).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fee_schedule_parse() {
+        let config = "# тариф\nwithdraw flat 1.00\ntransfer percent 1% min 0.50 cap 3.00/day\n\ndeposit tiered 0:0.10 1000:0.5%  # ступени\n";
+        let schedule: FeeSchedule = config.parse().unwrap();
+        assert_eq!(schedule.to_string(), "withdraw flat 1.00;transfer percent 1% min 0.50 cap 3.00/day;deposit tiered 0.00:0.10 1000.00:0.5%");
+        assert_eq!(schedule.to_string().parse::<FeeSchedule>().unwrap(), schedule);
+
+        let fee = |kind, amount| schedule.rule(kind).unwrap().charge.fee(amount).unwrap();
+        assert_eq!(fee(FeeKind::Transfer, m(10)), Balance::from_minor(50));
+        assert_eq!(fee(FeeKind::Transfer, Balance::from_minor(12_345)), Balance::from_minor(123));
+        assert_eq!(fee(FeeKind::Deposit, m(999)), Balance::from_minor(10));
+        assert_eq!(fee(FeeKind::Deposit, m(2000)), m(10));
+        let cap = schedule.rule(FeeKind::Transfer).unwrap().cap.unwrap();
+        assert_eq!(cap, Cap { limit: m(3), period: CapPeriod::Day });
+
+        assert_eq!("withdraw flat 1\nwithdraw flat 2".parse::<FeeSchedule>().unwrap_err().0, 2);
+        assert!("withdraw flat -1".parse::<FeeRule>().is_err());
+        assert!("transfer tiered 100:1 10:2".parse::<FeeRule>().is_err());
+        assert!("transfer percent 1% cap 3/week".parse::<FeeRule>().is_err());
+        let time = parse_date("2024-02-17").unwrap() + 3600;
+        assert_eq!(CapPeriod::Month.start(time), parse_date("2024-02-01").unwrap());
+        assert_eq!(CapPeriod::Day.start(time), parse_date("2024-02-17").unwrap());
+    }
+
+    #[test]
+    fn test_fees_charged_with_transaction_and_survive_restart() {
+        let dir = temp_dir("fees");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let config = dir.join("fees.conf");
+        std::fs::write(&config, "withdraw flat 1.00\ntransfer percent 1% cap 3.00/day\n").unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.load_fees(&config).unwrap();
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(1000) }).unwrap();
+        storage.commit(&Deposit { account: "Bob".into(), amount: m(100) }).unwrap();
+
+        // Средств хватает на сумму, но не на сумму с комиссией — не меняется ничего
+        let withdraw = Withdraw { account: "Bob".into(), amount: Balance::from_minor(9_950) };
+        assert!(matches!(storage.commit(&withdraw), Err(TxError::InsufficientFunds { .. })));
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(100)));
+        storage.commit(&Withdraw { account: "Bob".into(), amount: m(99) }).unwrap();
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(Balance::ZERO));
+
+        // Лимит за день: 1.00 + 1.50, от третьего перевода — только остаток лимита
+        for amount in [100, 150, 200, 100] {
+            storage.commit(&Transfer { from: "Alice".into(), to: "John".into(), amount: m(amount) }).unwrap();
+        }
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(1000 - 550 - 3)));
+        assert_eq!(storage.ledger().balance(FEES), m(4));
+        let fees: Vec<Balance> = storage
+            .history()
+            .for_account("Alice")
+            .filter(|entry| entry.kind == EntryKind::Fee)
+            .map(|entry| entry.amount)
+            .collect();
+        assert_eq!(fees, [m(1), Balance::from_minor(150), Balance::from_minor(50)]);
+        assert!(storage.trial_balance().unwrap().is_balanced());
+        let balances = storage.ledger().balances().map(|(name, balance)| (name.clone(), *balance)).collect::<Vec<_>>();
+        drop(storage);
+
+        // Журнал проигрывается по тарифу, действовавшему в момент записи
+        let mut storage = Storage::load_data(file).unwrap();
+        let mut restored = storage.ledger().balances().map(|(name, balance)| (name.clone(), *balance)).collect::<Vec<_>>();
+        let mut expected = balances;
+        restored.sort();
+        expected.sort();
+        assert_eq!(restored, expected);
+        assert_eq!(storage.fees().rules().len(), 2);
+
+        // Без файла комиссии отключаются
+        storage.load_fees(&dir.join("missing.conf")).unwrap();
+        storage.commit(&Withdraw { account: "John".into(), amount: m(10) }).unwrap();
+        assert_eq!(storage.get_balance(&"John".into()), Some(m(540)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_overdraft_limit_and_interest() {
+        let dir = temp_dir("overdraft");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        let bob: Name = "Bob".into();
+        let today = parse_date("2024-03-01").unwrap();
+        storage.set_overdraft(&bob, m(50)).unwrap();
+        storage.set_clock(ManualClock::new(today + 60));
+        storage.commit(&Withdraw { account: bob.clone(), amount: m(40) }).unwrap();
+        assert_eq!((storage.get_balance(&bob), storage.available(&bob)), (Some(m(-40)), Some(m(10))));
+        let result = storage.commit(&Transfer { from: bob.clone(), to: "Alice".into(), amount: m(20) });
+        assert!(matches!(result, Err(TxError::InsufficientFunds { available, requested }) if available == m(10) && requested == m(20)));
+        assert!(matches!(storage.set_overdraft(&bob, m(-1)), Err(StorageError::InvalidAmount(_))));
+
+        // Ставка овердрафта действует только на отрицательный остаток: 36.5% годовых — 0.1% в день
+        let product: InterestProduct = "1%,act/365,simple,daily,half_even,36.5%".parse().unwrap();
+        assert_eq!(product.to_string().parse::<InterestProduct>(), Ok(product));
+        storage.set_interest(&bob, product, today).unwrap();
+        storage.accrue_interest(today + SECONDS_PER_DAY).unwrap();
+        assert_eq!(storage.get_balance(&bob), Some(Balance::from_minor(-4_004)));
+        storage.compact(file, &SaveOptions::default()).unwrap();
+
+        // Долг сверх нового лимита можно только гасить
+        storage.set_overdraft(&bob, m(20)).unwrap();
+        storage.commit(&Deposit { account: bob.clone(), amount: m(10) }).unwrap();
+        assert!(matches!(storage.commit(&Withdraw { account: bob.clone(), amount: m(1) }), Err(TxError::InsufficientFunds { .. })));
+        drop(storage);
+
+        let mut storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.overdraft_limit("Bob"), m(20));
+        storage.set_overdraft(&bob, Balance::ZERO).unwrap();
+        assert_eq!(storage.available(&bob), Some(Balance::ZERO));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_holds_capture_release_and_expire() {
+        use std::sync::Arc;
+
+        let dir = temp_dir("holds");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        let clock = Arc::new(ManualClock::new(parse_date("2024-03-01").unwrap()));
+        storage.set_clock(Arc::clone(&clock));
+        let alice: Name = "Alice".into();
+        storage.commit(&Deposit { account: alice.clone(), amount: m(100) }).unwrap();
+        let card = storage.authorize(&alice, m(60), SECONDS_PER_DAY).unwrap();
+        assert_eq!((storage.get_balance(&alice), storage.held("Alice"), storage.available(&alice)), (Some(m(100)), m(60), Some(m(40))));
+
+        // Все проверки — по доступной сумме, а не по балансу
+        let result = storage.commit(&Withdraw { account: alice.clone(), amount: m(50) });
+        assert!(matches!(result, Err(TxError::InsufficientFunds { available, requested }) if available == m(40) && requested == m(50)));
+        assert!(matches!(storage.authorize(&alice, m(41), SECONDS_PER_DAY), Err(StorageError::InsufficientFunds { .. })));
+
+        // Частичный захват переводом: остаток остаётся удержанным
+        storage.capture(card, Some(m(20)), Some(&"Bob".into())).unwrap();
+        assert_eq!(storage.get_balance(&"Bob".into()), Some(m(20)));
+        let hold = storage.holds().get(card).unwrap();
+        assert_eq!((hold.amount, hold.captured), (m(40), m(20)));
+        assert_eq!(storage.available(&alice), Some(m(40)));
+        assert!(matches!(storage.capture(card, Some(m(41)), None), Err(TxError::InvalidAmount)));
+        storage.compact(file, &SaveOptions::default()).unwrap();
+
+        storage.capture(card, None, None).unwrap();
+        assert!(storage.holds().get(card).is_none());
+        assert_eq!((storage.get_balance(&alice), storage.available(&alice)), (Some(m(40)), Some(m(40))));
+        let released = storage.authorize(&alice, m(30), SECONDS_PER_DAY).unwrap();
+        assert_eq!(storage.release(released).unwrap().amount, m(30));
+        assert!(matches!(storage.release(released), Err(StorageError::HoldNotFound(_))));
+        let expiring = storage.authorize(&alice, m(10), SECONDS_PER_DAY).unwrap();
+        clock.advance(SECONDS_PER_DAY - 1);
+        assert!(storage.expire_holds(clock.now()).unwrap().is_empty());
+        clock.advance(SECONDS_PER_DAY);
+        assert_eq!(storage.expire_holds(clock.now()).unwrap()[0].id, expiring);
+        let pending = storage.authorize(&alice, m(5), SECONDS_PER_DAY).unwrap();
+        assert!(storage.trial_balance().unwrap().is_balanced());
+        drop(storage);
+
+        // Захваты и снятия проигрываются из журнала; номера не переиспользуются
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.set_clock(clock);
+        assert_eq!((storage.get_balance(&alice), storage.get_balance(&"Bob".into())), (Some(m(40)), Some(m(20))));
+        assert_eq!(storage.holds().iter().map(|hold| hold.id).collect::<Vec<_>>(), [pending]);
+        assert_eq!(storage.available(&alice), Some(m(35)));
+        assert_eq!(storage.authorize(&alice, m(1), SECONDS_PER_DAY).unwrap(), pending + 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_account_names_are_rejected() {
+        let dir = temp_dir("names");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+
+        let mut storage = Storage::load_data(file).unwrap();
+        for name in ["", "john smith", "a,b", "a;b", "tab\there", "#journal=1", "@cash"] {
+            assert!(matches!(storage.open_account(name.to_string(), m(5)), Err(StorageError::InvalidName(_))), "{:?}", name);
+            assert_eq!(storage.add_user(name.to_string()), None);
+        }
+        storage.open_account("Анна-Мария_1".to_string(), m(5)).unwrap();
+        drop(storage);
+
+        // Журнал после отказов читается, а допустимое имя переживает перезапуск
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.get_balance(&"Анна-Мария_1".into()), Some(m(5)));
+        assert_eq!(storage.get_all().len(), 5);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_atomically_rolls_back_every_book() {
+        let (alice, bob) = ("Alice".to_string(), "Bob".to_string());
+        let mut storage = Storage::new();
+        storage.open_account(alice.clone(), m(100)).unwrap();
+        storage.open_account(bob.clone(), m(50)).unwrap();
+        let kept = storage.authorize(&alice, m(5), SECONDS_PER_DAY).unwrap();
+
+        let failed: Result<(), StorageError> = storage.atomically(|storage| {
+            storage.set_overdraft(&alice, m(20))?;
+            storage.authorize(&alice, m(10), SECONDS_PER_DAY)?;
+            storage.add_schedule(Schedule::new(alice.clone(), bob.clone(), m(1), Recurrence::EveryDays(1), 0))?;
+            storage.set_interest(&alice, "1%,act/365,simple,daily".parse().unwrap(), 0)?;
+            storage.set_fees("withdraw flat 1".parse().unwrap())?;
+            storage.close_account(&bob)?;
+            storage.withdraw(&alice, m(100))?;
+            storage.withdraw(&alice, m(100))
+        });
+        assert!(matches!(failed, Err(StorageError::InsufficientFunds { .. })));
+
+        // Всё, что успела сделать транзакция, откачено, включая счётчики номеров
+        assert_eq!((storage.get_balance(&alice), storage.get_balance(&bob)), (Some(m(100)), Some(m(50))));
+        assert_eq!(storage.overdraft_limit(&alice), m(0));
+        assert_eq!(storage.holds().iter().map(|hold| hold.id).collect::<Vec<_>>(), [kept]);
+        assert_eq!(storage.holds().next_id(), kept + 1);
+        assert!(storage.schedules().is_empty());
+        assert_eq!(storage.schedules().next_id(), 1);
+        assert!(storage.interest().is_empty());
+        assert!(storage.fees().rule(FeeKind::Withdraw).is_none());
+        assert_eq!(storage.history().entries().len(), 4);
+        assert!(storage.trial_balance().unwrap().is_balanced());
+        assert_eq!(storage.ledger().balance("@cash"), m(-150));
+    }
+
+    #[test]
+    fn test_key_reused_for_other_transaction_conflicts() {
+        let dir = temp_dir("key-conflict");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let tx = Deposit { account: "Alice".into(), amount: m(10) };
+        let other = Deposit { account: "Alice".into(), amount: m(11) };
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit_keyed("pay-1", &tx).unwrap();
+        let conflict = |result| matches!(result, Err(TxError::Storage(StorageError::KeyConflict(_))));
+        assert!(conflict(storage.commit_keyed("pay-1", &other)));
+        storage.commit_keyed("pay-2", &other).unwrap();
+        storage.compact(file, &SaveOptions::default()).unwrap();
+        storage.commit_keyed("pay-3", &tx).unwrap();
+        drop(storage);
+
+        // Отпечатки восстанавливаются и из снапшота, и из журнала
+        let mut storage = Storage::load_data(file).unwrap();
+        assert!(conflict(storage.commit_keyed("pay-1", &other)));
+        assert!(conflict(storage.commit_keyed("pay-3", &other)));
+        storage.commit_keyed("pay-1", &tx).unwrap();
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(31)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_scheduler_tick_expires_holds_and_reports() {
+        let alice = "Alice".to_string();
+        let mut storage = Storage::new();
+        storage.open_account(alice.clone(), m(10)).unwrap();
+        let now = parse_date("2024-03-01").unwrap();
+        storage.set_clock(ManualClock::new(now));
+        let id = storage.authorize(&alice, m(4), SECONDS_PER_DAY).unwrap();
+
+        let scheduler = Scheduler::new(ManualClock::new(now + 2 * SECONDS_PER_DAY));
+        let tick = scheduler.tick(&mut storage);
+        assert_eq!(tick.expired.as_ref().unwrap().iter().map(|hold| hold.id).collect::<Vec<_>>(), [id]);
+        assert_eq!(tick.to_string(), format!("Удержание #{} на счёте Alice истекло: 4.00 снова доступно\n", id));
+        assert_eq!(storage.available(&alice), Some(m(10)));
+        assert_eq!(scheduler.tick(&mut storage).to_string(), "");
+    }
+
+    #[test]
+    fn test_transfer_to_self_needs_funds() {
+        let alice = "Alice".to_string();
+        let mut storage = Storage::new();
+        storage.add_user(alice.clone());
+        let opened = storage.history().len();
+        let transfer = Transfer { from: alice.clone(), to: alice.clone(), amount: m(1000) };
+        assert!(matches!(
+            storage.transfer(&alice, &alice, m(1000)),
+            Err(StorageError::InsufficientFunds { available, requested }) if available == Balance::ZERO && requested == m(1000)
+        ));
+        assert!(matches!(storage.commit(&transfer), Err(TxError::InsufficientFunds { .. })));
+        assert_eq!(storage.history().len(), opened);
+
+        // С деньгами на счёте перевод самому себе проходит и баланс не меняет
+        storage.deposit(&alice, m(1000)).unwrap();
+        storage.commit(&transfer).unwrap();
+        assert_eq!(storage.get_balance(&alice), Some(m(1000)));
+        assert!(storage.trial_balance().unwrap().is_balanced());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_failed_journal_append_is_not_kept() {
+        let dir = temp_dir("journal_full");
+        let path = dir.join("balance.csv.journal");
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        let record = Record::Close { name: "Bob".into() };
+
+        // /dev/full открывается, но отказывает и в записи, и в обрезке
+        std::os::unix::fs::symlink("/dev/full", &path).unwrap();
+        assert!(matches!(journal.append(1, record.clone()), Err(StorageError::Io(_))));
+        std::fs::remove_file(&path).unwrap();
+
+        // Стереть неудачную запись не вышло — дописывать после неё журнал больше не будет
+        assert!(matches!(journal.append(2, record.clone()), Err(StorageError::Io(_))));
+        assert!(!path.exists());
+        assert_eq!(journal.last_seq(), 0);
+
+        let (mut journal, records) = Journal::open(&path).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(journal.append(3, record).unwrap(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_closing_account_cancels_its_schedules() {
+        let dir = temp_dir("close_schedules");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        let date = |s: &str| parse_date(s).unwrap();
+        let scheduler = Scheduler::new(ManualClock::new(date("2024-01-01")));
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.commit(&Deposit { account: "John".into(), amount: m(100) }).unwrap();
+        for (from, to) in [("Alice", "Bob"), ("John", "Alice"), ("Bob", "John")] {
+            storage.add_schedule(Schedule::new(from.into(), to.into(), m(1), Recurrence::EveryDays(1), date("2024-01-01"))).unwrap();
+        }
+        storage.close_account(&"Bob".into()).unwrap();
+        let left: Vec<(Name, Name)> = storage.schedules().iter().map(|schedule| (schedule.from.clone(), schedule.to.clone())).collect();
+        assert_eq!(left, [("John".to_string(), "Alice".to_string())]);
+
+        // Платёж закрытого счёта не отказывает при каждом запуске — его больше нет
+        scheduler.clock().set(date("2024-01-03"));
+        let runs = scheduler.run_due(&mut storage).unwrap();
+        assert!(runs.iter().all(|run| run.status == RunStatus::Applied), "{:?}", runs);
+        assert_eq!(runs.len(), 3);
+        drop(storage);
+
+        let storage = Storage::load_data(file).unwrap();
+        assert_eq!(storage.schedules().len(), 1);
+        assert_eq!(storage.get_balance(&"Alice".into()), Some(m(3)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_old_history_moves_to_archive_on_compaction() {
+        use std::sync::Arc;
+
+        let dir = temp_dir("history_archive");
+        let file = dir.join("balance.csv");
+        let file = file.to_str().unwrap();
+        // Счета по умолчанию открываются по системным часам, поэтому время идёт от будущей даты
+        let start = parse_date("2100-01-01").unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.set_clock(Arc::clone(&clock));
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(100) }).unwrap();
+        storage.commit(&Transfer { from: "Alice".into(), to: "Bob".into(), amount: m(30) }).unwrap();
+        let first = storage.history().entries()[0].seq;
+        let old = storage.history().len();
+
+        clock.advance((Storage::HISTORY_DAYS + 1) * SECONDS_PER_DAY);
+        storage.commit(&Deposit { account: "Alice".into(), amount: m(5) }).unwrap();
+        storage.compact(file, &SaveOptions::default()).unwrap();
+
+        // В снапшоте только недавние записи; старые — в архиве, а балансы после них известны
+        let archive = std::fs::read_to_string(dir.join(format!("balance.csv.history.{}", first))).unwrap();
+        assert_eq!(archive.lines().count(), old);
+        assert_eq!(storage.history().for_account("Alice").count(), 1);
+        assert_eq!(storage.history().balance_at("Alice", start + SECONDS_PER_DAY), Some(m(70)));
+        assert_eq!(storage.history().balance_at("Bob", clock.now() + 1), Some(m(30)));
+        let statement = storage.history().statement("Alice", Some(start), None).unwrap();
+        assert_eq!((statement.opening, statement.closing, statement.entries.len()), (m(70), m(75), 1));
+        let seq = storage.history().entries()[0].seq;
+        drop(storage);
+
+        let mut storage = Storage::load_data(file).unwrap();
+        storage.set_clock(Arc::clone(&clock));
+        assert_eq!(storage.history().archived_before(), clock.now() / SECONDS_PER_DAY * SECONDS_PER_DAY - Storage::HISTORY_DAYS * SECONDS_PER_DAY);
+        assert_eq!(storage.history().statement("Bob", None, None).unwrap().closing, m(30));
+        storage.close_account(&"Bob".into()).unwrap();
+        assert_eq!(storage.history().entries().last().unwrap().seq, seq + 1);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,146 @@
+pub mod money {
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+
+    /// Денежная сумма с фиксированной точкой.
+    ///
+    /// Хранится целым числом минимальных единиц валюты (копеек): `10.50` — это 1050.
+    /// Количество знаков после точки задаётся константой [`Money::SCALE`].
+    /// Вся арифметика проверяемая: переполнение возвращает None, а не паникует
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+    pub struct Money(i64);
+
+    impl Money {
+        /// Сколько знаков после точки хранится (2 — копейки, центы)
+        pub const SCALE: u32 = 2;
+        /// Сколько минимальных единиц в одной целой (10^SCALE)
+        pub const FACTOR: i64 = 10i64.pow(Money::SCALE);
+        pub const ZERO: Money = Money(0);
+        pub const MAX: Money = Money(i64::MAX);
+
+        /// Сумма из минимальных единиц: `from_minor(1050)` — это `10.50`
+        pub const fn from_minor(minor: i64) -> Money {
+            Money(minor)
+        }
+
+        /// Сумма из целых единиц: `from_major(10)` — это `10.00`
+        ///
+        /// # Panics
+        /// Паникует, если сумма не помещается в i64 минимальных единиц
+        pub const fn from_major(major: i64) -> Money {
+            match major.checked_mul(Money::FACTOR) {
+                Some(minor) => Money(minor),
+                None => panic!("сумма не помещается в Money"),
+            }
+        }
+
+        /// Количество минимальных единиц
+        pub const fn minor(self) -> i64 {
+            self.0
+        }
+
+        pub const fn is_zero(self) -> bool {
+            self.0 == 0
+        }
+
+        pub const fn is_positive(self) -> bool {
+            self.0 > 0
+        }
+
+        pub const fn is_negative(self) -> bool {
+            self.0 < 0
+        }
+
+        pub fn checked_add(self, rhs: Money) -> Option<Money> {
+            self.0.checked_add(rhs.0).map(Money)
+        }
+
+        pub fn checked_sub(self, rhs: Money) -> Option<Money> {
+            self.0.checked_sub(rhs.0).map(Money)
+        }
+
+        pub fn checked_neg(self) -> Option<Money> {
+            self.0.checked_neg().map(Money)
+        }
+    }
+
+    impl Display for Money {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let sign = if self.0 < 0 { "-" } else { "" };
+            let abs = self.0.unsigned_abs();
+            let factor = Money::FACTOR as u64;
+            // pad учитывает ширину и выравнивание, заданные в форматной строке
+            f.pad(&format!(
+                "{}{}.{:0width$}",
+                sign,
+                abs / factor,
+                abs % factor,
+                width = Money::SCALE as usize
+            ))
+        }
+    }
+
+    /// Ошибка разбора суммы из строки
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ParseMoneyError {
+        /// Строка не похожа на десятичное число
+        Invalid(String),
+        /// Знаков после точки больше, чем Money::SCALE
+        TooPrecise(String),
+        /// Сумма не помещается в Money
+        Overflow(String),
+    }
+
+    impl Display for ParseMoneyError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ParseMoneyError::Invalid(s) => write!(f, "\"{}\" не является суммой", s),
+                ParseMoneyError::TooPrecise(s) => {
+                    write!(f, "в сумме \"{}\" больше {} знаков после точки", s, Money::SCALE)
+                }
+                ParseMoneyError::Overflow(s) => write!(f, "сумма \"{}\" слишком велика", s),
+            }
+        }
+    }
+
+    impl Error for ParseMoneyError {}
+
+    impl FromStr for Money {
+        type Err = ParseMoneyError;
+
+        /// Разбирает `10`, `10.5`, `10.50`, `-3.01`
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = || ParseMoneyError::Invalid(s.to_string());
+            let (negative, digits) = match s.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, s.strip_prefix('+').unwrap_or(s)),
+            };
+            let (major, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+            let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+            if major.is_empty() || !all_digits(major) || !all_digits(fraction) || digits.ends_with('.') {
+                return Err(invalid());
+            }
+            if fraction.len() > Money::SCALE as usize {
+                return Err(ParseMoneyError::TooPrecise(s.to_string()));
+            }
+
+            let overflow = || ParseMoneyError::Overflow(s.to_string());
+            let major: i64 = major.parse().map_err(|_| overflow())?;
+            let fraction: i64 = if fraction.is_empty() {
+                0
+            } else {
+                // "5" после точки — это 50 копеек, а не 5
+                fraction.parse::<i64>().map_err(|_| invalid())?
+                    * 10i64.pow(Money::SCALE - fraction.len() as u32)
+            };
+
+            let minor = major
+                .checked_mul(Money::FACTOR)
+                .and_then(|m| m.checked_add(fraction))
+                .ok_or_else(overflow)?;
+            Ok(Money(if negative { -minor } else { minor }))
+        }
+    }
+}
@@ -0,0 +1,89 @@
+pub mod persist {
+    use std::fs::{self, File};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+
+    /// Путь к файлу с суффиксом: `balance.csv` + `.tmp` -> `balance.csv.tmp`
+    pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    /// Путь к резервной копии номер `n` (1 — самая свежая)
+    pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+        with_suffix(path, &format!(".bak.{}", n))
+    }
+
+    /// Записывает `data` в `path` так, что при сбое на диске остаётся
+    /// либо старое содержимое файла целиком, либо новое.
+    ///
+    /// Данные пишутся во временный файл рядом с целевым, сбрасываются на диск
+    /// через fsync и только потом атомарно переименовываются поверх `path`.
+    pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+        let tmp = with_suffix(path, ".tmp");
+
+        let result = (|| {
+            let mut file = File::create(&tmp)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+            fs::rename(&tmp, path)?;
+            sync_dir(path)
+        })();
+
+        if result.is_err() {
+            // Недописанный временный файл больше не нужен, целевой файл не тронут
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    /// Сдвигает резервные копии `path.bak.1 .. path.bak.N` на одну позицию
+    /// и копирует текущий `path` в `path.bak.1`. Самая старая копия удаляется.
+    pub fn rotate_backups(path: &Path, keep: usize) -> io::Result<()> {
+        if keep == 0 || !path.exists() {
+            return Ok(());
+        }
+
+        let oldest = backup_path(path, keep);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for n in (1..keep).rev() {
+            let from = backup_path(path, n);
+            if from.exists() {
+                fs::rename(&from, backup_path(path, n + 1))?;
+            }
+        }
+
+        // Копируем, а не переименовываем: целевой файл должен существовать всё время
+        fs::copy(path, backup_path(path, 1))?;
+        Ok(())
+    }
+
+    /// Контрольная сумма FNV-1a (64 бита) — ею помечаются записи журнала,
+    /// чтобы отличить целую запись от оборванной при сбое
+    pub fn checksum(data: &[u8]) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in data {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    /// Сбрасывает на диск каталог, чтобы переименование пережило сбой питания
+    #[cfg(unix)]
+    fn sync_dir(path: &Path) -> io::Result<()> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()
+    }
+
+    #[cfg(not(unix))]
+    fn sync_dir(_path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
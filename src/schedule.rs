@@ -0,0 +1,504 @@
+pub mod schedule {
+    use std::collections::BTreeMap;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+    use crate::calendar::calendar::{self, Clock, SECONDS_PER_DAY, Timestamp, format_date};
+    use crate::holds::holds::Hold;
+    use crate::{Balance, Name, Storage, StorageError, Transfer};
+
+    /// Периодичность платежа. Платёж приходится на начало дня (00:00 UTC)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Recurrence {
+        /// Каждый месяц в день `day`; в коротких месяцах — в последний день
+        Monthly { day: u32 },
+        /// Каждые `n` дней
+        EveryDays(u32),
+    }
+
+    /// Начало дня `day` месяца; день ограничивается длиной месяца
+    fn day_of_month(year: i64, month: u32, day: u32) -> Timestamp {
+        let day = day.min(calendar::days_in_month(year, month));
+        calendar::days_from_civil(year, month, day).max(0) as u64 * SECONDS_PER_DAY
+    }
+
+    fn next_month(year: i64, month: u32) -> (i64, u32) {
+        if month == 12 { (year + 1, 1) } else { (year, month + 1) }
+    }
+
+    impl Recurrence {
+        /// Первый платёж не раньше `time`
+        pub fn first_on_or_after(&self, time: Timestamp) -> Timestamp {
+            match *self {
+                Recurrence::Monthly { day } => {
+                    let (year, month, _) = calendar::civil_from_days((time / SECONDS_PER_DAY) as i64);
+                    let this_month = day_of_month(year, month, day);
+                    if this_month >= time {
+                        return this_month;
+                    }
+                    let (year, month) = next_month(year, month);
+                    day_of_month(year, month, day)
+                }
+                Recurrence::EveryDays(_) => time.div_ceil(SECONDS_PER_DAY) * SECONDS_PER_DAY,
+            }
+        }
+
+        /// Платёж, следующий за платежом `due`
+        pub fn next_after(&self, due: Timestamp) -> Timestamp {
+            match *self {
+                Recurrence::Monthly { day } => {
+                    let (year, month, _) = calendar::civil_from_days((due / SECONDS_PER_DAY) as i64);
+                    let (year, month) = next_month(year, month);
+                    day_of_month(year, month, day)
+                }
+                Recurrence::EveryDays(n) => due + u64::from(n) * SECONDS_PER_DAY,
+            }
+        }
+    }
+
+    /// Текстовый вид: `monthly:<день>` или `every:<дней>`
+    impl Display for Recurrence {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Recurrence::Monthly { day } => write!(f, "monthly:{}", day),
+                Recurrence::EveryDays(n) => write!(f, "every:{}", n),
+            }
+        }
+    }
+
+    impl FromStr for Recurrence {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = || format!("ожидается monthly:<1-31> или every:<дней>: {}", s);
+            let (kind, n) = s.split_once(':').ok_or_else(invalid)?;
+            let n: u32 = n.parse().map_err(|_| invalid())?;
+            match kind {
+                "monthly" if (1..=31).contains(&n) => Ok(Recurrence::Monthly { day: n }),
+                "every" if n > 0 => Ok(Recurrence::EveryDays(n)),
+                _ => Err(invalid()),
+            }
+        }
+    }
+
+    /// Сколько раз пытаться провести платёж и через сколько секунд повторять
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RetryPolicy {
+        /// Всего попыток, включая первую
+        pub max_attempts: u32,
+        pub delay: u64,
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            RetryPolicy { max_attempts: 3, delay: SECONDS_PER_DAY }
+        }
+    }
+
+    /// Регулярный перевод (постоянное поручение)
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Schedule {
+        /// Номер, выданный при добавлении в [`Storage`]
+        pub id: u64,
+        pub from: Name,
+        pub to: Name,
+        pub amount: Balance,
+        pub recurrence: Recurrence,
+        pub retry: RetryPolicy,
+        /// Дата текущего платежа
+        pub due: Timestamp,
+        /// Когда делать следующую попытку: `due` или позже, если были отказы
+        pub next_run: Timestamp,
+        /// Неудачных попыток текущего платежа
+        pub failures: u32,
+    }
+
+    impl Schedule {
+        /// Поручение с первым платежом не раньше `start` и политикой повторов по умолчанию
+        pub fn new(from: Name, to: Name, amount: Balance, recurrence: Recurrence, start: Timestamp) -> Self {
+            let due = recurrence.first_on_or_after(start);
+            Schedule {
+                id: 0,
+                from,
+                to,
+                amount,
+                recurrence,
+                retry: RetryPolicy::default(),
+                due,
+                next_run: due,
+                failures: 0,
+            }
+        }
+
+        pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+            self.retry = retry;
+            self
+        }
+
+        pub fn transfer(&self) -> Transfer {
+            Transfer { from: self.from.clone(), to: self.to.clone(), amount: self.amount }
+        }
+
+        /// Номер очередной попытки текущего платежа, с единицы
+        pub fn attempt(&self) -> u32 {
+            self.failures + 1
+        }
+
+        /// Итог неудачной попытки: повторить или отказаться от этого платежа
+        pub fn status_after_failure(&self) -> RunStatus {
+            if self.attempt() >= self.retry.max_attempts {
+                RunStatus::GaveUp
+            } else {
+                RunStatus::Retry
+            }
+        }
+    }
+
+    /// Текстовый вид: `id,from,to,amount,recurrence,max_attempts,delay,due,next_run,failures`
+    impl Display for Schedule {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "{},{},{},{},{},{},{},{},{},{}",
+                self.id,
+                self.from,
+                self.to,
+                self.amount,
+                self.recurrence,
+                self.retry.max_attempts,
+                self.retry.delay,
+                self.due,
+                self.next_run,
+                self.failures
+            )
+        }
+    }
+
+    fn number<T: FromStr>(field: &str) -> Result<T, String> {
+        field.parse().map_err(|_| format!("некорректное число: {}", field))
+    }
+
+    impl FromStr for Schedule {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let fields: Vec<&str> = s.split(',').collect();
+            let [id, from, to, amount, recurrence, max_attempts, delay, due, next_run, failures] = fields.as_slice() else {
+                return Err(format!("ожидается 10 полей регулярного платежа: {}", s));
+            };
+            Ok(Schedule {
+                id: number(id)?,
+                from: from.to_string(),
+                to: to.to_string(),
+                amount: amount.parse().map_err(|e: crate::ParseMoneyError| e.to_string())?,
+                recurrence: recurrence.parse()?,
+                retry: RetryPolicy { max_attempts: number(max_attempts)?, delay: number(delay)? },
+                due: number(due)?,
+                next_run: number(next_run)?,
+                failures: number(failures)?,
+            })
+        }
+    }
+
+    /// Итог попытки провести платёж
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum RunStatus {
+        Applied,
+        /// Не прошёл, будет повторён через `retry.delay`
+        Retry,
+        /// Не прошёл за все попытки; следующий платёж — по расписанию
+        GaveUp,
+    }
+
+    impl Display for RunStatus {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                RunStatus::Applied => "applied",
+                RunStatus::Retry => "retry",
+                RunStatus::GaveUp => "gave_up",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl FromStr for RunStatus {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "applied" => Ok(RunStatus::Applied),
+                "retry" => Ok(RunStatus::Retry),
+                "gave_up" => Ok(RunStatus::GaveUp),
+                _ => Err(format!("неизвестный итог платежа: {}", s)),
+            }
+        }
+    }
+
+    /// Запись о попытке провести регулярный платёж
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct RunRecord {
+        pub schedule: u64,
+        /// Дата платежа, который проводился
+        pub due: Timestamp,
+        /// Время попытки
+        pub at: Timestamp,
+        pub attempt: u32,
+        pub status: RunStatus,
+        /// Причина отказа
+        pub error: Option<String>,
+    }
+
+    /// Текстовый вид: `schedule,due,at,attempt,status,error`; текст ошибки — последним,
+    /// в нём могут быть запятые
+    impl Display for RunRecord {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "{},{},{},{},{},{}",
+                self.schedule,
+                self.due,
+                self.at,
+                self.attempt,
+                self.status,
+                self.error.as_deref().unwrap_or("")
+            )
+        }
+    }
+
+    impl FromStr for RunRecord {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let fields: Vec<&str> = s.splitn(6, ',').collect();
+            let [schedule, due, at, attempt, status, error] = fields.as_slice() else {
+                return Err(format!("ожидается 6 полей записи о платеже: {}", s));
+            };
+            Ok(RunRecord {
+                schedule: number(schedule)?,
+                due: number(due)?,
+                at: number(at)?,
+                attempt: number(attempt)?,
+                status: status.parse()?,
+                error: (!error.is_empty()).then(|| error.to_string()),
+            })
+        }
+    }
+
+    /// Регулярные платежи хранилища и журнал попыток их провести
+    #[derive(Debug, Clone, Default)]
+    pub struct ScheduleBook {
+        schedules: BTreeMap<u64, Schedule>,
+        runs: Vec<RunRecord>,
+        next_id: u64,
+    }
+
+    impl ScheduleBook {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(&self, id: u64) -> Option<&Schedule> {
+            self.schedules.get(&id)
+        }
+
+        /// Платежи по возрастанию номера
+        pub fn iter(&self) -> impl Iterator<Item = &Schedule> {
+            self.schedules.values()
+        }
+
+        pub fn len(&self) -> usize {
+            self.schedules.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.schedules.is_empty()
+        }
+
+        /// Все попытки в порядке выполнения
+        pub fn runs(&self) -> &[RunRecord] {
+            &self.runs
+        }
+
+        /// Номер для следующего платежа; номера отменённых платежей не переиспользуются
+        pub fn next_id(&self) -> u64 {
+            self.next_id.max(1)
+        }
+
+        pub(crate) fn set_next_id(&mut self, id: u64) {
+            self.next_id = self.next_id.max(id);
+        }
+
+        pub(crate) fn insert(&mut self, schedule: Schedule) {
+            self.set_next_id(schedule.id + 1);
+            self.schedules.insert(schedule.id, schedule);
+        }
+
+        pub(crate) fn remove(&mut self, id: u64) -> Result<Schedule, StorageError> {
+            self.schedules.remove(&id).ok_or(StorageError::ScheduleNotFound(id))
+        }
+
+        /// Возвращает платёж `id`, журнал попыток и счётчик номеров в прежнее состояние при откате
+        pub(crate) fn restore(&mut self, id: u64, schedule: Option<Schedule>, runs: usize, next_id: u64) {
+            match schedule {
+                Some(schedule) => self.schedules.insert(id, schedule),
+                None => self.schedules.remove(&id),
+            };
+            self.runs.truncate(runs);
+            self.next_id = next_id;
+        }
+
+        /// Ближайший платёж, попытка которого назначена не позже `now`
+        pub(crate) fn due(&self, now: Timestamp) -> Option<&Schedule> {
+            self.schedules
+                .values()
+                .filter(|schedule| schedule.next_run <= now)
+                .min_by_key(|schedule| (schedule.next_run, schedule.id))
+        }
+
+        /// Восстанавливает запись о попытке из снапшота, не меняя платежи
+        pub(crate) fn restore_run(&mut self, run: RunRecord) {
+            self.runs.push(run);
+        }
+
+        /// Учитывает попытку: после успеха или окончательного отказа платёж
+        /// переходит на следующую дату, после отказа с повтором — откладывается
+        pub(crate) fn record(&mut self, run: RunRecord) -> Result<(), StorageError> {
+            let schedule = self.schedules.get_mut(&run.schedule).ok_or(StorageError::ScheduleNotFound(run.schedule))?;
+            match run.status {
+                RunStatus::Applied | RunStatus::GaveUp => {
+                    schedule.failures = 0;
+                    schedule.due = schedule.recurrence.next_after(schedule.due);
+                    schedule.next_run = schedule.due;
+                }
+                RunStatus::Retry => {
+                    schedule.failures += 1;
+                    schedule.next_run = run.at + schedule.retry.delay;
+                }
+            }
+            self.runs.push(run);
+            Ok(())
+        }
+    }
+
+    /// Как часто серверы делают проход планировщика
+    pub const TICK_EVERY: Duration = Duration::from_secs(60);
+
+    /// Итог одного прохода планировщика ([`Scheduler::tick`]). Шаги независимы:
+    /// ошибка одного не мешает остальным
+    #[derive(Debug)]
+    pub struct Tick {
+        pub runs: Result<Vec<RunRecord>, StorageError>,
+        pub interest: Result<Vec<(Name, Balance)>, StorageError>,
+        pub expired: Result<Vec<Hold>, StorageError>,
+    }
+
+    /// По строке на каждую попытку платежа, выплату процентов, снятое удержание и ошибку
+    impl Display for Tick {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match &self.runs {
+                Ok(runs) => {
+                    for run in runs {
+                        let (id, due, error) = (run.schedule, format_date(run.due), run.error.as_deref().unwrap_or_default());
+                        match run.status {
+                            RunStatus::Applied => writeln!(f, "Регулярный платёж #{} за {} проведён", id, due)?,
+                            RunStatus::Retry => writeln!(
+                                f,
+                                "Регулярный платёж #{} за {} не прошёл (попытка {}), будет повторён: {}",
+                                id, due, run.attempt, error
+                            )?,
+                            RunStatus::GaveUp => writeln!(
+                                f,
+                                "Регулярный платёж #{} за {} не прошёл после {} попыток: {}",
+                                id, due, run.attempt, error
+                            )?,
+                        }
+                    }
+                }
+                Err(e) => writeln!(f, "Не удалось провести регулярные платежи: {}", e)?,
+            }
+            match &self.interest {
+                Ok(posted) => {
+                    for (name, amount) in posted {
+                        if amount.is_negative() {
+                            writeln!(f, "Со счёта {} списаны проценты: {}", name, amount)?;
+                        } else {
+                            writeln!(f, "На счёт {} выплачены проценты: {}", name, amount)?;
+                        }
+                    }
+                }
+                Err(e) => writeln!(f, "Не удалось начислить проценты: {}", e)?,
+            }
+            match &self.expired {
+                Ok(expired) => {
+                    for hold in expired {
+                        writeln!(f, "Удержание #{} на счёте {} истекло: {} снова доступно", hold.id, hold.account, hold.amount)?;
+                    }
+                }
+                Err(e) => writeln!(f, "Не удалось снять истёкшие удержания: {}", e)?,
+            }
+            Ok(())
+        }
+    }
+
+    /// Планировщик: по своим часам проводит наступившие платежи, начисляет проценты
+    /// и снимает истёкшие удержания
+    pub struct Scheduler<C: Clock> {
+        clock: C,
+    }
+
+    impl<C: Clock> Scheduler<C> {
+        pub fn new(clock: C) -> Self {
+            Scheduler { clock }
+        }
+
+        pub fn clock(&self) -> &C {
+            &self.clock
+        }
+
+        /// Проводит все платежи, наступившие к текущему времени часов,
+        /// включая пропущенные, пока часы стояли
+        pub fn run_due(&self, storage: &mut Storage) -> Result<Vec<RunRecord>, StorageError> {
+            storage.run_due(self.clock.now())
+        }
+
+        /// Начисляет проценты за дни, прошедшие по часам ([`Storage::accrue_interest`])
+        pub fn accrue_interest(&self, storage: &mut Storage) -> Result<Vec<(Name, Balance)>, StorageError> {
+            storage.accrue_interest(self.clock.now())
+        }
+
+        /// Снимает удержания, истёкшие по часам ([`Storage::expire_holds`])
+        pub fn expire_holds(&self, storage: &mut Storage) -> Result<Vec<Hold>, StorageError> {
+            storage.expire_holds(self.clock.now())
+        }
+
+        /// Всё, что наступает со временем: платежи, проценты и истёкшие удержания
+        pub fn tick(&self, storage: &mut Storage) -> Tick {
+            Tick {
+                runs: self.run_due(storage),
+                interest: self.accrue_interest(storage),
+                expired: self.expire_holds(storage),
+            }
+        }
+    }
+
+    impl<C: Clock + 'static> Scheduler<C> {
+        /// Запускает поток, который сразу и затем раз в `every` делает проход по общему
+        /// хранилищу и отдаёт итог в `report`. Хранилище блокируется только на время прохода
+        pub fn spawn(
+            self,
+            storage: Arc<Mutex<Storage>>,
+            every: Duration,
+            report: impl Fn(Tick) + Send + 'static,
+        ) -> JoinHandle<()> {
+            thread::spawn(move || {
+                loop {
+                    let tick = self.tick(&mut storage.lock().unwrap_or_else(PoisonError::into_inner));
+                    report(tick);
+                    thread::sleep(every);
+                }
+            })
+        }
+    }
+}
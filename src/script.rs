@@ -0,0 +1,166 @@
+pub mod script {
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+    use crate::{Balance, Batch, Deposit, Operation, Storage, Transaction, Transfer, TxError, Withdraw};
+
+    /// Сценарий — последовательность операций, записанная текстом:
+    ///
+    /// ```text
+    /// deposit Alice 100; transfer Alice Bob 30
+    /// withdraw Bob 5   # комментарий до конца строки
+    /// ```
+    ///
+    /// Операции разделяются `;` или переводом строки; разделитель можно опустить,
+    /// если операции идут подряд в одной строке. Сценарий выполняется как одна
+    /// цепочка: по принципу «всё или ничего», ошибка шага — [`TxError::Step`]
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct Script {
+        pub steps: Vec<Operation>,
+    }
+
+    /// Ошибка разбора сценария; строка и позиция в ней считаются с единицы
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ScriptError {
+        pub line: usize,
+        pub column: usize,
+        pub message: String,
+    }
+
+    impl Display for ScriptError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "строка {}, позиция {}: {}", self.line, self.column, self.message)
+        }
+    }
+
+    impl Error for ScriptError {}
+
+    /// Слово сценария или разделитель вместе с его положением
+    #[derive(Debug)]
+    struct Token<'a> {
+        text: &'a str,
+        line: usize,
+        column: usize,
+    }
+
+    impl Token<'_> {
+        fn is_separator(&self) -> bool {
+            self.text == ";" || self.text == "\n"
+        }
+
+        fn error(&self, message: String) -> ScriptError {
+            ScriptError { line: self.line, column: self.column, message }
+        }
+    }
+
+    /// Разбивает текст на слова и разделители, отбрасывая комментарии
+    fn tokenize(s: &str) -> Vec<Token<'_>> {
+        let mut tokens = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(code, _)| code);
+            // Позиция — в символах, а не в байтах: имена могут быть не латиницей
+            let token = |text, offset: usize| Token { text, line: index + 1, column: line[..offset].chars().count() + 1 };
+
+            let mut start = None;
+            for (offset, c) in line.char_indices().chain([(line.len(), ' ')]) {
+                if c.is_whitespace() || c == ';' {
+                    if let Some(begin) = start.take() {
+                        tokens.push(token(&line[begin..offset], begin));
+                    }
+                    if c == ';' {
+                        tokens.push(token(";", offset));
+                    }
+                } else if start.is_none() {
+                    start = Some(offset);
+                }
+            }
+            tokens.push(token("\n", line.len()));
+        }
+        tokens
+    }
+
+    impl FromStr for Script {
+        type Err = ScriptError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let tokens = tokenize(s);
+            let mut steps = Vec::new();
+            let mut rest = tokens.as_slice();
+
+            while let Some((keyword, tail)) = rest.split_first() {
+                if keyword.is_separator() {
+                    rest = tail;
+                    continue;
+                }
+                let arity = match keyword.text {
+                    "deposit" | "withdraw" => 2,
+                    "transfer" => 3,
+                    other => return Err(keyword.error(format!("неизвестная операция \"{}\"", other))),
+                };
+
+                // Аргументы операции не могут переходить через разделитель
+                let args: Vec<&Token> = tail.iter().take_while(|token| !token.is_separator()).take(arity).collect();
+                if args.len() < arity {
+                    let end = tail.get(args.len()).map_or(keyword, |token| token);
+                    return Err(end.error(format!(
+                        "у {} должно быть аргументов: {}, найдено: {}",
+                        keyword.text,
+                        arity,
+                        args.len()
+                    )));
+                }
+
+                let name = |token: &Token| token.text.to_string();
+                let amount = args[arity - 1];
+                let amount: Balance = amount
+                    .text
+                    .parse()
+                    .map_err(|e| amount.error(format!("{}", e)))?;
+                steps.push(match keyword.text {
+                    "deposit" => Operation::Deposit(Deposit { account: name(args[0]), amount }),
+                    "withdraw" => Operation::Withdraw(Withdraw { account: name(args[0]), amount }),
+                    _ => Operation::Transfer(Transfer { from: name(args[0]), to: name(args[1]), amount }),
+                });
+                rest = &tail[arity..];
+            }
+
+            Ok(Script { steps })
+        }
+    }
+
+    /// Печатает сценарий так, что его можно разобрать обратно: операции через `; `,
+    /// а в альтернативной форме (`{:#}`) — по одной на строке
+    impl Display for Script {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let separator = if f.alternate() { "\n" } else { "; " };
+            for (i, step) in self.steps.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "{}", separator)?;
+                }
+                write!(f, "{}", step)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Script {
+        /// Пакет из операций сценария — в нём сценарий и выполняется
+        pub fn to_batch(&self) -> Batch {
+            self.steps.iter().cloned().fold(Batch::new(), Batch::with)
+        }
+    }
+
+    impl Transaction for Script {
+        fn apply(&self, accounts: &mut Storage) -> Result<(), TxError> {
+            self.to_batch().apply(accounts)
+        }
+
+        fn operations(&self) -> Vec<Operation> {
+            self.steps.clone()
+        }
+
+        fn reverse(&self) -> Box<dyn Transaction> {
+            Box::new(Script { steps: self.steps.iter().rev().map(Operation::reversed).collect() })
+        }
+    }
+}
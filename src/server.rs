@@ -0,0 +1,130 @@
+pub mod server {
+    use std::fmt::{Display, Formatter};
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::sync::{Mutex, PoisonError};
+    use crate::{Balance, Deposit, Name, Storage, Transfer, Withdraw};
+
+    /// Адрес сервера по умолчанию: только локальные подключения
+    pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+    /// Ответ сервера — ровно одна строка: `OK <данные>` или `ERR <сообщение>`
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Response {
+        Ok(String),
+        Err(String),
+    }
+
+    impl Display for Response {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let (status, text) = match self {
+                Response::Ok(text) => ("OK", text),
+                Response::Err(text) => ("ERR", text),
+            };
+            if text.is_empty() {
+                write!(f, "{}", status)
+            } else {
+                write!(f, "{} {}", status, text)
+            }
+        }
+    }
+
+    impl Response {
+        /// Разбирает строку ответа на стороне клиента
+        pub fn parse(line: &str) -> Option<Response> {
+            let line = line.trim_end();
+            let (status, text) = line.split_once(' ').unwrap_or((line, ""));
+            match status {
+                "OK" => Some(Response::Ok(text.to_string())),
+                "ERR" => Some(Response::Err(text.to_string())),
+                _ => None,
+            }
+        }
+    }
+
+    /// Выполняет одну команду протокола:
+    ///
+    /// ```text
+    /// add <name> [balance]             -> OK
+    /// deposit <name> <amount>          -> OK <новый баланс>
+    /// withdraw <name> <amount>         -> OK <новый баланс>
+    /// transfer <from> <to> <amount> [key] -> OK
+    /// balance <name>                   -> OK <баланс>
+    /// list                             -> OK <name>=<balance> ... (по имени)
+    /// ```
+    ///
+    /// Каждая изменяющая команда записывается в журнал до ответа
+    pub fn handle_command(storage: &mut Storage, line: &str) -> Response {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let amount = |arg: &str| arg.parse::<Balance>().map_err(|e| Response::Err(e.to_string()));
+        let result = match args.as_slice() {
+            ["add", name] => add(storage, name, Ok(Balance::ZERO)),
+            ["add", name, balance] => add(storage, name, amount(balance)),
+            ["deposit", name, value] => amount(value).and_then(|amount| {
+                let tx = Deposit { account: name.to_string(), amount };
+                storage.commit(&tx).map_err(|e| Response::Err(e.to_string()))?;
+                Ok(balance_of(storage, name))
+            }),
+            ["withdraw", name, value] => amount(value).and_then(|amount| {
+                let tx = Withdraw { account: name.to_string(), amount };
+                storage.commit(&tx).map_err(|e| Response::Err(e.to_string()))?;
+                Ok(balance_of(storage, name))
+            }),
+            ["transfer", from, to, value, key @ ..] if key.len() <= 1 => amount(value).and_then(|amount| {
+                let tx = Transfer { from: from.to_string(), to: to.to_string(), amount };
+                let result = match key.first() {
+                    Some(key) => storage.commit_keyed(key, &tx),
+                    None => storage.commit(&tx),
+                };
+                result.map(|_| String::new()).map_err(|e| Response::Err(e.to_string()))
+            }),
+            ["balance", name] => storage
+                .get_balance(&name.to_string())
+                .map(|balance| balance.to_string())
+                .ok_or_else(|| Response::Err(format!("Пользователь {} не найден", name))),
+            ["list"] => {
+                let mut accounts = storage.get_all();
+                accounts.sort();
+                let list: Vec<String> = accounts.iter().map(|(name, balance)| format!("{}={}", name, balance)).collect();
+                Ok(list.join(" "))
+            }
+            [] => Err(Response::Err("пустая команда".to_string())),
+            [command, ..] => Err(Response::Err(format!("неизвестная команда или неверные аргументы: {}", command))),
+        };
+        result.map(Response::Ok).unwrap_or_else(|response| response)
+    }
+
+    fn add(storage: &mut Storage, name: &str, balance: Result<Balance, Response>) -> Result<String, Response> {
+        storage
+            .open_account(name.to_string(), balance?)
+            .map(|_| String::new())
+            .map_err(|e| Response::Err(e.to_string()))
+    }
+
+    fn balance_of(storage: &Storage, name: &str) -> String {
+        storage.get_balance(&Name::from(name)).unwrap_or(Balance::ZERO).to_string()
+    }
+
+    /// Обслуживает одного клиента: читает команды по строке и отвечает на каждую.
+    ///
+    /// Хранилище блокируется только на время одной команды, поэтому клиенты,
+    /// подключённые одновременно, обслуживаются вперемешку. `quit` закрывает соединение
+    pub fn serve_client(stream: TcpStream, storage: &Mutex<Storage>) -> io::Result<()> {
+        // Ответы короткие и идут по одному на запрос — ждать их склейки незачем
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim() == "quit" {
+                // Клиент может закрыть сокет, не дожидаясь прощания — это не ошибка
+                let _ = writeln!(writer, "{}", Response::Ok("bye".to_string()));
+                break;
+            }
+            // Паника в другом потоке не портит Storage: каждая команда атомарна
+            let response = handle_command(&mut storage.lock().unwrap_or_else(PoisonError::into_inner), &line);
+            writeln!(writer, "{}", response)?;
+        }
+        Ok(())
+    }
+}
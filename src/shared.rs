@@ -0,0 +1,191 @@
+pub mod shared {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+    use crate::ledger::ledger;
+    use crate::{Balance, Batch, Name, Operation, Storage, StorageError, Transaction, TxError};
+
+    /// Хранилище, которое можно разделять между потоками.
+    ///
+    /// Баланс каждого счёта главной книги — клиентского и внутреннего — лежит под
+    /// своим мьютексом, поэтому операции с разными клиентами идут параллельно.
+    /// Транзакция блокирует все свои счета в едином порядке: сначала клиентские
+    /// по имени, затем внутренние — так два перевода навстречу друг другу
+    /// не могут взаимно заблокироваться. Внутренние счета (касса) держатся
+    /// только на время сложения, а не всей транзакции.
+    ///
+    /// Работает только в памяти: хранилище с журналом не принимается, потому что
+    /// подтверждённая здесь транзакция ещё не на диске. Набор счетов фиксируется
+    /// при создании. Комиссии здесь не считаются, поэтому, пока у хранилища есть
+    /// тариф комиссий, транзакции отклоняются. Применённые транзакции запоминаются
+    /// по порядку и в [`SharedStorage::into_inner`] проводятся через [`Storage::commit`] —
+    /// так в хранилище появляются их история и события
+    pub struct SharedStorage {
+        storage: Storage,
+        /// Счета в порядке блокировки: клиентские по имени, затем внутренние по имени
+        order: Vec<Name>,
+        cells: HashMap<Name, Mutex<Balance>>,
+        /// Операции применённых транзакций в порядке применения. Транзакция попадает
+        /// сюда, пока её счета ещё заблокированы, поэтому транзакции с общими
+        /// счетами идут в том же порядке, в каком применялись
+        applied: Mutex<Vec<Vec<Operation>>>,
+    }
+
+    /// Согласованный срез балансов всех счетов на один момент
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Snapshot {
+        pub balances: BTreeMap<Name, Balance>,
+    }
+
+    impl Snapshot {
+        /// Балансы клиентов без внутренних счетов банка
+        pub fn accounts(&self) -> impl Iterator<Item = (&Name, &Balance)> {
+            self.balances.iter().filter(|(name, _)| !ledger::is_internal(name))
+        }
+
+        /// Сумма сальдо всех счетов; у согласованного среза она всегда равна нулю
+        pub fn net(&self) -> Result<Balance, StorageError> {
+            self.balances
+                .values()
+                .try_fold(Balance::ZERO, |sum, balance| sum.checked_add(*balance))
+                .ok_or(StorageError::Overflow)
+        }
+    }
+
+    /// Мьютекс с балансом не остаётся в промежуточном состоянии: значение в нём
+    /// записывается одним присваиванием, поэтому отравление можно игнорировать
+    fn lock(cell: &Mutex<Balance>) -> MutexGuard<'_, Balance> {
+        cell.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Порядок блокировки: внутренние счета после клиентских, внутри групп — по имени
+    fn lock_key(name: &str) -> (bool, &str) {
+        (ledger::is_internal(name), name)
+    }
+
+    impl SharedStorage {
+        /// Хранилище с журналом ([`Storage::is_journaled`]) — [`StorageError::Unsupported`]
+        pub fn new(storage: Storage) -> Result<Self, StorageError> {
+            if storage.is_journaled() {
+                return Err(StorageError::Unsupported("SharedStorage работает только с хранилищем в памяти".to_string()));
+            }
+            let mut cells: HashMap<Name, Mutex<Balance>> = storage
+                .ledger()
+                .balances()
+                .map(|(name, balance)| (name.clone(), Mutex::new(*balance)))
+                .collect();
+            // Касса нужна любому депозиту, даже если в книге её ещё нет
+            cells.entry(ledger::CASH_VAULT.to_string()).or_insert_with(|| Mutex::new(Balance::ZERO));
+
+            let mut order: Vec<Name> = cells.keys().cloned().collect();
+            order.sort_by(|a, b| lock_key(a).cmp(&lock_key(b)));
+            Ok(SharedStorage { storage, order, cells, applied: Mutex::new(Vec::new()) })
+        }
+
+        pub fn get_balance(&self, name: &str) -> Option<Balance> {
+            self.cells.get(name).map(|cell| *lock(cell))
+        }
+
+        /// Срез всех балансов. Блокирует все счета в общем порядке, поэтому
+        /// не видит транзакций, применённых наполовину
+        pub fn snapshot(&self) -> Snapshot {
+            let guards: Vec<_> = self.order.iter().map(|name| (name, lock(&self.cells[name]))).collect();
+            Snapshot {
+                balances: guards.iter().map(|(name, guard)| ((*name).clone(), **guard)).collect(),
+            }
+        }
+
+        /// Применяет транзакцию по её операциям ([`Transaction::operations`])
+        /// по принципу «всё или ничего». Проверки те же, что у [`Storage`].
+        /// При действующем тарифе комиссий — [`StorageError::Unsupported`]
+        pub fn commit<T: Transaction + ?Sized>(&self, tx: &T) -> Result<(), TxError> {
+            if !self.storage.fees().is_empty() {
+                let message = "при действующем тарифе комиссий транзакции проводит только Storage";
+                return Err(StorageError::Unsupported(message.to_string()).into());
+            }
+            let ops = tx.operations();
+            let mut names: Vec<&str> = Vec::new();
+            for op in &ops {
+                let (amount, accounts) = match op {
+                    Operation::Deposit(tx) => (tx.amount, vec![tx.account.as_str()]),
+                    Operation::Withdraw(tx) => (tx.amount, vec![tx.account.as_str()]),
+                    Operation::Transfer(tx) => (tx.amount, vec![tx.from.as_str(), tx.to.as_str()]),
+                };
+                if !amount.is_positive() || amount > Storage::MAX_AMOUNT {
+                    return Err(TxError::InvalidAmount);
+                }
+                for name in accounts {
+                    if ledger::is_internal(name) || !self.cells.contains_key(name) {
+                        return Err(TxError::InvalidAccount);
+                    }
+                    names.push(name);
+                }
+            }
+            names.sort_unstable();
+            names.dedup();
+
+            // Считаем на копиях и записываем, только если все операции прошли
+            let mut guards: Vec<_> = names.iter().map(|name| (*name, lock(&self.cells[*name]))).collect();
+            let mut balances: HashMap<&str, Balance> = guards.iter().map(|(name, guard)| (*name, **guard)).collect();
+            let mut cash = Balance::ZERO;
+            for op in &ops {
+                match op {
+                    Operation::Deposit(tx) => {
+                        credit(&mut balances, &tx.account, tx.amount)?;
+                        cash = cash.checked_sub(tx.amount).ok_or(TxError::Overflow)?;
+                    }
+                    Operation::Withdraw(tx) => {
+                        debit(&mut balances, &tx.account, tx.amount, self.storage.allowance(&tx.account))?;
+                        cash = cash.checked_add(tx.amount).ok_or(TxError::Overflow)?;
+                    }
+                    Operation::Transfer(tx) => {
+                        debit(&mut balances, &tx.from, tx.amount, self.storage.allowance(&tx.from))?;
+                        credit(&mut balances, &tx.to, tx.amount)?;
+                    }
+                }
+            }
+
+            // Касса блокируется последней и ненадолго; клиентские счета всё ещё заняты,
+            // поэтому срез не увидит изменение клиента без изменения кассы
+            if !cash.is_zero() {
+                let mut vault = lock(self.cells.get(ledger::CASH_VAULT).ok_or(TxError::InvalidAccount)?);
+                *vault = vault.checked_add(cash).ok_or(TxError::Overflow)?;
+            }
+            for (name, guard) in guards.iter_mut() {
+                **guard = balances[name];
+            }
+            self.applied.lock().unwrap_or_else(PoisonError::into_inner).push(ops);
+            Ok(())
+        }
+
+        /// Возвращает исходное хранилище, проведя в нём все применённые транзакции
+        /// в порядке применения. У каждого счёта этот порядок тот же, что был здесь,
+        /// поэтому проверки проходят так же и балансы получаются те же
+        pub fn into_inner(self) -> Result<Storage, TxError> {
+            let SharedStorage { mut storage, applied, .. } = self;
+            for ops in applied.into_inner().unwrap_or_else(PoisonError::into_inner) {
+                storage.commit(&ops.into_iter().fold(Batch::new(), Batch::with))?;
+            }
+            Ok(storage)
+        }
+    }
+
+    fn credit(balances: &mut HashMap<&str, Balance>, name: &str, amount: Balance) -> Result<(), TxError> {
+        let balance = balances.get_mut(name).ok_or(TxError::InvalidAccount)?;
+        *balance = balance.checked_add(amount).ok_or(TxError::Overflow)?;
+        Ok(())
+    }
+
+    /// Как и в Storage, списание не может увести баланс в минус дальше лимита овердрафта,
+    /// а удержанные суммы недоступны (`allowance` — лимит за вычетом удержаний).
+    /// Лимиты и удержания фиксируются при создании вместе с набором счетов
+    fn debit(balances: &mut HashMap<&str, Balance>, name: &str, amount: Balance, allowance: Balance) -> Result<(), TxError> {
+        let balance = balances.get_mut(name).ok_or(TxError::InvalidAccount)?;
+        let after = balance.checked_sub(amount).ok_or(TxError::Overflow)?;
+        if after.checked_add(allowance).is_some_and(Balance::is_negative) {
+            let available = balance.checked_add(allowance).unwrap_or(Balance::MAX).max(Balance::ZERO);
+            return Err(TxError::InsufficientFunds { available, requested: amount });
+        }
+        *balance = after;
+        Ok(())
+    }
+}
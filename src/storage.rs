@@ -1,120 +1,1755 @@
 pub mod storage {
     use std::collections::HashMap;
-    use std::fs::File;
-    use std::{fs, io};
+    use std::collections::hash_map::Entry;
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
+    use std::fs::{File, TryLockError};
+    use std::io;
     use std::io::BufRead;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
     use crate::Balance;
     use crate::Name;
+    use crate::calendar::calendar::{Clock, SECONDS_PER_DAY, SystemClock, Timestamp};
+    use crate::events::events::{Event, EventBus, Subscriber, SubscriberId};
+    use crate::fees::fees::{self, FeeKind, FeeSchedule};
+    use crate::history::history::{EntryKind, History, HistoryEntry};
+    use crate::holds::holds::{Hold, HoldBook};
+    use crate::idempotency::idempotency::{self, KeyStore, Outcome};
+    use crate::interest::interest::{Accrual, InterestBook, InterestProduct};
+    use crate::journal::journal::{Journal, JournalEntry, Record};
+    use crate::ledger::ledger::{self, Leg, Ledger, Posting, Side, TrialBalance};
+    use crate::persist::persist;
+    use crate::schedule::schedule::{RunRecord, RunStatus, Schedule, ScheduleBook};
+    use crate::{BalanceDeltas, Batch, Deposit, Operation, Transaction, Transfer, TxError, Withdraw};
+
+    /// Имя счёта допустимо, если оно непустое, без пробельных символов, `,` и `;`
+    /// и не начинается с `#` или `@`. Такое имя без экранирования пишется в журнал
+    /// (поля через пробел, операции через `;`) и в строки снапшота (поля через запятую,
+    /// служебные строки начинаются с `#`); имена на `@` — у внутренних счетов банка
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with(['#', '@'])
+            && !name.chars().any(|c| c.is_whitespace() || c == ',' || c == ';')
+    }
+
+    /// Ошибки слоя хранения
+    #[derive(Debug)]
+    pub enum StorageError {
+        /// Пользователь с таким именем не найден
+        NotFound(Name),
+        /// Пользователь с таким именем уже существует
+        AlreadyExists(Name),
+        /// Имя счёта пустое или содержит недопустимые символы (см. [`is_valid_name`])
+        InvalidName(Name),
+        /// Списание `requested` больше, чем `available` — баланс плюс лимит овердрафта
+        InsufficientFunds { available: Balance, requested: Balance },
+        /// Сумма операции не положительна или больше Storage::MAX_AMOUNT
+        InvalidAmount(Balance),
+        /// Результат операции не помещается в Balance
+        Overflow,
+        Io(io::Error),
+        /// Строка CSV-файла не разобрана; `line` считается с единицы
+        Parse { line: usize, message: String },
+        /// Запись журнала с номером `seq` не удалось применить при восстановлении
+        Journal { seq: u64, message: String },
+        /// Проводка не сбалансирована или балансы клиентов разошлись с главной книгой
+        Unbalanced(String),
+        /// Ключ идемпотентности пустой, слишком длинный или содержит недопустимые символы
+        InvalidKey(String),
+        /// Ключ идемпотентности уже использован для другой транзакции
+        KeyConflict(String),
+        /// Изменение с номером `seq` нельзя отменить
+        Irreversible { seq: u64, message: String },
+        /// Регулярного платежа с таким номером нет
+        ScheduleNotFound(u64),
+        /// Удержания с таким номером нет или оно истекло
+        HoldNotFound(u64),
+        /// Хранилище в таком состоянии не поддерживает операцию
+        Unsupported(String),
+        /// Файл данных уже открыт другим процессом (занят его файл блокировки)
+        Locked(PathBuf),
+    }
+
+    impl Display for StorageError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                StorageError::NotFound(name) => write!(f, "Пользователь {} не найден", name),
+                StorageError::AlreadyExists(name) => write!(f, "Пользователь {} уже существует", name),
+                StorageError::InvalidName(name) => write!(f, "Недопустимое имя счёта \"{}\"", name),
+                StorageError::InsufficientFunds { available, requested } => {
+                    write!(f, "Недостаточно средств: доступно {}, требуется {}", available, requested)
+                }
+                StorageError::InvalidAmount(amount) => write!(
+                    f,
+                    "Некорректная сумма {}: должна быть больше нуля и не больше {}",
+                    amount,
+                    Storage::MAX_AMOUNT
+                ),
+                StorageError::Overflow => write!(f, "Переполнение баланса"),
+                StorageError::Io(e) => write!(f, "Ошибка ввода-вывода: {}", e),
+                StorageError::Parse { line, message } => {
+                    write!(f, "Ошибка в строке {}: {}", line, message)
+                }
+                StorageError::Journal { seq, message } => {
+                    write!(f, "Не удалось восстановить запись журнала {}: {}", seq, message)
+                }
+                StorageError::Unbalanced(message) => write!(f, "Нарушен баланс главной книги: {}", message),
+                StorageError::Irreversible { seq, message } => {
+                    write!(f, "Изменение #{} нельзя отменить: {}", seq, message)
+                }
+                StorageError::InvalidKey(key) => write!(f, "Некорректный ключ идемпотентности \"{}\"", key),
+                StorageError::KeyConflict(key) => {
+                    write!(f, "Ключ идемпотентности \"{}\" уже использован для другой транзакции", key)
+                }
+                StorageError::ScheduleNotFound(id) => write!(f, "Регулярный платёж #{} не найден", id),
+                StorageError::HoldNotFound(id) => write!(f, "Удержание #{} не найдено или истекло", id),
+                StorageError::Unsupported(message) => write!(f, "Операция не поддерживается: {}", message),
+                StorageError::Locked(path) => write!(f, "Файл {} занят другим процессом", path.display()),
+            }
+        }
+    }
+
+    impl Error for StorageError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            match self {
+                StorageError::Io(e) => Some(e),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<io::Error> for StorageError {
+        fn from(e: io::Error) -> Self {
+            StorageError::Io(e)
+        }
+    }
+
+    /// Параметры сохранения в файл
+    #[derive(Debug, Clone, Default)]
+    pub struct SaveOptions {
+        /// Сколько резервных копий `<file>.bak.N` хранить; 0 — не делать копий
+        pub backups: usize,
+    }
 
     pub struct Storage {
+        /// Балансы клиентов — проекция главной книги; меняются только проводками
         pub accounts: HashMap<Name, Balance>,
+        /// Главная книга: счета клиентов и внутренние счета банка
+        ledger: Ledger,
+        /// Журнал, в который пишутся подтверждённые изменения; None — хранилище только в памяти
+        journal: Option<Journal>,
+        /// Открытый `<file>.lock` с исключительной блокировкой: пока хранилище живо,
+        /// другой процесс не загрузит тот же файл. Блокировку снимает ОС при закрытии файла
+        lock: Option<File>,
+        /// Номер последней записи журнала, учтённой в текущем состоянии
+        journal_seq: u64,
+        history: History,
+        /// Ключи идемпотентности уже обработанных транзакций
+        keys: KeyStore,
+        /// Регулярные платежи и попытки их провести
+        schedules: ScheduleBook,
+        /// Процентные продукты счетов и начисленные по ним проценты
+        interest: InterestBook,
+        /// Тариф комиссий за операции
+        fees: FeeSchedule,
+        /// Лимиты овердрафта: насколько баланс счёта может уйти в минус.
+        /// Счетов без лимита здесь нет
+        overdrafts: HashMap<Name, Balance>,
+        /// Удержания: зарезервированные, но ещё не списанные суммы
+        holds: HoldBook,
+        /// Часы, по которым операции получают своё время
+        clock: Arc<dyn Clock>,
+        /// Время выполняемой операции: все изменения одной транзакции
+        /// получают одно время, а при восстановлении — время из журнала
+        op_time: Option<Timestamp>,
+        /// Номер изменения выполняемой транзакции: общий для всех её записей в истории
+        change: Option<u64>,
+        /// Номер изменения, которое отменяет выполняемая транзакция
+        reversing: Option<u64>,
+        events: EventBus,
+        /// Глубина вложенных [`Storage::atomically`]: события рассылаются на нулевой
+        nesting: usize,
+        /// Как отменить изменения, сделанные внутри [`Storage::atomically`], в порядке их выполнения.
+        /// Откат проходит его с конца до отметки; на нулевой глубине журнал отмены пуст
+        undo: Vec<Undo>,
+    }
+
+    /// Первая строка CSV-снапшота: до какой записи журнала он актуален
+    const JOURNAL_HEADER: &str = "#journal=";
+    /// Строка CSV-снапшота с записью истории
+    const HISTORY_PREFIX: &str = "#history=";
+    /// Строка CSV-снапшота с границей архива истории и счётчиком номеров:
+    /// `#history_archive=<раньше чего>,<следующий номер>`
+    const HISTORY_ARCHIVE_PREFIX: &str = "#history_archive=";
+    /// Строка CSV-снапшота с балансом счёта после его последней записи в архиве:
+    /// `#history_opening=<account>,<balance>`
+    const HISTORY_OPENING_PREFIX: &str = "#history_opening=";
+    /// Строка CSV-снапшота с ключом идемпотентности: `#key=<key>,<outcome>,<отпечаток>`
+    const KEY_PREFIX: &str = "#key=";
+    /// Строка CSV-снапшота с регулярным платежом
+    const SCHEDULE_PREFIX: &str = "#schedule=";
+    /// Строка CSV-снапшота с попыткой провести регулярный платёж
+    const RUN_PREFIX: &str = "#run=";
+    /// Строка CSV-снапшота с номером для следующего регулярного платежа
+    const SCHEDULE_ID_PREFIX: &str = "#schedule_id=";
+    /// Строка CSV-снапшота с процентным продуктом счёта: `#interest=<account>,<начисление>`
+    const INTEREST_PREFIX: &str = "#interest=";
+    /// Строка CSV-снапшота с правилом тарифа комиссий
+    const FEE_PREFIX: &str = "#fee=";
+    /// Строка CSV-снапшота с лимитом овердрафта: `#overdraft=<account>,<limit>`
+    const OVERDRAFT_PREFIX: &str = "#overdraft=";
+    /// Строка CSV-снапшота с удержанием
+    const HOLD_PREFIX: &str = "#hold=";
+    /// Строка CSV-снапшота с номером для следующего удержания
+    const HOLD_ID_PREFIX: &str = "#hold_id=";
+    /// Последняя строка CSV-снапшота: контрольная сумма всех строк до неё
+    const CHECKSUM_FOOTER: &str = "#checksum=";
+
+    /// Прежнее значение того, что изменила транзакция. None — значения не было
+    enum Undo {
+        /// Баланс клиента и сальдо счёта в главной книге
+        Balance { account: Name, client: Option<Balance>, ledger: Option<Balance> },
+        /// Ключ идемпотентности, записанный транзакцией
+        Key(String),
+        Schedule { id: u64, schedule: Option<Schedule>, runs: usize, next_id: u64 },
+        Interest { account: Name, accrual: Option<Accrual> },
+        Fees(FeeSchedule),
+        Overdraft { account: Name, limit: Option<Balance> },
+        Hold { id: u64, hold: Option<Hold>, next_id: u64 },
+    }
+
+    /// Отметка, до которой откатывается хранилище: история и события
+    /// обрезаются по длине, остальное — по журналу отмены
+    struct Checkpoint {
+        undo: usize,
+        history_len: usize,
+        history_seq: u64,
+        events: usize,
+    }
+
+    impl Default for Storage {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl Storage {
+        /// Наибольшая сумма одной операции
+        pub const MAX_AMOUNT: Balance = Balance::from_major(1_000_000_000_000);
+        /// Сколько дней истории остаётся в памяти и в снапшоте. Больше года —
+        /// чтобы хватало на годовые выписки; старые записи при компактизации уходят в архив
+        pub const HISTORY_DAYS: u64 = 400;
+
         /// Создаёт новый пустой банк
         pub fn new() -> Self {
             Storage {
                 accounts: HashMap::new(),
+                ledger: Ledger::new(),
+                journal: None,
+                lock: None,
+                journal_seq: 0,
+                history: History::new(),
+                keys: KeyStore::new(),
+                schedules: ScheduleBook::new(),
+                interest: InterestBook::new(),
+                fees: FeeSchedule::new(),
+                overdrafts: HashMap::new(),
+                holds: HoldBook::new(),
+                clock: Arc::new(SystemClock),
+                op_time: None,
+                change: None,
+                reversing: None,
+                events: EventBus::new(),
+                nesting: 0,
+                undo: Vec::new(),
             }
         }
+        /// Заводит счёт клиента с нулевым балансом. Для недопустимых имён (в том числе
+        /// на `@` — они зарезервированы за внутренними счетами банка), как и для
+        /// существующих, возвращается None
         pub fn add_user(&mut self, name: Name) -> Option<Balance> {
-            if self.accounts.contains_key(&name) {
-                None
-            } else {
-                self.accounts.insert(name, 0);
-                Some(0)
+            if !is_valid_name(&name) {
+                return None;
+            }
+            match self.accounts.entry(name.clone()) {
+                Entry::Occupied(_) => None,
+                Entry::Vacant(_) => {
+                    self.log_balance(&name);
+                    self.accounts.insert(name.clone(), Balance::ZERO);
+                    self.ledger.set_balance(&name, Balance::ZERO);
+                    let seq = self.change_seq();
+                    self.record(seq, &name, EntryKind::Open, None, Balance::ZERO);
+                    self.emit(Event::AccountOpened { account: name });
+                    Some(Balance::ZERO)
+                }
             }
         }
 
+        /// Закрывает счёт клиента. Остаток на нём переносится на транзитный счёт банка,
+        /// невыплаченные проценты пропадают, а регулярные платежи с этого счёта
+        /// и на него отменяются — иначе они отказывали бы при каждом запуске
         pub fn remove_user(&mut self, name: &Name) -> Option<Balance> {
-            self.accounts.remove(name)
+            let balance = self.get_balance(name)?;
+            if balance.is_positive() {
+                self.post(Posting::new("close", vec![Leg::debit(name, balance), Leg::credit(ledger::SUSPENSE, balance)]))
+                    .ok()?;
+            } else if balance.is_negative() {
+                let debt = balance.checked_neg()?;
+                self.post(Posting::new("close", vec![Leg::debit(ledger::SUSPENSE, debt), Leg::credit(name, debt)]))
+                    .ok()?;
+            }
+            self.log_balance(name);
+            self.log_interest(name);
+            self.log_overdraft(name);
+            let holds: Vec<u64> = self.holds.iter().filter(|hold| hold.account == *name).map(|hold| hold.id).collect();
+            holds.into_iter().for_each(|id| self.log_hold(id));
+            let schedules: Vec<u64> = self
+                .schedules
+                .iter()
+                .filter(|schedule| schedule.from == *name || schedule.to == *name)
+                .map(|schedule| schedule.id)
+                .collect();
+            for id in schedules {
+                self.log_schedule(id);
+                let _ = self.schedules.remove(id);
+            }
+            self.accounts.remove(name);
+            self.ledger.remove_account(name);
+            self.interest.remove(name);
+            self.overdrafts.remove(name);
+            self.holds.remove_account(name);
+            let seq = self.change_seq();
+            self.record(seq, name, EntryKind::Close, None, balance);
+            self.emit(Event::AccountClosed { account: name.clone(), balance });
+            Some(balance)
         }
 
         pub fn get_balance(&self, name: &Name) -> Option<Balance> {
             self.accounts.get(name).copied()
         }
 
-        pub fn deposit(&mut self, name: &Name, amount: Balance) -> Result<(), String> {
-            if let Some(balance) = self.accounts.get_mut(name) {
-                *balance += amount;
+        /// Лимит овердрафта счёта; ноль — уходить в минус нельзя
+        pub fn overdraft_limit(&self, name: &str) -> Balance {
+            self.overdrafts.get(name).copied().unwrap_or(Balance::ZERO)
+        }
+
+        /// Сколько удерживается на счёте сейчас; истёкшие удержания не считаются
+        pub fn held(&self, name: &str) -> Balance {
+            self.holds.held(name, self.now())
+        }
+
+        /// Насколько баланс может уйти ниже нуля: лимит овердрафта за вычетом удержаний.
+        /// Отрицательное значение — удержано больше лимита, и часть баланса недоступна
+        pub(crate) fn allowance(&self, name: &str) -> Balance {
+            self.overdraft_limit(name).checked_sub(self.held(name)).unwrap_or(Balance::ZERO)
+        }
+
+        /// Сколько можно списать со счёта: баланс плюс лимит овердрафта за вычетом
+        /// удержаний, но не меньше нуля. Сам баланс (`get_balance`) — по главной книге
+        pub fn available(&self, name: &Name) -> Option<Balance> {
+            let balance = self.get_balance(name)?;
+            Some(balance.checked_add(self.allowance(name)).unwrap_or(Balance::MAX).max(Balance::ZERO))
+        }
+
+        /// Удержания всех счетов
+        pub fn holds(&self) -> &HoldBook {
+            &self.holds
+        }
+
+        /// Изменения пишутся в журнал — хранилище загружено через [`Storage::load_data`]
+        pub fn is_journaled(&self) -> bool {
+            self.journal.is_some()
+        }
+
+        /// Обработанные ключи идемпотентности
+        pub fn keys(&self) -> &KeyStore {
+            &self.keys
+        }
+
+        /// Процентные продукты и начисленные проценты
+        pub fn interest(&self) -> &InterestBook {
+            &self.interest
+        }
+
+        /// Действующий тариф комиссий
+        pub fn fees(&self) -> &FeeSchedule {
+            &self.fees
+        }
+
+        /// Регулярные платежи и журнал попыток
+        pub fn schedules(&self) -> &ScheduleBook {
+            &self.schedules
+        }
+
+        /// История изменений всех счетов
+        pub fn history(&self) -> &History {
+            &self.history
+        }
+
+        /// Главная книга, проекцией которой являются балансы клиентов
+        pub fn ledger(&self) -> &Ledger {
+            &self.ledger
+        }
+
+        /// Оборотно-сальдовая ведомость. Заодно проверяет, что балансы клиентов
+        /// совпадают с сальдо их счетов в главной книге
+        pub fn trial_balance(&self) -> Result<TrialBalance, StorageError> {
+            for (name, balance) in &self.accounts {
+                if self.ledger.balance(name) != *balance {
+                    return Err(StorageError::Unbalanced(format!(
+                        "баланс {} = {}, а в главной книге {}",
+                        name,
+                        balance,
+                        self.ledger.balance(name)
+                    )));
+                }
+            }
+            self.ledger.trial_balance()
+        }
+
+        /// Пополнение: деньги поступают в кассу банка и зачисляются клиенту.
+        /// Комиссия за пополнение списывается вместе с ним
+        pub fn deposit(&mut self, name: &Name, amount: Balance) -> Result<(), StorageError> {
+            self.atomically(|storage| {
+                storage.credit_deposit(name, amount)?;
+                storage.charge_fee(name, FeeKind::Deposit, amount)
+            })
+        }
+
+        /// Пополнение без комиссии — так зачисляется начальный баланс счёта
+        fn credit_deposit(&mut self, name: &Name, amount: Balance) -> Result<(), StorageError> {
+            Storage::check_amount(amount)?;
+            self.post(Posting::new("deposit", vec![Leg::debit(ledger::CASH_VAULT, amount), Leg::credit(name, amount)]))?;
+            let seq = self.change_seq();
+            self.record(seq, name, EntryKind::Deposit, None, amount);
+            let balance = self.get_balance(name).unwrap_or(Balance::ZERO);
+            self.emit(Event::Deposited { account: name.clone(), amount, balance });
+            Ok(())
+        }
+
+        /// Снятие: деньги списываются со счёта клиента и выдаются из кассы.
+        /// Средств должно хватить и на сумму, и на комиссию
+        pub fn withdraw(&mut self, name: &Name, amount: Balance) -> Result<(), StorageError> {
+            Storage::check_amount(amount)?;
+            self.atomically(|storage| {
+                storage.post(Posting::new("withdraw", vec![Leg::debit(name, amount), Leg::credit(ledger::CASH_VAULT, amount)]))?;
+                let seq = storage.change_seq();
+                storage.record(seq, name, EntryKind::Withdraw, None, amount);
+                let balance = storage.get_balance(name).unwrap_or(Balance::ZERO);
+                storage.emit(Event::Withdrawn { account: name.clone(), amount, balance });
+                storage.charge_fee(name, FeeKind::Withdraw, amount)
+            })
+        }
+
+        /// Переводит `amount` со счёта `from` на счёт `to`.
+        /// Оба счёта проверяются до изменения балансов; комиссию платит `from`
+        pub fn transfer(&mut self, from: &Name, to: &Name, amount: Balance) -> Result<(), StorageError> {
+            Storage::check_amount(amount)?;
+            self.atomically(|storage| {
+                storage.post(Posting::new("transfer", vec![Leg::debit(from, amount), Leg::credit(to, amount)]))?;
+                let seq = storage.change_seq();
+                storage.record(seq, from, EntryKind::TransferOut, Some(to), amount);
+                storage.record(seq, to, EntryKind::TransferIn, Some(from), amount);
+                storage.emit(Event::Transferred { from: from.clone(), to: to.clone(), amount });
+                storage.charge_fee(from, FeeKind::Transfer, amount)
+            })
+        }
+
+        /// Списывает комиссию за операцию на сумму `amount` в доход банка.
+        /// Комиссия не может увести баланс в минус — тогда отказывает вся операция.
+        /// При отмене изменений комиссия не берётся
+        fn charge_fee(&mut self, account: &Name, kind: FeeKind, amount: Balance) -> Result<(), StorageError> {
+            let Some(rule) = self.fees.rule(kind).filter(|_| self.reversing.is_none()) else {
+                return Ok(());
+            };
+            let mut fee = rule.charge.fee(amount).ok_or(StorageError::Overflow)?;
+            if let Some(cap) = rule.cap {
+                let charged = fees::charged(&self.history, account, kind, cap.period.start(self.now()));
+                fee = fee.min(cap.limit.checked_sub(charged).unwrap_or(Balance::ZERO).max(Balance::ZERO));
+            }
+            if !fee.is_positive() {
+                return Ok(());
+            }
+            let posting = Posting::new("fee", vec![Leg::debit(account, fee), Leg::credit(ledger::FEES, fee)]);
+            self.post(posting).map_err(|e| match e {
+                // Операция уже списала `amount`: в ошибке — сколько было доступно до неё и сколько нужно вместе с комиссией
+                StorageError::InsufficientFunds { available, requested } if kind != FeeKind::Deposit => {
+                    StorageError::InsufficientFunds {
+                        available: available.checked_add(amount).unwrap_or(Balance::MAX),
+                        requested: requested.checked_add(amount).unwrap_or(Balance::MAX),
+                    }
+                }
+                e => e,
+            })?;
+            let seq = self.change_seq();
+            self.record(seq, account, EntryKind::Fee, None, fee);
+            let balance = self.get_balance(account).unwrap_or(Balance::ZERO);
+            self.emit(Event::FeeCharged { account: account.clone(), amount: fee, balance });
+            Ok(())
+        }
+
+        /// Проводит проводку по главной книге и обновляет балансы клиентов.
+        ///
+        /// Все счета клиентов в проводке должны существовать, а списание
+        /// не может увести баланс клиента в минус дальше его лимита овердрафта;
+        /// удержанные суммы при этом недоступны. При ошибке ничего не меняется
+        pub fn post(&mut self, posting: Posting) -> Result<(), StorageError> {
+            self.post_with(posting, false)
+        }
+
+        /// Как [`Storage::post`]; с `allow_debt` списание может увести баланс в минус —
+        /// так банк списывает начисленные им самим суммы
+        fn post_with(&mut self, posting: Posting, allow_debt: bool) -> Result<(), StorageError> {
+            for leg in &posting.legs {
+                if !ledger::is_internal(&leg.account) && !self.accounts.contains_key(&leg.account) {
+                    return Err(StorageError::NotFound(leg.account.clone()));
+                }
+            }
+
+            if !allow_debt {
+                self.check_debits(&posting)?;
+            }
+            self.atomically(|storage| {
+                posting.legs.iter().for_each(|leg| storage.log_balance(&leg.account));
+                for (account, balance) in storage.ledger.post(posting)? {
+                    if !ledger::is_internal(&account) {
+                        storage.accounts.insert(account, balance);
+                    }
+                }
+                Ok(())
+            })
+        }
+
+        /// Проверяет, что каждому счёту клиента хватает средств на все его дебеты.
+        /// Дебеты суммируются до того, как книга сведёт ноги одного счёта: иначе перевод
+        /// самому себе прошёл бы и без денег. Уменьшить долг сверх лимита можно, увеличить — нет
+        fn check_debits(&self, posting: &Posting) -> Result<(), StorageError> {
+            let mut debits: Vec<(&str, Balance)> = Vec::new();
+            for leg in posting.legs.iter().filter(|leg| leg.side == Side::Debit && !ledger::is_internal(&leg.account)) {
+                match debits.iter_mut().find(|(account, _)| *account == leg.account) {
+                    Some((_, total)) => *total = total.checked_add(leg.amount).ok_or(StorageError::Overflow)?,
+                    None => debits.push((&leg.account, leg.amount)),
+                }
+            }
+            for (account, debit) in debits {
+                let previous = self.accounts.get(account).copied().unwrap_or(Balance::ZERO);
+                let allowance = self.allowance(account);
+                let after = previous.checked_sub(debit).ok_or(StorageError::Overflow)?;
+                if after.checked_add(allowance).is_some_and(Balance::is_negative) {
+                    return Err(StorageError::InsufficientFunds {
+                        available: previous.checked_add(allowance).unwrap_or(Balance::MAX).max(Balance::ZERO),
+                        requested: debit,
+                    });
+                }
+            }
+            Ok(())
+        }
+
+        /// Добавляет запись в историю; баланс после операции берётся из `accounts`
+        fn record(&mut self, seq: u64, account: &Name, kind: EntryKind, counterparty: Option<&Name>, amount: Balance) {
+            let entry = HistoryEntry {
+                seq,
+                timestamp: self.now(),
+                account: account.clone(),
+                kind,
+                counterparty: counterparty.cloned(),
+                amount,
+                balance: self.get_balance(account).unwrap_or(Balance::ZERO),
+                reverses: self.reversing,
+            };
+            self.history.push(entry);
+        }
+
+        /// Подписывает на события изменения балансов. Событие приходит, когда
+        /// изменение окончательно прошло: события отменённой транзакции не рассылаются
+        pub fn subscribe(&mut self, subscriber: impl Subscriber + 'static) -> SubscriberId {
+            self.events.subscribe(subscriber)
+        }
+
+        pub fn unsubscribe(&mut self, id: SubscriberId) -> bool {
+            self.events.unsubscribe(id)
+        }
+
+        /// Публикует событие; вне [`Storage::atomically`] оно сразу рассылается
+        fn emit(&mut self, event: Event) {
+            self.events.publish(event);
+            if self.nesting == 0 {
+                self.events.flush();
+            }
+        }
+
+        /// Сообщает подписчикам об отказе транзакции и возвращает `result` как есть.
+        ///
+        /// Вызывается в конце [`Transaction::apply`]. Отказ шага внутри внешней
+        /// транзакции не публикуется: о нём после отката сообщит внешняя
+        pub fn report(
+            &mut self,
+            result: Result<(), TxError>,
+            operations: impl FnOnce() -> Vec<Operation>,
+        ) -> Result<(), TxError> {
+            if let Err(e) = &result
+                && self.nesting == 0
+            {
+                self.emit(Event::TransactionFailed { operations: operations(), error: e.to_string() });
+            }
+            result
+        }
+
+        /// Номер изменения для новой записи истории: внутри транзакции — её общий номер
+        fn change_seq(&mut self) -> u64 {
+            match self.change {
+                Some(seq) => seq,
+                None => self.history.next_seq(),
+            }
+        }
+
+        /// Выполняет `f` как одно изменение: все записи истории получают один номер.
+        /// Вложенные вызовы используют номер внешнего
+        fn as_one_change<T>(&mut self, f: impl FnOnce(&mut Storage) -> T) -> T {
+            if self.change.is_some() {
+                return f(self);
+            }
+            self.change = Some(self.history.next_seq());
+            let result = f(self);
+            self.change = None;
+            result
+        }
+
+        /// Подменяет часы хранилища. По ним операции получают время в истории и журнале,
+        /// по ним же истекают удержания и считаются лимиты комиссий за период
+        pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+            self.clock = Arc::new(clock);
+        }
+
+        fn now(&self) -> Timestamp {
+            self.op_time.unwrap_or_else(|| self.clock.now())
+        }
+
+        /// Сумма операции должна быть положительной: отрицательный депозит
+        /// был бы скрытым списанием, а отрицательный перевод — переводом в обратную сторону
+        fn check_amount(amount: Balance) -> Result<(), StorageError> {
+            if amount.is_positive() && amount <= Storage::MAX_AMOUNT {
                 Ok(())
             } else {
-                Err("Пользователь не найден".into())
+                Err(StorageError::InvalidAmount(amount))
             }
         }
 
-        pub fn withdraw(&mut self, name: &Name, amount: Balance) -> Result<(), String> {
-            if let Some(balance) = self.accounts.get_mut(name) {
-                if *balance >= amount {
-                    *balance -= amount;
-                    Ok(())
+        /// Открывает счёт с начальным балансом и записывает это в журнал
+        pub fn open_account(&mut self, name: Name, balance: Balance) -> Result<(), StorageError> {
+            if !is_valid_name(&name) {
+                return Err(StorageError::InvalidName(name));
+            }
+            let time = self.now();
+            self.atomically_at(time, |storage| {
+                if storage.add_user(name.clone()).is_none() {
+                    return Err(StorageError::AlreadyExists(name));
+                }
+                if !balance.is_zero() {
+                    storage.credit_deposit(&name, balance)?;
+                }
+                storage.append(time, Record::Open { name, balance })
+            })
+        }
+
+        /// Закрывает счёт, записывает это в журнал и возвращает остаток на нём
+        pub fn close_account(&mut self, name: &Name) -> Result<Balance, StorageError> {
+            let time = self.now();
+            self.atomically_at(time, |storage| {
+                let balance = storage
+                    .remove_user(name)
+                    .ok_or_else(|| StorageError::NotFound(name.clone()))?;
+                storage.append(time, Record::Close { name: name.clone() })?;
+                Ok(balance)
+            })
+        }
+
+        /// Применяет транзакцию атомарно и записывает её в журнал.
+        ///
+        /// Когда метод вернул Ok, транзакция уже на диске и переживёт сбой;
+        /// если запись в журнал не удалась, изменения балансов откатываются
+        pub fn commit<T: Transaction + ?Sized>(&mut self, tx: &T) -> Result<(), TxError> {
+            let time = self.now();
+            let result = self.atomically_at(time, |storage| {
+                storage.as_one_change(|storage| tx.apply(storage))?;
+                storage.append(time, Record::Commit(tx.operations()))?;
+                Ok(())
+            });
+            self.report(result, || tx.operations())
+        }
+
+        /// Как [`Storage::commit`], но с ключом идемпотентности, выданным клиентом.
+        ///
+        /// Первый вызов с ключом применяет транзакцию и запоминает её итог — успех
+        /// или отказ — вместе с отпечатком её операций. Повторный вызов с тем же ключом
+        /// и той же транзакцией ничего не применяет и возвращает запомненный итог,
+        /// а с другой транзакцией — [`StorageError::KeyConflict`]. Ключ попадает в журнал
+        /// вместе с транзакцией, поэтому переживает сбой и перезапуск
+        pub fn commit_keyed<T: Transaction + ?Sized>(&mut self, key: &str, tx: &T) -> Result<(), TxError> {
+            if !idempotency::is_valid_key(key) {
+                return self.report(Err(StorageError::InvalidKey(key.to_string()).into()), || tx.operations());
+            }
+            let fingerprint = idempotency::fingerprint(&tx.operations());
+            if !self.keys.matches(key, fingerprint) {
+                return self.report(Err(StorageError::KeyConflict(key.to_string()).into()), || tx.operations());
+            }
+            if let Some(outcome) = self.keys.get(key) {
+                return outcome.to_result();
+            }
+
+            let time = self.now();
+            // Внешний откат нужен, только если не удалось записать итог в журнал
+            let recorded = self.atomically_at(time, |storage| {
+                let result = storage.atomically(|storage| storage.as_one_change(|storage| tx.apply(storage)));
+                if let Some(outcome) = Outcome::of(&result) {
+                    storage.log(Undo::Key(key.to_string()));
+                    storage.keys.insert(key.to_string(), outcome, fingerprint);
+                    let record = Record::Keyed { key: key.to_string(), outcome, ops: tx.operations() };
+                    storage.append(time, record)?;
+                }
+                Ok::<_, StorageError>(result)
+            });
+            let result = recorded.map_err(TxError::from).and_then(|result| result);
+            self.report(result, || tx.operations())
+        }
+
+        /// Применяет операции одним пакетом, как [`Storage::commit`], и запоминает ключ
+        /// идемпотентности каждой операции, у которой он есть. Пакет и ключи попадают
+        /// в журнал одной записью.
+        ///
+        /// Ключ, который уже обработан или повторяется в пакете, — [`StorageError::KeyConflict`]:
+        /// уже обработанные операции вызывающий отбирает сам, по [`KeyStore::matches`].
+        /// Отказ не запоминается — ни одна операция не применена, и пакет можно повторить
+        pub fn commit_keyed_steps(&mut self, steps: &[(Option<String>, Operation)]) -> Result<(), TxError> {
+            let batch = steps.iter().map(|(_, op)| op.clone()).fold(Batch::new(), Batch::with);
+            let keys: Vec<&str> = steps.iter().filter_map(|(key, _)| key.as_deref()).collect();
+            for (index, key) in keys.iter().enumerate() {
+                let error = if !idempotency::is_valid_key(key) {
+                    StorageError::InvalidKey(key.to_string())
+                } else if self.keys.get(key).is_some() || keys[..index].contains(key) {
+                    StorageError::KeyConflict(key.to_string())
                 } else {
-                    Err("Недостаточно средств".into())
+                    continue;
+                };
+                return self.report(Err(error.into()), || batch.operations());
+            }
+
+            let time = self.now();
+            let result = self.atomically_at(time, |storage| {
+                storage.as_one_change(|storage| batch.apply(storage))?;
+                storage.remember_steps(steps);
+                let keys = steps.iter().map(|(key, _)| key.clone()).collect();
+                storage.append(time, Record::KeyedSteps { keys, ops: batch.operations() })?;
+                Ok(())
+            });
+            self.report(result, || batch.operations())
+        }
+
+        /// Запоминает ключи применённого пакета: у каждого — отпечаток своей операции
+        fn remember_steps(&mut self, steps: &[(Option<String>, Operation)]) {
+            for (key, op) in steps {
+                if let Some(key) = key {
+                    self.log(Undo::Key(key.clone()));
+                    self.keys.insert(key.clone(), Outcome::Applied, idempotency::fingerprint(&op.operations()));
                 }
+            }
+        }
+
+        /// Меняет тариф комиссий и записывает его в журнал, если он отличается
+        /// от действующего. Новый тариф применяется к следующим операциям
+        pub fn set_fees(&mut self, schedule: FeeSchedule) -> Result<(), StorageError> {
+            if schedule == self.fees {
+                return Ok(());
+            }
+            let time = self.now();
+            self.atomically(|storage| {
+                storage.log(Undo::Fees(storage.fees.clone()));
+                storage.fees = schedule.clone();
+                storage.append(time, Record::Fees(schedule))
+            })
+        }
+
+        /// Устанавливает лимит овердрафта счёта и записывает его в журнал; ноль снимает лимит.
+        /// Если долг уже больше нового лимита, счёт можно только пополнять
+        pub fn set_overdraft(&mut self, name: &Name, limit: Balance) -> Result<(), StorageError> {
+            if !self.accounts.contains_key(name) {
+                return Err(StorageError::NotFound(name.clone()));
+            }
+            if limit.is_negative() || limit > Storage::MAX_AMOUNT {
+                return Err(StorageError::InvalidAmount(limit));
+            }
+            let time = self.now();
+            self.atomically(|storage| {
+                storage.apply_overdraft(name, limit);
+                storage.append(time, Record::Overdraft { account: name.clone(), limit })
+            })
+        }
+
+        fn apply_overdraft(&mut self, name: &Name, limit: Balance) {
+            self.log_overdraft(name);
+            if limit.is_zero() {
+                self.overdrafts.remove(name);
             } else {
-                Err("Пользователь не найден".into())
+                self.overdrafts.insert(name.clone(), limit);
             }
         }
 
-        pub fn get_all(&self) -> Vec<(Name, i64)> {
-            self.accounts.iter().map(|(n, b)| (n.clone(), *b)).collect()
+        /// Удерживает `amount` на счёте на `ttl` секунд, записывает это в журнал
+        /// и возвращает номер удержания. Удержать можно не больше доступной суммы
+        pub fn authorize(&mut self, name: &Name, amount: Balance, ttl: u64) -> Result<u64, StorageError> {
+            Storage::check_amount(amount)?;
+            let available = self.available(name).ok_or_else(|| StorageError::NotFound(name.clone()))?;
+            if amount > available {
+                return Err(StorageError::InsufficientFunds { available, requested: amount });
+            }
+            let time = self.now();
+            let hold = Hold { id: self.holds.next_id(), ..Hold::new(name.clone(), amount, time, ttl) };
+            let id = hold.id;
+            self.atomically(|storage| {
+                storage.log_hold(id);
+                storage.holds.insert(hold.clone());
+                storage.append(time, Record::Hold(hold))?;
+                storage.emit(Event::HoldPlaced { id, account: name.clone(), amount });
+                Ok(id)
+            })
         }
 
-        /// Загружает данные из CSV-файла или создаёт хранилище с дефолтными пользователями
-        pub fn load_data(file: &str) -> Storage {
-            let mut storage = Storage::new();
+        /// Списывает удержанную сумму: снятием или, если указан `to`, переводом на `to`.
+        /// Без `amount` списывается всё удержание; после частичного захвата остаток
+        /// остаётся удержанным до следующего захвата, снятия или истечения срока
+        pub fn capture(&mut self, id: u64, amount: Option<Balance>, to: Option<&Name>) -> Result<(), TxError> {
+            let now = self.now();
+            let hold = self.holds.get(id).filter(|hold| hold.is_active(now)).ok_or(StorageError::HoldNotFound(id))?;
+            let amount = amount.unwrap_or(hold.amount);
+            let op = Storage::capture_operation(hold, amount, to);
+            let result = self.atomically_at(now, |storage| {
+                storage.apply_capture(id, amount, &op)?;
+                storage.append(now, Record::Capture { id, amount, to: to.cloned() })?;
+                Ok(())
+            });
+            self.report(result, || vec![op.clone()])
+        }
+
+        /// Операция, которой списывается захваченная часть удержания
+        fn capture_operation(hold: &Hold, amount: Balance, to: Option<&Name>) -> Operation {
+            match to {
+                Some(to) => Operation::Transfer(Transfer { from: hold.account.clone(), to: to.clone(), amount }),
+                None => Operation::Withdraw(Withdraw { account: hold.account.clone(), amount }),
+            }
+        }
 
-            // Проверяем, существует ли файл
-            if Path::new(file).exists() {
-                // Открываем файл
-                let file = File::open(file).unwrap();
-
-                // Оборачиваем файл в BufReader
-                // BufReader читает данные блоками и хранит их в буфере,
-                // поэтому построчное чтение (lines()) работает быстрее, чем читать по байту
-                let reader = io::BufReader::new(file);
-
-                // Читаем файл построчно
-                for line in reader.lines() {
-                    // Каждая строка — это Result<String>, поэтому делаем if let Ok
-                    if let Ok(line) = line {
-                        // Разделяем строку по запятой: "Name,Balance"
-                        let parts: Vec<&str> = line.trim().split(',').collect();
-
-                        if parts.len() == 2 {
-                            let name = parts[0].to_string();
-                            // Пробуем преобразовать баланс из строки в число
-                            let balance: i64 = parts[1].parse().unwrap_or(0);
-
-                            // Добавляем пользователя и выставляем баланс
-                            storage.add_user(name.clone());
-                            let _ = storage.deposit(&name, balance);
-                        }
+        /// Сначала уменьшает удержание — иначе списание упрётся в него же, — затем
+        /// проводит операцию как одно изменение. Проверки — по доступной сумме
+        fn apply_capture(&mut self, id: u64, amount: Balance, op: &Operation) -> Result<(), TxError> {
+            let now = self.now();
+            self.log_hold(id);
+            self.holds.capture(id, amount, now)?;
+            self.as_one_change(|storage| op.apply(storage))
+        }
+
+        /// Снимает удержание целиком, не списывая деньги, и записывает это в журнал
+        pub fn release(&mut self, id: u64) -> Result<Hold, StorageError> {
+            let time = self.now();
+            self.atomically(|storage| storage.release_at(id, time))
+        }
+
+        /// Снимает все удержания, срок которых истёк к моменту `now`
+        pub fn expire_holds(&mut self, now: Timestamp) -> Result<Vec<Hold>, StorageError> {
+            let expired = self.holds.expired(now);
+            if expired.is_empty() {
+                return Ok(Vec::new());
+            }
+            self.atomically(|storage| expired.into_iter().map(|id| storage.release_at(id, now)).collect())
+        }
+
+        fn release_at(&mut self, id: u64, time: Timestamp) -> Result<Hold, StorageError> {
+            self.log_hold(id);
+            let hold = self.holds.remove(id)?;
+            self.append(time, Record::Release { id })?;
+            self.emit(Event::HoldReleased { id, account: hold.account.clone(), amount: hold.amount });
+            Ok(hold)
+        }
+
+        /// Читает тариф из файла (см. [`FeeSchedule::load`]) и делает его действующим.
+        /// Если файла нет, комиссии отключаются
+        pub fn load_fees(&mut self, path: &Path) -> Result<(), StorageError> {
+            self.set_fees(FeeSchedule::load(path)?)
+        }
+
+        /// Добавляет регулярный платёж, записывает его в журнал и возвращает его номер.
+        /// Оба счёта должны существовать на момент добавления
+        pub fn add_schedule(&mut self, mut schedule: Schedule) -> Result<u64, StorageError> {
+            Storage::check_amount(schedule.amount)?;
+            for name in [&schedule.from, &schedule.to] {
+                if !self.accounts.contains_key(name) {
+                    return Err(StorageError::NotFound(name.clone()));
+                }
+            }
+            schedule.id = self.schedules.next_id();
+            let id = schedule.id;
+            let time = self.now();
+            self.atomically(|storage| {
+                storage.log_schedule(id);
+                storage.schedules.insert(schedule.clone());
+                storage.append(time, Record::Schedule(schedule))
+            })?;
+            Ok(id)
+        }
+
+        /// Отменяет регулярный платёж и записывает это в журнал
+        pub fn cancel_schedule(&mut self, id: u64) -> Result<Schedule, StorageError> {
+            let time = self.now();
+            self.atomically(|storage| {
+                storage.log_schedule(id);
+                let schedule = storage.schedules.remove(id)?;
+                storage.append(time, Record::Unschedule { id })?;
+                Ok(schedule)
+            })
+        }
+
+        /// Назначает счёту процентный продукт и записывает это в журнал. Проценты
+        /// начисляются с дня, в который попадает `start`. Если продукт уже был,
+        /// меняются только условия: начисленное по старым сохраняется
+        pub fn set_interest(&mut self, account: &Name, product: InterestProduct, start: Timestamp) -> Result<(), StorageError> {
+            if !self.accounts.contains_key(account) {
+                return Err(StorageError::NotFound(account.clone()));
+            }
+            let time = self.now();
+            self.atomically(|storage| {
+                storage.log_interest(account);
+                storage.interest.set(account, product, start);
+                storage.append(time, Record::Interest { account: account.clone(), product, start })
+            })
+        }
+
+        /// Отключает процентный продукт счёта. Невыплаченные проценты пропадают;
+        /// false — продукта у счёта не было
+        pub fn clear_interest(&mut self, account: &Name) -> Result<bool, StorageError> {
+            if self.interest.get(account).is_none() {
+                return Ok(false);
+            }
+            let time = self.now();
+            self.atomically(|storage| {
+                storage.log_interest(account);
+                storage.interest.remove(account);
+                storage.append(time, Record::InterestOff { account: account.clone() })?;
+                Ok(true)
+            })
+        }
+
+        /// Начисляет проценты за все прошедшие дни — до начала дня, в который
+        /// попадает `now`, — и выплачивает их в конце периодов выплаты.
+        /// Возвращает выплаченные (или списанные) суммы по порядку
+        pub fn accrue_interest(&mut self, now: Timestamp) -> Result<Vec<(Name, Balance)>, StorageError> {
+            let through = now / SECONDS_PER_DAY * SECONDS_PER_DAY;
+            if self.interest.behind(through).is_empty() {
+                return Ok(Vec::new());
+            }
+            self.atomically(|storage| {
+                let posted = storage.accrue_through(through)?;
+                storage.append(now, Record::Accrue { through })?;
+                Ok(posted)
+            })
+        }
+
+        /// Начисляет проценты по дням на остаток на конец каждого дня из истории.
+        /// Выплата проводится в последнюю секунду дня, которым заканчивается период
+        fn accrue_through(&mut self, through: Timestamp) -> Result<Vec<(Name, Balance)>, StorageError> {
+            let mut posted = Vec::new();
+            for account in self.interest.behind(through) {
+                while let Some(accrual) = self.interest.get(&account).filter(|accrual| accrual.next_day < through) {
+                    let end = accrual.next_day + SECONDS_PER_DAY;
+                    // В старых снапшотах истории нет — тогда остаток считается текущим
+                    let balance = self
+                        .history
+                        .balance_at(&account, end)
+                        .or_else(|| self.get_balance(&account))
+                        .unwrap_or(Balance::ZERO);
+                    self.log_interest(&account);
+                    let accrual = self.interest.get_mut(&account).ok_or_else(|| StorageError::NotFound(account.clone()))?;
+                    let Some(minor) = accrual.accrue_day(balance)? else {
+                        continue;
+                    };
+                    if minor != 0 {
+                        let amount = Balance::from_minor(minor);
+                        self.atomically_at(end - 1, |storage| storage.post_interest(&account, amount))?;
+                        posted.push((account.clone(), amount));
                     }
                 }
+            }
+            Ok(posted)
+        }
+
+        /// Выплачивает проценты со счёта процентных расходов банка
+        /// или, если `amount` отрицательна, списывает их туда
+        fn post_interest(&mut self, account: &Name, amount: Balance) -> Result<(), StorageError> {
+            let (legs, kind, abs) = if amount.is_negative() {
+                let abs = amount.checked_neg().ok_or(StorageError::Overflow)?;
+                (vec![Leg::debit(account, abs), Leg::credit(ledger::INTEREST, abs)], EntryKind::InterestCharge, abs)
             } else {
+                (vec![Leg::debit(ledger::INTEREST, amount), Leg::credit(account, amount)], EntryKind::Interest, amount)
+            };
+            self.post_with(Posting::new("interest", legs), true)?;
+            let seq = self.change_seq();
+            self.record(seq, account, kind, None, abs);
+            let balance = self.get_balance(account).unwrap_or(Balance::ZERO);
+            self.emit(Event::InterestPosted { account: account.clone(), amount, balance });
+            Ok(())
+        }
+
+        /// Проводит все регулярные платежи, попытки которых назначены не позже `now`,
+        /// по порядку назначенного времени. Перевод выполняется как транзакция,
+        /// назначенная на это время: так пропущенные платежи ложатся в историю своими датами.
+        ///
+        /// Каждая попытка — успешная или нет — записывается в журнал попыток
+        /// и в журнал хранилища. Ошибка возвращается, только если не удалась запись в журнал
+        pub fn run_due(&mut self, now: Timestamp) -> Result<Vec<RunRecord>, StorageError> {
+            let mut runs = Vec::new();
+            while let Some(schedule) = self.schedules.due(now).cloned() {
+                let tx = schedule.transfer();
+                let at = schedule.next_run;
+                let (run, result) = self.atomically_at(at, |storage| {
+                    let result = storage.atomically(|storage| storage.as_one_change(|storage| tx.apply(storage)));
+                    let (status, error) = match &result {
+                        Ok(()) => (RunStatus::Applied, None),
+                        Err(e) => (schedule.status_after_failure(), Some(e.to_string())),
+                    };
+                    let run = RunRecord {
+                        schedule: schedule.id,
+                        due: schedule.due,
+                        at,
+                        attempt: schedule.attempt(),
+                        status,
+                        error,
+                    };
+                    storage.log_schedule(run.schedule);
+                    storage.schedules.record(run.clone())?;
+                    storage.append(at, Record::Run(run.clone()))?;
+                    Ok::<_, StorageError>((run, result))
+                })?;
+                // Отказ уже учтён в попытке; подписчики узнают о нём из события
+                let _ = self.report(result, || tx.operations());
+                runs.push(run);
+            }
+            Ok(runs)
+        }
+
+        /// Пробный прогон транзакции на копии балансов: без журнала, истории
+        /// и ключей идемпотентности. Возвращает изменения балансов клиентов
+        pub fn simulate<T: Transaction + ?Sized>(&self, tx: &T) -> Result<BalanceDeltas, TxError> {
+            let mut scratch = Storage {
+                accounts: self.accounts.clone(),
+                ledger: self.ledger.clone(),
+                overdrafts: self.overdrafts.clone(),
+                holds: self.holds.clone(),
+                // История нужна для лимитов комиссий
+                history: self.history.clone(),
+                fees: self.fees.clone(),
+                ..Storage::new()
+            };
+            tx.apply(&mut scratch)?;
+
+            let mut deltas = BalanceDeltas::new();
+            for (name, balance) in scratch.accounts {
+                let before = self.get_balance(&name).unwrap_or(Balance::ZERO);
+                let delta = balance.checked_sub(before).ok_or(TxError::Overflow)?;
+                if !delta.is_zero() {
+                    deltas.insert(name, delta);
+                }
+            }
+            Ok(deltas)
+        }
+
+        /// Отменяет изменение `seq` компенсирующей транзакцией и записывает её в журнал.
+        ///
+        /// Операции изменения восстанавливаются по истории и отменяются в обратном порядке,
+        /// поэтому цепочка отменяется целиком. Записи отмены в истории ссылаются на `seq`.
+        /// Отменить можно только депозиты, снятия и переводы, и только один раз.
+        /// Комиссия за отменяемые операции не возвращается, а за отмену — не берётся
+        pub fn reverse(&mut self, seq: u64) -> Result<(), TxError> {
+            let ops: Vec<Operation> = self.change_operations(seq)?.iter().rev().map(Operation::reversed).collect();
+            let time = self.now();
+            let result = self.atomically_at(time, |storage| {
+                storage.apply_reversal(seq, &ops)?;
+                storage.append(time, Record::Reverse { original: seq, ops: ops.clone() })?;
+                Ok(())
+            });
+            self.report(result, || ops)
+        }
+
+        /// Операции, из которых состояло изменение `seq`, восстановленные по истории
+        fn change_operations(&self, seq: u64) -> Result<Vec<Operation>, StorageError> {
+            let irreversible = |message: &str| StorageError::Irreversible { seq, message: message.to_string() };
+            if let Some(by) = self.history.reversed_by(seq) {
+                return Err(irreversible(&format!("уже отменено изменением #{}", by)));
+            }
+
+            let mut ops = Vec::new();
+            for entry in self.history.change(seq) {
+                let (account, amount) = (entry.account.clone(), entry.amount);
+                match (entry.kind, &entry.counterparty) {
+                    (EntryKind::Deposit, _) => ops.push(Operation::Deposit(Deposit { account, amount })),
+                    (EntryKind::Withdraw, _) => ops.push(Operation::Withdraw(Withdraw { account, amount })),
+                    (EntryKind::TransferOut, Some(to)) => {
+                        ops.push(Operation::Transfer(Transfer { from: account, to: to.clone(), amount }))
+                    }
+                    // Вторая сторона перевода уже учтена по исходящей записи
+                    (EntryKind::TransferIn, _) => {}
+                    // Комиссия не возвращается
+                    (EntryKind::Fee, _) => {}
+                    _ => return Err(irreversible("отменяются только депозиты, снятия и переводы")),
+                }
+            }
+            if ops.is_empty() {
+                return Err(irreversible("такого изменения нет в истории"));
+            }
+            Ok(ops)
+        }
+
+        /// Применяет операции отмены как одно изменение со ссылкой на `original`
+        fn apply_reversal(&mut self, original: u64, ops: &[Operation]) -> Result<(), TxError> {
+            let previous = self.reversing.replace(original);
+            let result = self.as_one_change(|storage| ops.iter().try_for_each(|op| op.apply(storage)));
+            self.reversing = previous;
+            result
+        }
+
+        fn append(&mut self, time: Timestamp, record: Record) -> Result<(), StorageError> {
+            if let Some(journal) = self.journal.as_mut() {
+                self.journal_seq = journal.append(time, record)?;
+            }
+            Ok(())
+        }
+
+        /// Повторно применяет запись журнала при загрузке (журнал в этот момент не подключён)
+        fn replay(&mut self, entry: JournalEntry) -> Result<(), StorageError> {
+            let JournalEntry { seq, timestamp, record } = entry;
+            let result = self.atomically_at(timestamp, |storage| match record {
+                Record::Open { name, balance } => {
+                    storage.open_account(name, balance).map_err(|e| e.to_string())
+                }
+                Record::Close { name } => storage.close_account(&name).map(drop).map_err(|e| e.to_string()),
+                Record::Commit(ops) => storage
+                    .atomically(|storage| storage.as_one_change(|storage| ops.iter().try_for_each(|op| op.apply(storage))))
+                    .map_err(|e| e.to_string()),
+                Record::KeyedSteps { keys, ops } => storage
+                    .atomically(|storage| {
+                        storage.as_one_change(|storage| ops.iter().try_for_each(|op| op.apply(storage)))?;
+                        storage.remember_steps(&keys.into_iter().zip(ops).collect::<Vec<_>>());
+                        Ok::<_, TxError>(())
+                    })
+                    .map_err(|e| e.to_string()),
+                Record::Reverse { original, ops } => storage
+                    .atomically(|storage| storage.apply_reversal(original, &ops))
+                    .map_err(|e| e.to_string()),
+                Record::Schedule(schedule) => {
+                    storage.schedules.insert(schedule);
+                    Ok(())
+                }
+                Record::Unschedule { id } => storage.schedules.remove(id).map(drop).map_err(|e| e.to_string()),
+                Record::Run(run) => {
+                    let schedule = storage.schedules.get(run.schedule).ok_or_else(|| {
+                        StorageError::ScheduleNotFound(run.schedule).to_string()
+                    })?;
+                    let tx = schedule.transfer();
+                    storage
+                        .atomically(|storage| {
+                            if run.status == RunStatus::Applied {
+                                storage.as_one_change(|storage| tx.apply(storage))?;
+                            }
+                            storage.log_schedule(run.schedule);
+                            storage.schedules.record(run)?;
+                            Ok::<_, TxError>(())
+                        })
+                        .map_err(|e| e.to_string())
+                }
+                Record::Interest { account, product, start } => {
+                    storage.interest.set(&account, product, start);
+                    Ok(())
+                }
+                Record::InterestOff { account } => {
+                    storage.interest.remove(&account);
+                    Ok(())
+                }
+                Record::Accrue { through } => storage.accrue_through(through).map(drop).map_err(|e| e.to_string()),
+                Record::Fees(schedule) => {
+                    storage.fees = schedule;
+                    Ok(())
+                }
+                Record::Overdraft { account, limit } => {
+                    storage.apply_overdraft(&account, limit);
+                    Ok(())
+                }
+                Record::Hold(hold) => {
+                    storage.holds.insert(hold);
+                    Ok(())
+                }
+                Record::Capture { id, amount, to } => {
+                    let hold = storage.holds.get(id).ok_or_else(|| StorageError::HoldNotFound(id).to_string())?;
+                    let op = Storage::capture_operation(hold, amount, to.as_ref());
+                    storage
+                        .atomically(|storage| storage.apply_capture(id, amount, &op))
+                        .map_err(|e| e.to_string())
+                }
+                Record::Release { id } => storage.holds.remove(id).map(drop).map_err(|e| e.to_string()),
+                Record::Keyed { key, outcome, ops } => {
+                    storage.keys.insert(key, outcome, idempotency::fingerprint(&ops));
+                    if outcome != Outcome::Applied {
+                        return Ok(());
+                    }
+                    storage
+                        .atomically(|storage| storage.as_one_change(|storage| ops.iter().try_for_each(|op| op.apply(storage))))
+                        .map_err(|e| e.to_string())
+                }
+            });
+            self.journal_seq = seq;
+            result.map_err(|message| StorageError::Journal { seq, message })
+        }
+
+        /// Выполняет `f` по принципу «всё или ничего»: если `f` вернула ошибку,
+        /// хранилище возвращается ровно в то состояние, в котором было до вызова
+        pub fn atomically<T, E, F>(&mut self, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Storage) -> Result<T, E>,
+        {
+            let checkpoint = self.checkpoint();
+            self.nesting += 1;
+            let result = f(self);
+            self.nesting -= 1;
+            if result.is_err() {
+                self.rollback(checkpoint);
+            }
+            if self.nesting == 0 {
+                self.undo.clear();
+                self.events.flush();
+            }
+            result
+        }
+
+        /// Как [`Storage::atomically`], но все изменения внутри `f` происходят в момент `time`
+        fn atomically_at<T, E, F>(&mut self, time: Timestamp, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Storage) -> Result<T, E>,
+        {
+            let previous = self.op_time.replace(time);
+            let result = self.atomically(f);
+            self.op_time = previous;
+            result
+        }
+
+        fn checkpoint(&self) -> Checkpoint {
+            Checkpoint {
+                undo: self.undo.len(),
+                history_len: self.history.len(),
+                history_seq: self.history.peek_seq(),
+                events: self.events.pending_len(),
+            }
+        }
+
+        fn rollback(&mut self, checkpoint: Checkpoint) {
+            for undo in self.undo.split_off(checkpoint.undo).into_iter().rev() {
+                self.revert(undo);
+            }
+            self.history.truncate(checkpoint.history_len, checkpoint.history_seq);
+            self.events.discard_after(checkpoint.events);
+        }
+
+        fn revert(&mut self, undo: Undo) {
+            match undo {
+                Undo::Balance { account, client, ledger } => {
+                    match client {
+                        Some(balance) => self.accounts.insert(account.clone(), balance),
+                        None => self.accounts.remove(&account),
+                    };
+                    self.ledger.restore(&account, ledger);
+                }
+                Undo::Key(key) => self.keys.remove(&key),
+                Undo::Schedule { id, schedule, runs, next_id } => self.schedules.restore(id, schedule, runs, next_id),
+                Undo::Interest { account, accrual } => self.interest.restore(&account, accrual),
+                Undo::Fees(fees) => self.fees = fees,
+                Undo::Overdraft { account, limit } => {
+                    match limit {
+                        Some(limit) => self.overdrafts.insert(account, limit),
+                        None => self.overdrafts.remove(&account),
+                    };
+                }
+                Undo::Hold { id, hold, next_id } => self.holds.restore(id, hold, next_id),
+            }
+        }
+
+        /// Запоминает, как отменить изменение. Вне [`Storage::atomically`] откатывать
+        /// нечего, и журнал отмены не растёт
+        fn log(&mut self, undo: Undo) {
+            if self.nesting > 0 {
+                self.undo.push(undo);
+            }
+        }
+
+        fn log_balance(&mut self, account: &str) {
+            if self.nesting > 0 {
+                let client = self.accounts.get(account).copied();
+                let ledger = self.ledger.get(account);
+                self.log(Undo::Balance { account: account.to_string(), client, ledger });
+            }
+        }
+
+        fn log_schedule(&mut self, id: u64) {
+            if self.nesting > 0 {
+                let schedule = self.schedules.get(id).cloned();
+                let (runs, next_id) = (self.schedules.runs().len(), self.schedules.next_id());
+                self.log(Undo::Schedule { id, schedule, runs, next_id });
+            }
+        }
+
+        fn log_interest(&mut self, account: &str) {
+            if self.nesting > 0 {
+                let accrual = self.interest.get(account).cloned();
+                self.log(Undo::Interest { account: account.to_string(), accrual });
+            }
+        }
+
+        fn log_overdraft(&mut self, account: &str) {
+            if self.nesting > 0 {
+                let limit = self.overdrafts.get(account).copied();
+                self.log(Undo::Overdraft { account: account.to_string(), limit });
+            }
+        }
+
+        fn log_hold(&mut self, id: u64) {
+            if self.nesting > 0 {
+                let hold = self.holds.get(id).cloned();
+                self.log(Undo::Hold { id, hold, next_id: self.holds.next_id() });
+            }
+        }
+
+        /// Открывает `<file>.lock` и берёт на нём исключительную блокировку, не дожидаясь её
+        fn lock_file(path: &Path) -> Result<File, StorageError> {
+            let lock_path = persist::with_suffix(path, ".lock");
+            let lock = File::options().create(true).truncate(false).write(true).open(&lock_path)?;
+            match lock.try_lock() {
+                Ok(()) => Ok(lock),
+                Err(TryLockError::WouldBlock) => Err(StorageError::Locked(lock_path)),
+                Err(TryLockError::Error(e)) => Err(e.into()),
+            }
+        }
+
+        pub fn get_all(&self) -> Vec<(Name, Balance)> {
+            self.accounts.iter().map(|(n, b)| (n.clone(), *b)).collect()
+        }
+
+        /// Загружает самый свежий целый снапшот (`<file>` или одну из его резервных
+        /// копий `<file>.bak.N`) и проигрывает поверх него только хвост журнала
+        /// `<file>.journal`. Если нет ни снапшота, ни журнала, создаёт хранилище
+        /// с дефолтными пользователями.
+        ///
+        /// Загруженное хранилище пишет все дальнейшие изменения в тот же журнал.
+        /// Пока оно живо, `<file>.lock` заблокирован: второй процесс с тем же файлом
+        /// сразу получает [`StorageError::Locked`], а не пишет в журнал вперемешку
+        pub fn load_data(file: &str) -> Result<Storage, StorageError> {
+            let path = Path::new(file);
+            let lock = Storage::lock_file(path)?;
+            let (mut journal, records) = Journal::open(&Journal::path_for(path))?;
+
+            // Снапшот пригоден, только если журнал продолжает его без пропусков
+            let first_seq = records.first().map(|entry| entry.seq);
+            let covered = |storage: &Storage| first_seq.is_none_or(|first| first <= storage.journal_seq + 1);
+
+            let mut snapshots = Vec::new();
+            let mut first_error = None;
+            for candidate in Storage::snapshot_candidates(path) {
+                match Storage::read_file(&candidate) {
+                    Ok(storage) if covered(&storage) => snapshots.push(storage),
+                    Ok(_) => {}
+                    Err(e) => {
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+
+            let fresh = first_error.is_none() && snapshots.is_empty() && records.is_empty();
+            // При равных номерах предпочитаем основной файл: он идёт в списке первым
+            let newest = snapshots
+                .into_iter()
+                .reduce(|best, storage| if storage.journal_seq > best.journal_seq { storage } else { best });
+            let mut storage = match newest {
+                Some(storage) => storage,
+                None => match first_error {
+                    // Есть файлы снапшотов, но ни одного целого — молча начинать с нуля нельзя
+                    Some(e) => return Err(e),
+                    None => Storage::new(),
+                },
+            };
+
+            // Записи, которые уже учтены в снапшоте, пропускаем
+            for entry in records {
+                if entry.seq > storage.journal_seq {
+                    storage.replay(entry)?;
+                }
+            }
+
+            // Новые записи должны получать номера после тех, что учтены в снапшоте
+            journal.continue_after(storage.journal_seq);
+            storage.journal = Some(journal);
+            storage.lock = Some(lock);
+
+            if fresh {
                 // если файла нет, создаём пользователей с нуля
                 for u in ["John", "Alice", "Bob", "Vasya"] {
-                    storage.add_user(u.to_string());
+                    storage.open_account(u.to_string(), Balance::ZERO)?;
                 }
             }
 
-            storage
+            Ok(storage)
         }
 
-        /// Сохраняет текущее состояние Storage в CSV-файл
-        pub fn save(&self, file: &str) {
+        /// Сохраняет новый снапшот и удаляет из журнала записи, которые больше не нужны
+        /// ни одному из хранимых снапшотов. После этого загрузка проигрывает только
+        /// изменения, сделанные после компактизации
+        pub fn compact(&mut self, file: &str, options: &SaveOptions) -> Result<(), StorageError> {
+            self.archive_history(Path::new(file))?;
+            self.save_with(file, options)?;
+
+            let Some(journal) = self.journal.as_mut() else {
+                return Ok(());
+            };
+
+            // Старые резервные копии тоже должны оставаться пригодными,
+            // поэтому журнал обрезается по самому старому из них
+            let path = Path::new(file);
+            let oldest = Storage::snapshot_candidates(path)
+                .iter()
+                .take(options.backups + 1)
+                .filter_map(|candidate| Storage::read_file(candidate).ok())
+                .map(|storage| storage.journal_seq)
+                .min()
+                .unwrap_or(self.journal_seq);
+            journal.truncate_through(oldest)
+        }
+
+        /// Переносит записи истории старше [`Storage::HISTORY_DAYS`] в файл архива
+        /// `<file>.history.<номер первой записи>`, чтобы снапшот не рос вместе с историей.
+        /// Если снапшот после переноса не сохранился, следующая попытка начнёт с той же
+        /// записи и перепишет тот же файл
+        fn archive_history(&mut self, path: &Path) -> Result<(), StorageError> {
+            let before = self.now().saturating_sub(Storage::HISTORY_DAYS * SECONDS_PER_DAY) / SECONDS_PER_DAY * SECONDS_PER_DAY;
+            let count = self.history.archivable(before);
+            let Some(first) = self.history.entries().first().filter(|_| count > 0) else {
+                return Ok(());
+            };
             let mut data = String::new();
+            for entry in &self.history.entries()[..count] {
+                data.push_str(&format!("{}\n", entry));
+            }
+            persist::write_atomic(&persist::with_suffix(path, &format!(".history.{}", first.seq)), data.as_bytes())?;
+            self.history.archive(count, before);
+            Ok(())
+        }
+
+        /// Компактизация с одной резервной копией, которую вызывают программы по ходу работы.
+        /// Каждое изменение к этому моменту уже записано в журнал, поэтому снапшот только
+        /// ускоряет следующую загрузку, а ошибка сохранения ничего не теряет
+        pub fn save_progress(&mut self, file: &str) -> Result<(), StorageError> {
+            self.compact(file, &SaveOptions { backups: 1 })
+        }
+
+        /// Существующие файлы снапшотов: основной и резервные копии по порядку
+        fn snapshot_candidates(path: &Path) -> Vec<PathBuf> {
+            let mut candidates = Vec::new();
+            if path.exists() {
+                candidates.push(path.to_path_buf());
+            }
+            for n in 1.. {
+                let backup = persist::backup_path(path, n);
+                if !backup.exists() {
+                    break;
+                }
+                candidates.push(backup);
+            }
+            candidates
+        }
+
+        fn read_file(path: &Path) -> Result<Storage, StorageError> {
+            // Оборачиваем файл в BufReader
+            // BufReader читает данные блоками и хранит их в буфере,
+            // поэтому построчное чтение (lines()) работает быстрее, чем читать по байту
+            let reader = io::BufReader::new(File::open(path)?);
+            Storage::read_from(reader)
+        }
+
+        /// Читает балансы в формате "Name,Balance" из любого BufRead.
+        ///
+        /// Снапшот с заголовком `#journal=` обязан заканчиваться строкой `#checksum=`
+        /// с верной контрольной суммой — иначе он считается повреждённым.
+        /// Старые файлы без заголовка читаются как есть
+        pub fn read_from<R: BufRead>(reader: R) -> Result<Storage, StorageError> {
+            let mut storage = Storage::new();
+            let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+            Storage::verify_checksum(&lines)?;
+            let (mut archived_before, mut next_seq, mut opening) = (0, 0, HashMap::new());
+
+            for (index, line) in lines.iter().enumerate() {
+                let line_no = index + 1;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with(CHECKSUM_FOOTER) {
+                    continue;
+                }
+                if let Some(entry) = line.strip_prefix(HISTORY_PREFIX) {
+                    let entry = entry
+                        .parse()
+                        .map_err(|message| StorageError::Parse { line: line_no, message })?;
+                    storage.history.push(entry);
+                    continue;
+                }
+                if let Some(archive) = line.strip_prefix(HISTORY_ARCHIVE_PREFIX) {
+                    let parsed = archive.split_once(',').and_then(|(before, seq)| Some((before.parse().ok()?, seq.parse().ok()?)));
+                    (archived_before, next_seq) = parsed.ok_or_else(|| StorageError::Parse {
+                        line: line_no,
+                        message: format!("ожидается \"#history_archive=<время>,<номер>\", получено \"{}\"", line),
+                    })?;
+                    continue;
+                }
+                if let Some(entry) = line.strip_prefix(HISTORY_OPENING_PREFIX) {
+                    let parsed = entry.split_once(',').and_then(|(account, balance)| Some((account, balance.parse().ok()?)));
+                    let (account, balance): (&str, Balance) = parsed.ok_or_else(|| StorageError::Parse {
+                        line: line_no,
+                        message: format!("ожидается \"#history_opening=<account>,<balance>\", получено \"{}\"", line),
+                    })?;
+                    opening.insert(account.to_string(), balance);
+                    continue;
+                }
+                if let Some(entry) = line.strip_prefix(KEY_PREFIX) {
+                    let invalid = |message: String| StorageError::Parse { line: line_no, message };
+                    let parts: Vec<&str> = entry.splitn(3, ',').collect();
+                    let [key, outcome, fingerprint] = parts[..] else {
+                        return Err(invalid(format!("ожидается \"#key=<key>,<outcome>,<отпечаток>\", получено \"{}\"", line)));
+                    };
+                    if !idempotency::is_valid_key(key) {
+                        return Err(invalid(format!("некорректный ключ идемпотентности \"{}\"", key)));
+                    }
+                    let fingerprint = u64::from_str_radix(fingerprint, 16)
+                        .map_err(|_| invalid(format!("некорректный отпечаток транзакции \"{}\"", fingerprint)))?;
+                    storage.keys.insert(key.to_string(), outcome.parse().map_err(invalid)?, fingerprint);
+                    continue;
+                }
+                if let Some(schedule) = line.strip_prefix(SCHEDULE_PREFIX) {
+                    let schedule = schedule
+                        .parse()
+                        .map_err(|message| StorageError::Parse { line: line_no, message })?;
+                    storage.schedules.insert(schedule);
+                    continue;
+                }
+                if let Some(run) = line.strip_prefix(RUN_PREFIX) {
+                    let run: RunRecord = run
+                        .parse()
+                        .map_err(|message| StorageError::Parse { line: line_no, message })?;
+                    // Попытки уже учтены в сохранённом состоянии платежей
+                    storage.schedules.restore_run(run);
+                    continue;
+                }
+                if let Some(entry) = line.strip_prefix(INTEREST_PREFIX) {
+                    let invalid = |message: String| StorageError::Parse { line: line_no, message };
+                    let (account, accrual) = entry
+                        .split_once(',')
+                        .ok_or_else(|| invalid(format!("ожидается \"#interest=<account>,<начисление>\", получено \"{}\"", line)))?;
+                    let accrual: Accrual = accrual.parse().map_err(invalid)?;
+                    storage.interest.insert(account.to_string(), accrual);
+                    continue;
+                }
+                if let Some(rule) = line.strip_prefix(FEE_PREFIX) {
+                    let rule = rule
+                        .parse()
+                        .map_err(|message| StorageError::Parse { line: line_no, message })?;
+                    storage.fees = storage.fees.clone().with(rule);
+                    continue;
+                }
+                if let Some(entry) = line.strip_prefix(OVERDRAFT_PREFIX) {
+                    let invalid = |message: String| StorageError::Parse { line: line_no, message };
+                    let (account, limit) = entry
+                        .split_once(',')
+                        .ok_or_else(|| invalid(format!("ожидается \"#overdraft=<account>,<limit>\", получено \"{}\"", line)))?;
+                    let limit = limit.parse().map_err(|e: crate::ParseMoneyError| invalid(e.to_string()))?;
+                    storage.overdrafts.insert(account.to_string(), limit);
+                    continue;
+                }
+                if let Some(hold) = line.strip_prefix(HOLD_PREFIX) {
+                    let hold = hold
+                        .parse()
+                        .map_err(|message| StorageError::Parse { line: line_no, message })?;
+                    storage.holds.insert(hold);
+                    continue;
+                }
+                if let Some(id) = line.strip_prefix(HOLD_ID_PREFIX) {
+                    let id = id.parse().map_err(|_| StorageError::Parse {
+                        line: line_no,
+                        message: format!("некорректный номер удержания \"{}\"", id),
+                    })?;
+                    storage.holds.set_next_id(id);
+                    continue;
+                }
+                if let Some(id) = line.strip_prefix(SCHEDULE_ID_PREFIX) {
+                    let id = id.parse().map_err(|_| StorageError::Parse {
+                        line: line_no,
+                        message: format!("некорректный номер платежа \"{}\"", id),
+                    })?;
+                    storage.schedules.set_next_id(id);
+                    continue;
+                }
+                if let Some(seq) = line.strip_prefix(JOURNAL_HEADER) {
+                    storage.journal_seq = seq.parse().map_err(|_| StorageError::Parse {
+                        line: line_no,
+                        message: format!("некорректный номер записи журнала \"{}\"", seq),
+                    })?;
+                    continue;
+                }
+
+                // Разделяем строку по запятой: "Name,Balance"
+                let (name, balance) = line.split_once(',').ok_or_else(|| StorageError::Parse {
+                    line: line_no,
+                    message: format!("ожидается \"Name,Balance\", получено \"{}\"", line),
+                })?;
+                let balance: Balance = balance.trim().parse().map_err(|e| StorageError::Parse {
+                    line: line_no,
+                    message: format!("некорректный баланс \"{}\": {}", balance, e),
+                })?;
+
+                let name = name.trim();
+                if ledger::is_internal(name) {
+                    // Внутренний счёт банка: есть только в главной книге
+                    storage.ledger.set_balance(name, balance);
+                    continue;
+                }
+                match storage.accounts.entry(name.to_string()) {
+                    Entry::Occupied(entry) => {
+                        return Err(StorageError::Parse {
+                            line: line_no,
+                            message: format!("пользователь {} встречается повторно", entry.key()),
+                        });
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(balance);
+                        storage.ledger.set_balance(name, balance);
+                    }
+                }
+            }
+
+            storage.history.restore_archive(archived_before, next_seq, opening);
+
+            // В старых файлах нет внутренних счетов: откуда взялись деньги клиентов,
+            // неизвестно, поэтому разница относится на транзитный счёт
+            let net = storage.ledger.net()?;
+            if !net.is_zero() {
+                let suspense = storage.ledger.balance(ledger::SUSPENSE);
+                storage
+                    .ledger
+                    .set_balance(ledger::SUSPENSE, suspense.checked_sub(net).ok_or(StorageError::Overflow)?);
+            }
+
+            Ok(storage)
+        }
+
+        fn verify_checksum(lines: &[String]) -> Result<(), StorageError> {
+            if !lines.first().is_some_and(|line| line.starts_with(JOURNAL_HEADER)) {
+                return Ok(());
+            }
+
+            let corrupted = |message: &str| StorageError::Parse {
+                line: lines.len(),
+                message: message.to_string(),
+            };
+            let (last, body) = lines.split_last().ok_or_else(|| corrupted("пустой снапшот"))?;
+            let expected = last
+                .strip_prefix(CHECKSUM_FOOTER)
+                .ok_or_else(|| corrupted("снапшот оборван: нет контрольной суммы"))?;
+
+            let mut data = String::new();
+            for line in body {
+                data.push_str(line);
+                data.push('\n');
+            }
+            if format!("{:016x}", persist::checksum(data.as_bytes())) != expected.trim() {
+                return Err(corrupted("контрольная сумма снапшота не совпадает"));
+            }
+            Ok(())
+        }
+
+        /// Сохраняет текущее состояние Storage в CSV-файл
+        pub fn save(&self, file: &str) -> Result<(), StorageError> {
+            self.save_with(file, &SaveOptions::default())
+        }
+
+        /// Сохраняет состояние в CSV-файл атомарно: сначала во временный файл,
+        /// затем fsync и переименование поверх `file`. При сбое на диске
+        /// остаётся либо старая, либо новая версия файла целиком.
+        ///
+        /// Первой строкой пишется номер последней учтённой записи журнала,
+        /// чтобы при загрузке не применить её второй раз
+        pub fn save_with(&self, file: &str, options: &SaveOptions) -> Result<(), StorageError> {
+            let mut data = format!("{}{}\n", JOURNAL_HEADER, self.journal_seq);
 
             // Собираем все данные в одну строку формата "Name,Balance"
             for (name, balance) in self.get_all() {
                 data.push_str(&format!("{},{}\n", name, balance));
             }
+            // Внутренние счета банка, чтобы главная книга после загрузки сходилась
+            for (name, balance) in self.ledger.balances() {
+                if ledger::is_internal(name) {
+                    data.push_str(&format!("{},{}\n", name, balance));
+                }
+            }
+            // Недавняя история хранится в том же файле, чтобы она всегда соответствовала балансам;
+            // от перенесённой в архив остаются только балансы счетов после неё
+            for entry in self.history.entries() {
+                data.push_str(&format!("{}{}\n", HISTORY_PREFIX, entry));
+            }
+            if self.history.archived_before() > 0 {
+                let (before, next_seq) = (self.history.archived_before(), self.history.peek_seq());
+                data.push_str(&format!("{}{},{}\n", HISTORY_ARCHIVE_PREFIX, before, next_seq));
+            }
+            for (account, balance) in self.history.opening() {
+                data.push_str(&format!("{}{},{}\n", HISTORY_OPENING_PREFIX, account, balance));
+            }
+
+            // Ключи идемпотентности сохраняются вместе с балансами, к которым привели
+            for (key, outcome, fingerprint) in self.keys.iter() {
+                data.push_str(&format!("{}{},{},{:016x}\n", KEY_PREFIX, key, outcome, fingerprint));
+            }
+
+            // Регулярные платежи — в том состоянии, к которому привели уже проведённые попытки
+            if !self.schedules.is_empty() || !self.schedules.runs().is_empty() {
+                data.push_str(&format!("{}{}\n", SCHEDULE_ID_PREFIX, self.schedules.next_id()));
+            }
+            for schedule in self.schedules.iter() {
+                data.push_str(&format!("{}{}\n", SCHEDULE_PREFIX, schedule));
+            }
+            for run in self.schedules.runs() {
+                data.push_str(&format!("{}{}\n", RUN_PREFIX, run));
+            }
+            // Начисленные, но не выплаченные проценты — с точностью до долей копейки
+            for (account, accrual) in self.interest.iter() {
+                data.push_str(&format!("{}{},{}\n", INTEREST_PREFIX, account, accrual));
+            }
+            // Тариф комиссий: от него зависит, как проиграются следующие записи журнала
+            for rule in self.fees.rules() {
+                data.push_str(&format!("{}{}\n", FEE_PREFIX, rule));
+            }
+            for (account, limit) in &self.overdrafts {
+                data.push_str(&format!("{}{},{}\n", OVERDRAFT_PREFIX, account, limit));
+            }
+            // Номер пишется всегда, когда удержания были: снятые номера не переиспользуются
+            if self.holds.next_id() > 1 {
+                data.push_str(&format!("{}{}\n", HOLD_ID_PREFIX, self.holds.next_id()));
+            }
+            for hold in self.holds.iter() {
+                data.push_str(&format!("{}{}\n", HOLD_PREFIX, hold));
+            }
+
+            data.push_str(&format!("{}{:016x}\n", CHECKSUM_FOOTER, persist::checksum(data.as_bytes())));
 
-            // Записываем в файл
-            // Здесь мы не используем BufWriter, потому что сразу пишем всю строку целиком.
-            fs::write(file, data).expect("Не удалось записать файл");
+            let path = Path::new(file);
+            persist::rotate_backups(path, options.backups)?;
+            persist::write_atomic(path, data.as_bytes())?;
+            Ok(())
         }
     }
-}
\ No newline at end of file
+}
@@ -1,56 +1,226 @@
 pub mod transaction {
+    use std::collections::BTreeMap;
     use std::error::Error;
     use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
     use my_macros::Transaction;
+    use crate::{Balance, Name};
     use crate::Storage;
+    use crate::StorageError;
     use crate::impl_add;
 
     #[derive(Debug)]
     pub enum TxError {
-        InsufficientFunds,
+        /// Доступно `available` (баланс плюс лимит овердрафта), а списать нужно `requested`
+        InsufficientFunds { available: Balance, requested: Balance },
         InvalidAccount,
+        /// Сумма не положительна или слишком велика
+        InvalidAmount,
+        /// Баланс после операции не помещается в Balance
+        Overflow,
+        /// Прочие ошибки хранилища, у которых нет своего варианта в TxError
+        Storage(StorageError),
+        /// Шаг пакета с номером `index` (с нуля) завершился ошибкой `source`
+        Step { index: usize, source: Box<TxError> },
+    }
+
+    impl TxError {
+        /// Исходная ошибка без обёрток [`TxError::Step`] вложенных пакетов
+        pub fn root(&self) -> &TxError {
+            match self {
+                TxError::Step { source, .. } => source.root(),
+                e => e,
+            }
+        }
     }
 
     impl Display for TxError {
         fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
             match self {
-                TxError::InsufficientFunds => { write!(f, "Не хватает денег на балансе") },
+                TxError::InsufficientFunds { available, requested } => {
+                    write!(f, "Не хватает денег на балансе: доступно {}, требуется {}", available, requested)
+                }
                 TxError::InvalidAccount => {write!(f, "Неверный аккаунт") }
+                TxError::InvalidAmount => { write!(f, "Некорректная сумма транзакции") }
+                TxError::Overflow => { write!(f, "Переполнение баланса") }
+                TxError::Storage(e) => { write!(f, "Ошибка хранилища: {}", e) }
+                TxError::Step { index, source } => { write!(f, "Шаг {}: {}", index + 1, source) }
             }
         }
     }
 
-    impl Error for TxError {}
+    impl Error for TxError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            match self {
+                TxError::Storage(e) => Some(e),
+                TxError::Step { source, .. } => Some(source.as_ref()),
+                _ => None,
+            }
+        }
+    }
 
+    impl From<StorageError> for TxError {
+        fn from(e: StorageError) -> Self {
+            match e {
+                StorageError::NotFound(_) => TxError::InvalidAccount,
+                StorageError::InsufficientFunds { available, requested } => TxError::InsufficientFunds { available, requested },
+                StorageError::InvalidAmount(_) => TxError::InvalidAmount,
+                StorageError::Overflow => TxError::Overflow,
+                e => TxError::Storage(e),
+            }
+        }
+    }
+
+    /// Цепочка из двух транзакций, выполняемая по принципу «всё или ничего»:
+    /// если `t2` завершилась ошибкой, изменения `t1` откатываются
     pub struct TxCombinator<T1: Transaction, T2: Transaction> {
         pub t1: T1,
         pub t2: T2,
     }
 
+    /// Изменения балансов клиентов, которые произвела бы транзакция: счёт -> разница.
+    /// Счета, баланс которых в итоге не изменился, не включаются
+    pub type BalanceDeltas = BTreeMap<Name, Balance>;
+
     pub trait Transaction {
         fn apply(&self, accounts: &mut Storage) -> Result<(), TxError>;
+
+        /// Элементарные операции, из которых состоит транзакция, в порядке применения.
+        /// Именно они записываются в журнал и проигрываются при загрузке
+        fn operations(&self) -> Vec<Operation>;
+
+        /// Компенсирующая транзакция, которая отменяет действие этой:
+        /// депозит отменяется снятием, перевод — переводом в обратную сторону,
+        /// цепочка — цепочкой отмен её шагов в обратном порядке
+        fn reverse(&self) -> Box<dyn Transaction>;
+
+        /// Пробный прогон: применяет транзакцию к копии `storage` и возвращает
+        /// изменения балансов или первую ошибку. Само хранилище не меняется
+        fn simulate(&self, storage: &Storage) -> Result<BalanceDeltas, TxError> {
+            storage.simulate(self)
+        }
+
+        /// Проверяет, что транзакция выполнилась бы без ошибок, ничего не меняя
+        fn validate(&self, storage: &Storage) -> Result<(), TxError> {
+            self.simulate(storage).map(drop)
+        }
+    }
+
+    impl<T: Transaction + ?Sized> Transaction for Box<T> {
+        fn apply(&self, accounts: &mut Storage) -> Result<(), TxError> {
+            (**self).apply(accounts)
+        }
+
+        fn operations(&self) -> Vec<Operation> {
+            (**self).operations()
+        }
+
+        fn reverse(&self) -> Box<dyn Transaction> {
+            (**self).reverse()
+        }
     }
 
     impl<T1: Transaction, T2: Transaction> Transaction for TxCombinator<T1, T2> {
         fn apply(&self, accounts: &mut Storage) -> Result<(), TxError> {
-            self.t1.apply(accounts)?;
-            self.t2.apply(accounts)?;
-            Ok(())
+            let result = accounts.atomically(|accounts| {
+                self.t1.apply(accounts)?;
+                self.t2.apply(accounts)?;
+                Ok(())
+            });
+            accounts.report(result, || self.operations())
+        }
+
+        fn operations(&self) -> Vec<Operation> {
+            let mut ops = self.t1.operations();
+            ops.extend(self.t2.operations());
+            ops
+        }
+
+        fn reverse(&self) -> Box<dyn Transaction> {
+            Box::new(TxCombinator { t1: self.t2.reverse(), t2: self.t1.reverse() })
+        }
+    }
+
+    /// Пакет транзакций произвольных видов, собранный во время выполнения.
+    ///
+    /// Шаги применяются по порядку по принципу «всё или ничего»; ошибка шага
+    /// возвращается как [`TxError::Step`] с его номером
+    #[derive(Default)]
+    pub struct Batch {
+        steps: Vec<Box<dyn Transaction>>,
+    }
+
+    impl Batch {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Добавляет шаг в конец пакета
+        pub fn push(&mut self, tx: impl Transaction + 'static) -> &mut Self {
+            self.steps.push(Box::new(tx));
+            self
+        }
+
+        /// То же, что [`Batch::push`], но для цепочки вызовов при сборке
+        pub fn with(mut self, tx: impl Transaction + 'static) -> Self {
+            self.push(tx);
+            self
+        }
+
+        pub fn len(&self) -> usize {
+            self.steps.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.steps.is_empty()
+        }
+    }
+
+    impl From<Vec<Box<dyn Transaction>>> for Batch {
+        fn from(steps: Vec<Box<dyn Transaction>>) -> Self {
+            Batch { steps }
+        }
+    }
+
+    impl FromIterator<Box<dyn Transaction>> for Batch {
+        fn from_iter<I: IntoIterator<Item = Box<dyn Transaction>>>(iter: I) -> Self {
+            Batch { steps: iter.into_iter().collect() }
+        }
+    }
+
+    impl Transaction for Batch {
+        fn apply(&self, accounts: &mut Storage) -> Result<(), TxError> {
+            let result = accounts.atomically(|accounts| {
+                for (index, step) in self.steps.iter().enumerate() {
+                    step.apply(accounts)
+                        .map_err(|e| TxError::Step { index, source: Box::new(e) })?;
+                }
+                Ok(())
+            });
+            accounts.report(result, || self.operations())
+        }
+
+        fn operations(&self) -> Vec<Operation> {
+            self.steps.iter().flat_map(|step| step.operations()).collect()
+        }
+
+        fn reverse(&self) -> Box<dyn Transaction> {
+            Box::new(self.steps.iter().rev().map(|step| step.reverse()).collect::<Batch>())
         }
     }
 
-    #[derive(Transaction)]
+    #[derive(Debug, Clone, PartialEq, Transaction)]
     pub struct Deposit {
         pub account: String,
-        pub amount: i64,
+        pub amount: Balance,
     }
 
-    #[derive(Transaction)]
+    #[derive(Debug, Clone, PartialEq, Transaction)]
     #[transaction("transfer")]
     pub struct Transfer {
         pub from: String,
         pub to: String,
-        pub amount: i64,
+        pub amount: Balance,
     }
 
     impl Display for Transfer {
@@ -66,11 +236,93 @@ pub mod transaction {
         (Transfer, Transfer)
     }
 
-    #[derive(Transaction)]
+    #[derive(Debug, Clone, PartialEq, Transaction)]
     #[transaction("withdraw")]
     pub struct Withdraw {
         pub account: String,
-        pub amount: i64,
+        pub amount: Balance,
+    }
+
+    /// Одна элементарная операция, известная только во время выполнения.
+    ///
+    /// Текстовый вид — `deposit Alice 100`, `withdraw Bob 5`, `transfer Alice Bob 30`;
+    /// в нём операции хранятся в журнале
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Operation {
+        Deposit(Deposit),
+        Withdraw(Withdraw),
+        Transfer(Transfer),
+    }
+
+    impl Transaction for Operation {
+        fn apply(&self, accounts: &mut Storage) -> Result<(), TxError> {
+            match self {
+                Operation::Deposit(tx) => tx.apply(accounts),
+                Operation::Withdraw(tx) => tx.apply(accounts),
+                Operation::Transfer(tx) => tx.apply(accounts),
+            }
+        }
+
+        fn operations(&self) -> Vec<Operation> {
+            vec![self.clone()]
+        }
+
+        fn reverse(&self) -> Box<dyn Transaction> {
+            Box::new(self.reversed())
+        }
+    }
+
+    impl Operation {
+        /// Операция, компенсирующая эту
+        pub fn reversed(&self) -> Operation {
+            match self {
+                Operation::Deposit(tx) => Operation::Withdraw(Withdraw { account: tx.account.clone(), amount: tx.amount }),
+                Operation::Withdraw(tx) => Operation::Deposit(Deposit { account: tx.account.clone(), amount: tx.amount }),
+                Operation::Transfer(tx) => Operation::Transfer(Transfer {
+                    from: tx.to.clone(),
+                    to: tx.from.clone(),
+                    amount: tx.amount,
+                }),
+            }
+        }
+    }
+
+    impl Display for Operation {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Operation::Deposit(tx) => write!(f, "deposit {} {}", tx.account, tx.amount),
+                Operation::Withdraw(tx) => write!(f, "withdraw {} {}", tx.account, tx.amount),
+                Operation::Transfer(tx) => write!(f, "transfer {} {} {}", tx.from, tx.to, tx.amount),
+            }
+        }
+    }
+
+    impl FromStr for Operation {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let args: Vec<&str> = s.split_whitespace().collect();
+            let amount = |arg: &str| {
+                arg.parse::<Balance>().map_err(|e| e.to_string())
+            };
+
+            match args.as_slice() {
+                ["deposit", account, value] => Ok(Operation::Deposit(Deposit {
+                    account: account.to_string(),
+                    amount: amount(value)?,
+                })),
+                ["withdraw", account, value] => Ok(Operation::Withdraw(Withdraw {
+                    account: account.to_string(),
+                    amount: amount(value)?,
+                })),
+                ["transfer", from, to, value] => Ok(Operation::Transfer(Transfer {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    amount: amount(value)?,
+                })),
+                _ => Err(format!("неизвестная операция: {}", s.trim())),
+            }
+        }
     }
 
     #[macro_export]